@@ -24,7 +24,8 @@ fn main() {
     }
     let file = File::open(&args[1]).unwrap();
     let file = BufReader::new(file);
-    let model = inochi2d::Model::parse(file).unwrap();
+    // Only the puppet is needed, don’t read the textures.
+    let model = inochi2d::LazyModel::open(file).unwrap();
     let puppet = model.puppet;
     let root = &puppet.nodes;
     println!("{root:?}");
@@ -1,4 +1,4 @@
-import init, { parse, setup_context, setup, decode_textures, has_astc, has_bptc } from './inochi2d.js';
+import init, { parse, setup_context, setup, decode_textures_async, has_astc, has_bptc } from './inochi2d.js';
 
 let gl;
 let renderer;
@@ -40,7 +40,8 @@ async function run() {
 async function change_model() {
   const array = await fetch_array(get_name());
   data = parse(array);
-  const textures = decode_textures(data);
+  // Spend at most 8 ms per frame decoding, so that the page stays responsive.
+  const textures = await decode_textures_async(data, gl, 8);
   setup(gl, data, textures);
 }
 
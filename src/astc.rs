@@ -0,0 +1,1347 @@
+// Copyright (c) 2023 Emmanuel Gil Peyrot <linkmauve@linkmauve.fr>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! ASTC LDR textures, stored in the .astc container used by ARM’s tools.
+
+use std::io;
+
+const MAGIC: [u8; 4] = [0x13, 0xab, 0xa1, 0x5c];
+const HEADER_SIZE: usize = 16;
+
+/// What the specification mandates for blocks we can’t decode.
+const ERROR_COLOR: [u8; 4] = [255, 0, 255, 255];
+
+/// The 2D block sizes that GPUs support, in the order of their GL internal formats.
+pub const BLOCK_SIZES: [(u8, u8); 14] = [
+    (4, 4),
+    (5, 4),
+    (5, 5),
+    (6, 5),
+    (6, 6),
+    (8, 5),
+    (8, 6),
+    (8, 8),
+    (10, 5),
+    (10, 6),
+    (10, 8),
+    (10, 10),
+    (12, 10),
+    (12, 12),
+];
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_le_u24(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], 0])
+}
+
+/// As large as any GPU accepts, a header claiming more is more likely crafted than real.
+const MAX_DIMENSION: u32 = 16384;
+
+/// Returns the size of the blocks and of the decoded RGBA pixels, in bytes.
+fn sizes(header: &Header) -> io::Result<(usize, usize)> {
+    if header.width > MAX_DIMENSION || header.height > MAX_DIMENSION {
+        return Err(invalid("ASTC texture too large"));
+    }
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let blocks = width
+        .div_ceil(header.block_width as usize)
+        .checked_mul(height.div_ceil(header.block_height as usize))
+        .and_then(|count| count.checked_mul(16));
+    let pixels = width
+        .checked_mul(height)
+        .and_then(|count| count.checked_mul(4));
+    blocks
+        .zip(pixels)
+        .ok_or_else(|| invalid("ASTC texture too large"))
+}
+
+pub struct Header {
+    pub block_width: u8,
+    pub block_height: u8,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Returns the header and the blocks of the image.
+pub fn parse(astc: &[u8]) -> io::Result<(Header, &[u8])> {
+    if astc.len() < HEADER_SIZE || astc[..4] != MAGIC {
+        return Err(invalid("Not an ASTC file"));
+    }
+    let block_width = astc[4];
+    let block_height = astc[5];
+    if !BLOCK_SIZES.contains(&(block_width, block_height)) || astc[6] != 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Unsupported ASTC block size {block_width}×{block_height}×{}",
+                astc[6]
+            ),
+        ));
+    }
+    let width = read_le_u24(&astc[7..]);
+    let height = read_le_u24(&astc[10..]);
+    if read_le_u24(&astc[13..]) != 1 {
+        return Err(invalid("3D ASTC textures are unsupported"));
+    }
+    let header = Header {
+        block_width,
+        block_height,
+        width,
+        height,
+    };
+    let (size, _) = sizes(&header)?;
+    let blocks = astc[HEADER_SIZE..]
+        .get(..size)
+        .ok_or_else(|| invalid("Truncated ASTC data"))?;
+    Ok((header, blocks))
+}
+
+#[derive(Clone, Copy)]
+enum Encoding {
+    Bits(u32),
+    Trits(u32),
+    Quints(u32),
+}
+
+/// Every ISE range, in the order used by the quantisation level of weights and endpoints.
+const RANGES: [(u32, Encoding); 21] = [
+    (2, Encoding::Bits(1)),
+    (3, Encoding::Trits(0)),
+    (4, Encoding::Bits(2)),
+    (5, Encoding::Quints(0)),
+    (6, Encoding::Trits(1)),
+    (8, Encoding::Bits(3)),
+    (10, Encoding::Quints(1)),
+    (12, Encoding::Trits(2)),
+    (16, Encoding::Bits(4)),
+    (20, Encoding::Quints(2)),
+    (24, Encoding::Trits(3)),
+    (32, Encoding::Bits(5)),
+    (40, Encoding::Quints(3)),
+    (48, Encoding::Trits(4)),
+    (64, Encoding::Bits(6)),
+    (80, Encoding::Quints(4)),
+    (96, Encoding::Trits(5)),
+    (128, Encoding::Bits(7)),
+    (160, Encoding::Quints(5)),
+    (192, Encoding::Trits(6)),
+    (256, Encoding::Bits(8)),
+];
+
+pub(crate) fn ise_size(count: u32, range: usize) -> u32 {
+    match RANGES[range].1 {
+        Encoding::Bits(bits) => count * bits,
+        Encoding::Trits(bits) => count * bits + (8 * count).div_ceil(5),
+        Encoding::Quints(bits) => count * bits + (7 * count).div_ceil(3),
+    }
+}
+
+fn bits(value: u128, start: u32, count: u32) -> u32 {
+    if count == 0 || start >= 128 {
+        return 0;
+    }
+    ((value >> start) & ((1 << count) - 1)) as u32
+}
+
+fn decode_trits(t: u32) -> [u32; 5] {
+    let bit = |n: u32| (t >> n) & 1;
+    let (c, t3, t4);
+    if (t >> 2) & 7 == 7 {
+        c = ((t >> 5) & 7) << 2 | (t & 3);
+        t4 = 2;
+        t3 = 2;
+    } else {
+        c = t & 0x1f;
+        if (t >> 5) & 3 == 3 {
+            t4 = 2;
+            t3 = bit(7);
+        } else {
+            t4 = bit(7);
+            t3 = (t >> 5) & 3;
+        }
+    }
+    let cbit = |n: u32| (c >> n) & 1;
+    let (t0, t1, t2);
+    if c & 3 == 3 {
+        t2 = 2;
+        t1 = cbit(4);
+        t0 = (cbit(3) << 1) | (cbit(2) & !cbit(3) & 1);
+    } else if (c >> 2) & 3 == 3 {
+        t2 = 2;
+        t1 = 2;
+        t0 = c & 3;
+    } else {
+        t2 = cbit(4);
+        t1 = (c >> 2) & 3;
+        t0 = (cbit(1) << 1) | (cbit(0) & !cbit(1) & 1);
+    }
+    [t0, t1, t2, t3, t4]
+}
+
+fn decode_quints(q: u32) -> [u32; 3] {
+    let bit = |n: u32| (q >> n) & 1;
+    if (q >> 1) & 3 == 3 && (q >> 5) & 3 == 0 {
+        let q2 = (bit(0) << 2) | ((bit(4) & !bit(0) & 1) << 1) | (bit(3) & !bit(0) & 1);
+        return [4, 4, q2];
+    }
+    let (c, q2);
+    if (q >> 1) & 3 == 3 {
+        q2 = 4;
+        c = (((q >> 3) & 3) << 3) | ((!(q >> 5) & 3) << 1) | bit(0);
+    } else {
+        q2 = (q >> 5) & 3;
+        c = q & 0x1f;
+    }
+    if c & 7 == 5 {
+        [(c >> 3) & 3, 4, q2]
+    } else {
+        [c & 7, (c >> 3) & 3, q2]
+    }
+}
+
+/// Decodes `out.len()` values of the given range, returned as (trit or quint, low bits).
+fn decode_ise(data: u128, start: u32, range: usize, out: &mut [(u32, u32)]) {
+    let count = out.len();
+    // Bits past the end of the sequence must be read as zeroes.
+    let end = start + ise_size(count as u32, range);
+    let data = if end < 128 {
+        data & ((1 << end) - 1)
+    } else {
+        data
+    };
+    match RANGES[range].1 {
+        Encoding::Bits(n) => {
+            for (i, value) in out.iter_mut().enumerate() {
+                *value = (0, bits(data, start + i as u32 * n, n));
+            }
+        }
+        Encoding::Trits(n) => {
+            let mut pos = start;
+            for block in 0..count.div_ceil(5) {
+                let mut m = [0u32; 5];
+                let mut t = 0;
+                // The trit bits are spread between the low bits of each value.
+                for (i, (offset, width)) in [(0, 2), (2, 2), (4, 1), (5, 2), (7, 1)]
+                    .into_iter()
+                    .enumerate()
+                {
+                    m[i] = bits(data, pos, n);
+                    pos += n;
+                    t |= bits(data, pos, width) << offset;
+                    pos += width;
+                }
+                let trits = decode_trits(t);
+                for i in 0..5 {
+                    if let Some(value) = out.get_mut(block * 5 + i) {
+                        *value = (trits[i], m[i]);
+                    }
+                }
+            }
+        }
+        Encoding::Quints(n) => {
+            let mut pos = start;
+            for block in 0..count.div_ceil(3) {
+                let mut m = [0u32; 3];
+                let mut q = 0;
+                for (i, (offset, width)) in [(0, 3), (3, 2), (5, 2)].into_iter().enumerate() {
+                    m[i] = bits(data, pos, n);
+                    pos += n;
+                    q |= bits(data, pos, width) << offset;
+                    pos += width;
+                }
+                let quints = decode_quints(q);
+                for i in 0..3 {
+                    if let Some(value) = out.get_mut(block * 3 + i) {
+                        *value = (quints[i], m[i]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn replicate(value: u32, from: u32, to: u32) -> u32 {
+    let mut result = 0;
+    let mut shift = to as i32 - from as i32;
+    while shift > -(from as i32) {
+        if shift >= 0 {
+            result |= value << shift;
+        } else {
+            result |= value >> -shift;
+        }
+        shift -= from as i32;
+    }
+    result & ((1 << to) - 1)
+}
+
+fn unquantize_weight(range: usize, (d, m): (u32, u32)) -> u32 {
+    let (c, b) = match RANGES[range].1 {
+        Encoding::Bits(n) => {
+            let value = replicate(m, n, 6);
+            return if value > 32 { value + 1 } else { value };
+        }
+        Encoding::Trits(0) => return [0, 32, 64][d as usize],
+        Encoding::Quints(0) => return [0, 16, 32, 48, 64][d as usize],
+        Encoding::Trits(1) => (50, 0),
+        Encoding::Trits(2) => (23, ((m >> 1) & 1) * 0x45),
+        Encoding::Trits(3) => (11, (((m >> 1) & 1) << 5) | (((m >> 2) & 1) * 0x41)),
+        Encoding::Quints(1) => (28, 0),
+        Encoding::Quints(2) => (13, ((m >> 1) & 1) * 0x42),
+        _ => unreachable!(),
+    };
+    let a = if m & 1 != 0 { 0x7f } else { 0 };
+    let t = (d * c + b) ^ a;
+    let t = (a & 0x20) | (t >> 2);
+    if t > 32 {
+        t + 1
+    } else {
+        t
+    }
+}
+
+fn unquantize_color(range: usize, (d, m): (u32, u32)) -> u32 {
+    let (c, b) = match RANGES[range].1 {
+        Encoding::Bits(n) => return replicate(m, n, 8),
+        Encoding::Trits(0) => return [0, 128, 255][d as usize],
+        Encoding::Quints(0) => return [0, 64, 128, 191, 255][d as usize],
+        Encoding::Trits(n) => {
+            let bit = |i: u32| (m >> i) & 1;
+            let b = match n {
+                1 => 0,
+                2 => bit(1) * 0b100010110,
+                3 => {
+                    let cb = (m >> 1) & 3;
+                    (cb << 7) | (cb << 2) | cb
+                }
+                4 => {
+                    let dcb = (m >> 1) & 7;
+                    (dcb << 6) | dcb
+                }
+                5 => {
+                    let edcb = (m >> 1) & 15;
+                    (edcb << 5) | (edcb >> 2)
+                }
+                _ => {
+                    let fedcb = (m >> 1) & 31;
+                    (fedcb << 4) | (fedcb >> 4)
+                }
+            };
+            ([204, 93, 44, 22, 11, 5][n as usize - 1], b)
+        }
+        Encoding::Quints(n) => {
+            let bit = |i: u32| (m >> i) & 1;
+            let b = match n {
+                1 => 0,
+                2 => bit(1) * 0b100001100,
+                3 => {
+                    let cb = (m >> 1) & 3;
+                    (cb << 7) | (cb << 1) | (cb >> 1)
+                }
+                4 => {
+                    let dcb = (m >> 1) & 7;
+                    (dcb << 6) | (dcb >> 1)
+                }
+                _ => {
+                    let edcb = (m >> 1) & 15;
+                    (edcb << 5) | (edcb >> 3)
+                }
+            };
+            ([113, 54, 26, 13, 6][n as usize - 1], b)
+        }
+    };
+    let a = if m & 1 != 0 { 0x1ff } else { 0 };
+    let t = (d * c + b) ^ a;
+    (a & 0x80) | (t >> 2)
+}
+
+struct BlockMode {
+    width: usize,
+    height: usize,
+    range: usize,
+    dual_plane: bool,
+}
+
+fn decode_block_mode(mode: u32) -> Option<BlockMode> {
+    let mut r = (mode >> 4) & 1;
+    let mut high = (mode >> 9) & 1;
+    let mut dual_plane = (mode >> 10) & 1;
+    let a = (mode >> 5) & 3;
+    let (width, height);
+    if mode & 3 != 0 {
+        r |= (mode & 3) << 1;
+        let b = (mode >> 7) & 3;
+        match (mode >> 2) & 3 {
+            0 => (width, height) = (b + 4, a + 2),
+            1 => (width, height) = (b + 8, a + 2),
+            2 => (width, height) = (a + 2, b + 8),
+            _ => {
+                let b = b & 1;
+                if mode & 0x100 != 0 {
+                    (width, height) = (b + 2, a + 2);
+                } else {
+                    (width, height) = (a + 2, b + 6);
+                }
+            }
+        }
+    } else {
+        r |= ((mode >> 2) & 3) << 1;
+        if (mode >> 2) & 3 == 0 {
+            return None;
+        }
+        let b = (mode >> 9) & 3;
+        match (mode >> 7) & 3 {
+            0 => (width, height) = (12, a + 2),
+            1 => (width, height) = (a + 2, 12),
+            2 => {
+                (width, height) = (a + 6, b + 6);
+                dual_plane = 0;
+                high = 0;
+            }
+            _ => match a {
+                0 => (width, height) = (6, 10),
+                1 => (width, height) = (10, 6),
+                _ => return None,
+            },
+        }
+    }
+    Some(BlockMode {
+        width: width as usize,
+        height: height as usize,
+        range: (r - 2 + 6 * high) as usize,
+        dual_plane: dual_plane != 0,
+    })
+}
+
+fn hash52(mut p: u32) -> u32 {
+    p ^= p >> 15;
+    p = p.wrapping_sub(p << 17);
+    p = p.wrapping_add(p << 7);
+    p = p.wrapping_add(p << 4);
+    p ^= p >> 5;
+    p = p.wrapping_add(p << 16);
+    p ^= p >> 7;
+    p ^= p >> 3;
+    p ^= p << 6;
+    p ^= p >> 17;
+    p
+}
+
+pub(crate) fn select_partition(
+    seed: u32,
+    mut x: u32,
+    mut y: u32,
+    partition_count: u32,
+    small_block: bool,
+) -> usize {
+    if small_block {
+        x <<= 1;
+        y <<= 1;
+    }
+    let seed = seed + (partition_count - 1) * 1024;
+    let rnum = hash52(seed);
+    let mut seeds = [0u32; 8];
+    for (i, s) in seeds.iter_mut().enumerate() {
+        let s4 = (rnum >> (i * 4)) & 0xf;
+        *s = s4 * s4;
+    }
+    let (sh1, sh2) = if seed & 1 != 0 {
+        (
+            if seed & 2 != 0 { 4 } else { 5 },
+            if partition_count == 3 { 6 } else { 5 },
+        )
+    } else {
+        (
+            if partition_count == 3 { 6 } else { 5 },
+            if seed & 2 != 0 { 4 } else { 5 },
+        )
+    };
+    for (i, s) in seeds.iter_mut().enumerate() {
+        *s >>= if i % 2 == 0 { sh1 } else { sh2 };
+    }
+    // The z coordinate is always zero for 2D textures, so seeds 9 to 12 are unused.
+    let a = (seeds[0] * x + seeds[1] * y + (rnum >> 14)) & 0x3f;
+    let b = (seeds[2] * x + seeds[3] * y + (rnum >> 10)) & 0x3f;
+    let mut c = (seeds[4] * x + seeds[5] * y + (rnum >> 6)) & 0x3f;
+    let mut d = (seeds[6] * x + seeds[7] * y + (rnum >> 2)) & 0x3f;
+    if partition_count < 4 {
+        d = 0;
+    }
+    if partition_count < 3 {
+        c = 0;
+    }
+    if a >= b && a >= c && a >= d {
+        0
+    } else if b >= c && b >= d {
+        1
+    } else if c >= d {
+        2
+    } else {
+        3
+    }
+}
+
+fn bit_transfer_signed(a: i32, b: i32) -> (i32, i32) {
+    let b = (b >> 1) | (a & 0x80);
+    let mut a = (a >> 1) & 0x3f;
+    if a & 0x20 != 0 {
+        a -= 0x40;
+    }
+    (a, b)
+}
+
+fn blue_contract(r: i32, g: i32, b: i32, a: i32) -> [i32; 4] {
+    [(r + b) >> 1, (g + b) >> 1, b, a]
+}
+
+fn clamp(color: [i32; 4]) -> [u8; 4] {
+    color.map(|c| c.clamp(0, 255) as u8)
+}
+
+/// Returns both endpoints for one color endpoint mode, or None for HDR modes.
+fn decode_endpoints(cem: u32, v: &[i32]) -> Option<([u8; 4], [u8; 4])> {
+    let (e0, e1) = match cem {
+        0 => ([v[0], v[0], v[0], 255], [v[1], v[1], v[1], 255]),
+        1 => {
+            let l0 = (v[0] >> 2) | (v[1] & 0xc0);
+            let l1 = (l0 + (v[1] & 0x3f)).min(255);
+            ([l0, l0, l0, 255], [l1, l1, l1, 255])
+        }
+        4 => ([v[0], v[0], v[0], v[2]], [v[1], v[1], v[1], v[3]]),
+        5 => {
+            let (b0, a0) = bit_transfer_signed(v[1], v[0]);
+            let (b2, a2) = bit_transfer_signed(v[3], v[2]);
+            let l = a0 + b0;
+            ([a0, a0, a0, a2], [l, l, l, a2 + b2])
+        }
+        6 => (
+            [
+                (v[0] * v[3]) >> 8,
+                (v[1] * v[3]) >> 8,
+                (v[2] * v[3]) >> 8,
+                255,
+            ],
+            [v[0], v[1], v[2], 255],
+        ),
+        8 | 12 => {
+            let (a0, a1) = if cem == 12 { (v[6], v[7]) } else { (255, 255) };
+            let s0 = v[0] + v[2] + v[4];
+            let s1 = v[1] + v[3] + v[5];
+            if s1 >= s0 {
+                ([v[0], v[2], v[4], a0], [v[1], v[3], v[5], a1])
+            } else {
+                (
+                    blue_contract(v[1], v[3], v[5], a1),
+                    blue_contract(v[0], v[2], v[4], a0),
+                )
+            }
+        }
+        9 | 13 => {
+            let (b1, a0) = bit_transfer_signed(v[1], v[0]);
+            let (b3, a2) = bit_transfer_signed(v[3], v[2]);
+            let (b5, a4) = bit_transfer_signed(v[5], v[4]);
+            let (b7, a6) = if cem == 13 {
+                bit_transfer_signed(v[7], v[6])
+            } else {
+                (0, 255)
+            };
+            if b1 + b3 + b5 >= 0 {
+                ([a0, a2, a4, a6], [a0 + b1, a2 + b3, a4 + b5, a6 + b7])
+            } else {
+                (
+                    blue_contract(a0 + b1, a2 + b3, a4 + b5, a6 + b7),
+                    blue_contract(a0, a2, a4, a6),
+                )
+            }
+        }
+        10 => (
+            [
+                (v[0] * v[3]) >> 8,
+                (v[1] * v[3]) >> 8,
+                (v[2] * v[3]) >> 8,
+                v[4],
+            ],
+            [v[0], v[1], v[2], v[5]],
+        ),
+        _ => return None,
+    };
+    Some((clamp(e0), clamp(e1)))
+}
+
+fn interpolate(e0: u8, e1: u8, weight: u32) -> u8 {
+    let c0 = e0 as u32 * 257;
+    let c1 = e1 as u32 * 257;
+    let c = (c0 * (64 - weight) + c1 * weight + 32) / 64;
+    (c >> 8) as u8
+}
+
+/// Decodes a single block into `out`, row by row, using the block dimensions.
+pub(crate) fn decode_block(
+    block: &[u8; 16],
+    block_width: usize,
+    block_height: usize,
+    out: &mut [[u8; 4]],
+) {
+    if decode_block_inner(block, block_width, block_height, out).is_none() {
+        out.fill(ERROR_COLOR);
+    }
+}
+
+fn decode_block_inner(
+    block: &[u8; 16],
+    block_width: usize,
+    block_height: usize,
+    out: &mut [[u8; 4]],
+) -> Option<()> {
+    let data = u128::from_le_bytes(*block);
+    let mode = bits(data, 0, 11);
+
+    // Void-extent blocks are a single constant color.
+    if mode & 0x1ff == 0x1fc {
+        if mode & 0x200 != 0 {
+            // HDR.
+            return None;
+        }
+        let color = [0, 1, 2, 3].map(|i| (bits(data, 64 + i * 16, 16) >> 8) as u8);
+        out.fill(color);
+        return Some(());
+    }
+
+    let mode = decode_block_mode(mode)?;
+    if mode.width > block_width || mode.height > block_height {
+        return None;
+    }
+    let planes = if mode.dual_plane { 2 } else { 1 };
+    let num_weights = mode.width * mode.height * planes;
+    if num_weights > 64 {
+        return None;
+    }
+    let weight_bits = ise_size(num_weights as u32, mode.range);
+    if !(24..=96).contains(&weight_bits) {
+        return None;
+    }
+
+    let partition_count = bits(data, 11, 2) + 1;
+    if partition_count == 4 && mode.dual_plane {
+        return None;
+    }
+
+    let mut cems = [0u32; 4];
+    let mut extra_cem_bits = 0;
+    let color_start;
+    let partition_seed;
+    if partition_count == 1 {
+        cems[0] = bits(data, 13, 4);
+        color_start = 17;
+        partition_seed = 0;
+    } else {
+        partition_seed = bits(data, 13, 10);
+        color_start = 29;
+        let selector = bits(data, 23, 2);
+        if selector == 0 {
+            let cem = bits(data, 25, 4);
+            cems.fill(cem);
+        } else {
+            extra_cem_bits = 3 * partition_count - 4;
+            let extra = bits(data, 128 - weight_bits - extra_cem_bits, extra_cem_bits);
+            let value = bits(data, 25, 4) | (extra << 4);
+            let base_class = selector - 1;
+            for (i, cem) in cems.iter_mut().enumerate().take(partition_count as usize) {
+                let c = (value >> i) & 1;
+                let m = (value >> (partition_count as usize + 2 * i)) & 3;
+                *cem = ((base_class + c) << 2) | m;
+            }
+        }
+    }
+    let ccs_bits = if mode.dual_plane { 2 } else { 0 };
+    let ccs = bits(
+        data,
+        128 - weight_bits - extra_cem_bits - ccs_bits,
+        ccs_bits,
+    );
+
+    let num_values: u32 = cems
+        .iter()
+        .take(partition_count as usize)
+        .map(|cem| (cem / 4 + 1) * 2)
+        .sum();
+    if num_values > 18 {
+        return None;
+    }
+    let color_end = 128 - weight_bits - extra_cem_bits - ccs_bits;
+    let available = color_end.checked_sub(color_start)?;
+    let color_range = (0..RANGES.len())
+        .rev()
+        .find(|&range| ise_size(num_values, range) <= available)?;
+    // Ranges below 6 are not allowed for color endpoints.
+    if color_range < 4 {
+        return None;
+    }
+
+    let mut values = [(0, 0); 18];
+    decode_ise(
+        data,
+        color_start,
+        color_range,
+        &mut values[..num_values as usize],
+    );
+    let values = values.map(|value| unquantize_color(color_range, value) as i32);
+
+    let mut endpoints = [([0u8; 4], [0u8; 4]); 4];
+    let mut offset = 0;
+    for (cem, endpoint) in cems
+        .iter()
+        .zip(endpoints.iter_mut())
+        .take(partition_count as usize)
+    {
+        let count = ((cem / 4 + 1) * 2) as usize;
+        *endpoint = decode_endpoints(*cem, &values[offset..offset + count])?;
+        offset += count;
+    }
+
+    // Weights are stored bit-reversed from the top of the block.
+    let mut weights = [(0, 0); 64];
+    decode_ise(
+        data.reverse_bits(),
+        0,
+        mode.range,
+        &mut weights[..num_weights],
+    );
+    let weights = weights.map(|weight| unquantize_weight(mode.range, weight));
+
+    let small_block = block_width * block_height < 31;
+    let ds = (1024 + block_width as u32 / 2) / (block_width as u32 - 1).max(1);
+    let dt = (1024 + block_height as u32 / 2) / (block_height as u32 - 1).max(1);
+    let grid_width = mode.width as u32;
+    let grid_height = mode.height as u32;
+    let weight = |plane: usize, x: usize, y: usize| -> u32 {
+        let cs = ds * x as u32;
+        let ct = dt * y as u32;
+        let gs = (cs * (grid_width - 1) + 32) >> 6;
+        let gt = (ct * (grid_height - 1) + 32) >> 6;
+        let (js, fs) = ((gs >> 4) as usize, gs & 0xf);
+        let (jt, ft) = ((gt >> 4) as usize, gt & 0xf);
+        let get = |s: usize, t: usize| -> u32 {
+            if s < mode.width && t < mode.height {
+                weights[(t * mode.width + s) * planes + plane]
+            } else {
+                0
+            }
+        };
+        let w11 = (fs * ft + 8) >> 4;
+        let w10 = ft - w11;
+        let w01 = fs - w11;
+        let w00 = 16 + w11 - fs - ft;
+        (get(js, jt) * w00
+            + get(js + 1, jt) * w01
+            + get(js, jt + 1) * w10
+            + get(js + 1, jt + 1) * w11
+            + 8)
+            >> 4
+    };
+
+    for y in 0..block_height {
+        for x in 0..block_width {
+            let partition = if partition_count == 1 {
+                0
+            } else {
+                select_partition(
+                    partition_seed,
+                    x as u32,
+                    y as u32,
+                    partition_count,
+                    small_block,
+                )
+            };
+            let (e0, e1) = endpoints[partition];
+            let w0 = weight(0, x, y);
+            let w1 = if mode.dual_plane { weight(1, x, y) } else { w0 };
+            let pixel = &mut out[y * block_width + x];
+            for channel in 0..4 {
+                let w = if mode.dual_plane && channel as u32 == ccs {
+                    w1
+                } else {
+                    w0
+                };
+                pixel[channel] = interpolate(e0[channel], e1[channel], w);
+            }
+        }
+    }
+    Some(())
+}
+
+/// Decodes raw ASTC blocks into RGBA pixels.
+pub fn decode_blocks(header: &Header, blocks: &[u8]) -> io::Result<Vec<u8>> {
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let block_width = header.block_width as usize;
+    let block_height = header.block_height as usize;
+    let (blocks_size, pixels_size) = sizes(header)?;
+    if blocks.len() < blocks_size {
+        return Err(invalid("Truncated ASTC data"));
+    }
+    let blocks_x = width.div_ceil(block_width);
+    let blocks_y = height.div_ceil(block_height);
+    let mut data = vec![0u8; pixels_size];
+    let mut pixels = vec![[0u8; 4]; block_width * block_height];
+    for (i, block) in blocks
+        .chunks_exact(16)
+        .take(blocks_x * blocks_y)
+        .enumerate()
+    {
+        let bx = (i % blocks_x) * block_width;
+        let by = (i / blocks_x) * block_height;
+        decode_block(
+            block.try_into().unwrap(),
+            block_width,
+            block_height,
+            &mut pixels,
+        );
+        for y in 0..block_height.min(height - by) {
+            let count = block_width.min(width - bx);
+            let offset = ((by + y) * width + bx) * 4;
+            for (dst, src) in data[offset..offset + count * 4]
+                .chunks_exact_mut(4)
+                .zip(&pixels[y * block_width..])
+            {
+                dst.copy_from_slice(src);
+            }
+        }
+    }
+    Ok(data)
+}
+
+/// Decodes an .astc file into RGBA pixels.
+pub fn decode(astc: &[u8]) -> io::Result<(u32, u32, Vec<u8>)> {
+    let (header, blocks) = parse(astc)?;
+    let data = decode_blocks(&header, blocks)?;
+    Ok((header.width, header.height, data))
+}
+
+#[cfg(feature = "encoding")]
+pub use encoder::encode;
+
+#[cfg(feature = "encoding")]
+mod encoder {
+    use super::*;
+    use crate::blocks;
+    use crate::Quality;
+
+    struct BitWriter {
+        bits: u128,
+        pos: u32,
+    }
+
+    impl BitWriter {
+        fn new() -> BitWriter {
+            BitWriter { bits: 0, pos: 0 }
+        }
+
+        fn write(&mut self, value: u32, count: u32) {
+            if count > 0 {
+                self.bits |= ((value & ((1 << count) - 1)) as u128) << self.pos;
+            }
+            self.pos += count;
+        }
+    }
+
+    /// One way to lay out a single-partition, single-plane block.
+    struct Config {
+        mode: u32,
+        width: usize,
+        height: usize,
+        range: usize,
+        color_range: usize,
+        /// Rough estimate of the error this layout will produce, lower is better.
+        score: f32,
+    }
+
+    struct Encoder {
+        block_width: usize,
+        block_height: usize,
+        /// Smallest trit block encoding each tuple, so that trailing zeroes encode to zero bits.
+        trits: [u32; 243],
+        quints: [u32; 125],
+        /// Nearest quantised value for each 8-bit color, per range.
+        colors: Vec<[(u32, u32); 256]>,
+        /// Nearest quantised value for each weight from 0 to 64, per range.
+        weights: Vec<[(u32, u32); 65]>,
+        /// Candidate layouts for opaque and for translucent blocks, best first.
+        opaque: Vec<Config>,
+        translucent: Vec<Config>,
+        candidates: usize,
+    }
+
+    /// Every (trit or quint, low bits) pair of a range.
+    fn range_values(range: usize) -> impl Iterator<Item = (u32, u32)> {
+        let (count, bits) = match RANGES[range].1 {
+            Encoding::Bits(n) => (1, n),
+            Encoding::Trits(n) => (3, n),
+            Encoding::Quints(n) => (5, n),
+        };
+        (0..count).flat_map(move |d| (0..1 << bits).map(move |m| (d, m)))
+    }
+
+    fn nearest<const N: usize>(
+        range: usize,
+        unquantize: fn(usize, (u32, u32)) -> u32,
+    ) -> [(u32, u32); N] {
+        let values: Vec<_> = range_values(range)
+            .map(|value| (unquantize(range, value), value))
+            .collect();
+        core::array::from_fn(|target| {
+            values
+                .iter()
+                .min_by_key(|(unquantized, _)| unquantized.abs_diff(target as u32))
+                .unwrap()
+                .1
+        })
+    }
+
+    impl Encoder {
+        fn new(block_width: usize, block_height: usize, quality: Quality) -> Encoder {
+            let mut trits = [u32::MAX; 243];
+            for t in (0..256).rev() {
+                let [t0, t1, t2, t3, t4] = decode_trits(t);
+                trits[(t0 + 3 * t1 + 9 * t2 + 27 * t3 + 81 * t4) as usize] = t;
+            }
+            let mut quints = [u32::MAX; 125];
+            for q in (0..128).rev() {
+                let [q0, q1, q2] = decode_quints(q);
+                quints[(q0 + 5 * q1 + 25 * q2) as usize] = q;
+            }
+            let colors = (0..RANGES.len())
+                .map(|range| nearest(range, unquantize_color))
+                .collect();
+            let weights = (0..12)
+                .map(|range| nearest(range, unquantize_weight))
+                .collect();
+
+            let mut modes = Vec::new();
+            for mode in 0..2048 {
+                // Skip void-extent blocks.
+                if mode & 0x1ff == 0x1fc {
+                    continue;
+                }
+                if let Some(block_mode) = decode_block_mode(mode) {
+                    if !block_mode.dual_plane {
+                        modes.push((mode, block_mode));
+                    }
+                }
+            }
+
+            let configs = |num_values: u32| {
+                let mut configs: Vec<Config> = Vec::new();
+                for (mode, block_mode) in &modes {
+                    let BlockMode {
+                        width,
+                        height,
+                        range,
+                        ..
+                    } = *block_mode;
+                    if width > block_width || height > block_height || width * height > 64 {
+                        continue;
+                    }
+                    if configs
+                        .iter()
+                        .any(|c| (c.width, c.height, c.range) == (width, height, range))
+                    {
+                        continue;
+                    }
+                    let weight_bits = ise_size((width * height) as u32, range);
+                    if !(24..=96).contains(&weight_bits) {
+                        continue;
+                    }
+                    let available = 128 - 17 - weight_bits;
+                    let Some(color_range) = (0..RANGES.len())
+                        .rev()
+                        .find(|&range| ise_size(num_values, range) <= available)
+                    else {
+                        continue;
+                    };
+                    if color_range < 4 {
+                        continue;
+                    }
+                    // Assume endpoints about half the range apart, and uniform quantisation
+                    // errors, plus a penalty for every texel without its own weight.
+                    let weight_step = 128. / (RANGES[range].0 - 1) as f32;
+                    let color_step = 255. / (RANGES[color_range].0 - 1) as f32;
+                    let coverage = (width * height) as f32 / (block_width * block_height) as f32;
+                    let score = (weight_step * weight_step + color_step * color_step) / 12.
+                        + (1. - coverage) * 256.;
+                    configs.push(Config {
+                        mode: *mode,
+                        width,
+                        height,
+                        range,
+                        color_range,
+                        score,
+                    });
+                }
+                configs.sort_by(|a, b| a.score.total_cmp(&b.score));
+                configs
+            };
+
+            Encoder {
+                block_width,
+                block_height,
+                trits,
+                quints,
+                colors,
+                weights,
+                opaque: configs(6),
+                translucent: configs(8),
+                candidates: match quality {
+                    Quality::Fast => 1,
+                    Quality::Normal => 4,
+                    Quality::Best => 16,
+                },
+            }
+        }
+
+        fn write_ise(&self, writer: &mut BitWriter, range: usize, values: &[(u32, u32)]) {
+            match RANGES[range].1 {
+                Encoding::Bits(n) => {
+                    for &(_, m) in values {
+                        writer.write(m, n);
+                    }
+                }
+                Encoding::Trits(n) => {
+                    for group in values.chunks(5) {
+                        let mut index = 0;
+                        for (i, &(d, _)) in group.iter().enumerate() {
+                            index += d * 3u32.pow(i as u32);
+                        }
+                        let t = self.trits[index as usize];
+                        for (i, (offset, width)) in [(0, 2), (2, 2), (4, 1), (5, 2), (7, 1)]
+                            .into_iter()
+                            .enumerate()
+                            .take(group.len())
+                        {
+                            writer.write(group[i].1, n);
+                            writer.write(t >> offset, width);
+                        }
+                    }
+                }
+                Encoding::Quints(n) => {
+                    for group in values.chunks(3) {
+                        let mut index = 0;
+                        for (i, &(d, _)) in group.iter().enumerate() {
+                            index += d * 5u32.pow(i as u32);
+                        }
+                        let q = self.quints[index as usize];
+                        for (i, (offset, width)) in [(0, 3), (3, 2), (5, 2)]
+                            .into_iter()
+                            .enumerate()
+                            .take(group.len())
+                        {
+                            writer.write(group[i].1, n);
+                            writer.write(q >> offset, width);
+                        }
+                    }
+                }
+            }
+        }
+
+        /// Resamples the weight of each texel to the weight grid, by inverting the bilinear
+        /// filter the decoder applies.
+        fn grid_weights(&self, config: &Config, texels: &[f32]) -> Vec<f32> {
+            if config.width == self.block_width && config.height == self.block_height {
+                return texels.to_vec();
+            }
+            let ds = (1024 + self.block_width as u32 / 2) / (self.block_width as u32 - 1).max(1);
+            let dt = (1024 + self.block_height as u32 / 2) / (self.block_height as u32 - 1).max(1);
+            let mut sums = vec![0f32; config.width * config.height];
+            let mut totals = vec![0f32; config.width * config.height];
+            for y in 0..self.block_height {
+                for x in 0..self.block_width {
+                    let gs = (ds * x as u32 * (config.width as u32 - 1) + 32) >> 6;
+                    let gt = (dt * y as u32 * (config.height as u32 - 1) + 32) >> 6;
+                    let (js, fs) = ((gs >> 4) as usize, (gs & 0xf) as f32 / 16.);
+                    let (jt, ft) = ((gt >> 4) as usize, (gt & 0xf) as f32 / 16.);
+                    for (s, t, factor) in [
+                        (js, jt, (1. - fs) * (1. - ft)),
+                        (js + 1, jt, fs * (1. - ft)),
+                        (js, jt + 1, (1. - fs) * ft),
+                        (js + 1, jt + 1, fs * ft),
+                    ] {
+                        if s < config.width && t < config.height && factor > 0. {
+                            let i = t * config.width + s;
+                            sums[i] += factor * texels[y * self.block_width + x];
+                            totals[i] += factor;
+                        }
+                    }
+                }
+            }
+            sums.iter()
+                .zip(totals)
+                .map(|(sum, total)| if total > 0. { sum / total } else { 0. })
+                .collect()
+        }
+
+        fn encode_config(
+            &self,
+            pixels: &[[u8; 4]],
+            config: &Config,
+            e0: [f32; 4],
+            e1: [f32; 4],
+            channels: usize,
+        ) -> [u8; 16] {
+            let quantize = |endpoint: [f32; 4]| {
+                endpoint.map(|v| self.colors[config.color_range][v.round() as usize])
+            };
+            let mut q0 = quantize(e0);
+            let mut q1 = quantize(e1);
+            let unquantize = |q: [(u32, u32); 4]| {
+                q.map(|value| unquantize_color(config.color_range, value) as f32)
+            };
+            let mut u0 = unquantize(q0);
+            let mut u1 = unquantize(q1);
+            // The decoder swaps the endpoints and applies blue contraction otherwise.
+            if u1[..3].iter().sum::<f32>() < u0[..3].iter().sum::<f32>() {
+                core::mem::swap(&mut q0, &mut q1);
+                core::mem::swap(&mut u0, &mut u1);
+            }
+
+            let direction: Vec<f32> = (0..channels).map(|c| u1[c] - u0[c]).collect();
+            let length: f32 = direction.iter().map(|d| d * d).sum();
+            let texels: Vec<f32> = pixels
+                .iter()
+                .map(|pixel| {
+                    if length == 0. {
+                        return 0.;
+                    }
+                    let t: f32 = (0..channels)
+                        .map(|c| (pixel[c] as f32 - u0[c]) * direction[c])
+                        .sum();
+                    (t / length * 64.).clamp(0., 64.)
+                })
+                .collect();
+            let weights: Vec<_> = self
+                .grid_weights(config, &texels)
+                .into_iter()
+                .map(|w| self.weights[config.range][w.round() as usize])
+                .collect();
+
+            let mut writer = BitWriter::new();
+            writer.write(config.mode, 11);
+            // A single partition.
+            writer.write(0, 2);
+            writer.write(if channels == 4 { 12 } else { 8 }, 4);
+            let mut values = Vec::with_capacity(8);
+            for c in 0..channels {
+                values.push(q0[c]);
+                values.push(q1[c]);
+            }
+            self.write_ise(&mut writer, config.color_range, &values);
+            let mut weight_writer = BitWriter::new();
+            self.write_ise(&mut weight_writer, config.range, &weights);
+            (writer.bits | weight_writer.bits.reverse_bits()).to_le_bytes()
+        }
+
+        fn encode_block(&self, pixels: &[[u8; 4]]) -> [u8; 16] {
+            if pixels.iter().all(|pixel| *pixel == pixels[0]) {
+                return void_extent(pixels[0]);
+            }
+            let floats: Vec<[f32; 4]> = pixels.iter().map(|pixel| pixel.map(f32::from)).collect();
+            let (mean, axis) = blocks::principal_axis(&floats);
+            let (e0, e1) = blocks::bounding_endpoints(&floats, mean, axis);
+            let opaque = pixels.iter().all(|pixel| pixel[3] == 255);
+            let (configs, channels) = if opaque {
+                (&self.opaque, 3)
+            } else {
+                (&self.translucent, 4)
+            };
+
+            let mut best = ([0u8; 16], u32::MAX);
+            let mut decoded = vec![[0u8; 4]; pixels.len()];
+            for config in configs.iter().take(self.candidates) {
+                let block = self.encode_config(pixels, config, e0, e1, channels);
+                decode_block(&block, self.block_width, self.block_height, &mut decoded);
+                let error = blocks::error(pixels, &decoded);
+                if error < best.1 {
+                    best = (block, error);
+                }
+            }
+            best.0
+        }
+    }
+
+    /// A block of a single color, with no extent information.
+    fn void_extent(color: [u8; 4]) -> [u8; 16] {
+        let mut writer = BitWriter::new();
+        // LDR void extent, with both reserved bits set.
+        writer.write(0xdfc, 12);
+        // All extent coordinates set to one mean there is no extent.
+        writer.write(u32::MAX, 26);
+        writer.write(u32::MAX, 26);
+        for channel in color {
+            writer.write(channel as u32 * 257, 16);
+        }
+        writer.bits.to_le_bytes()
+    }
+
+    /// Encodes RGBA pixels into an .astc file, using one of the 2D block sizes GPUs support.
+    pub fn encode(
+        width: u32,
+        height: u32,
+        rgba: &[u8],
+        block_width: u8,
+        block_height: u8,
+        quality: Quality,
+    ) -> io::Result<Vec<u8>> {
+        if !BLOCK_SIZES.contains(&(block_width, block_height)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unsupported ASTC block size {block_width}×{block_height}"),
+            ));
+        }
+        if width >= 1 << 24 || height >= 1 << 24 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Texture too large for ASTC",
+            ));
+        }
+        let encoder = Encoder::new(block_width as usize, block_height as usize, quality);
+        let blocks = blocks::encode_blocks(
+            width,
+            height,
+            block_width as usize,
+            block_height as usize,
+            rgba,
+            |pixels| encoder.encode_block(pixels),
+        );
+
+        let mut astc = Vec::with_capacity(HEADER_SIZE + blocks.len());
+        astc.extend_from_slice(&MAGIC);
+        astc.extend_from_slice(&[block_width, block_height, 1]);
+        astc.extend_from_slice(&width.to_le_bytes()[..3]);
+        astc.extend_from_slice(&height.to_le_bytes()[..3]);
+        astc.extend_from_slice(&1u32.to_le_bytes()[..3]);
+        astc.extend_from_slice(&blocks);
+        Ok(astc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_one(block: [u8; 16]) -> [[u8; 4]; 16] {
+        let mut pixels = [[0u8; 4]; 16];
+        decode_block(&block, 4, 4, &mut pixels);
+        pixels
+    }
+
+    #[test]
+    fn void_extent() {
+        // Constant UNORM16 color, of which only the high byte remains.
+        let pixels = decode_one([
+            0xfc, 0xfd, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0xff, 0x40, 0x80, 0x34, 0x12,
+            0xcd, 0xab,
+        ]);
+        assert_eq!(pixels, [[0xff, 0x80, 0x12, 0xab]; 16]);
+    }
+
+    #[test]
+    fn luminance_gradient() {
+        // 4×4 weights of range 0..=3, luminance endpoints 0 and 255, every row weighted
+        // 0, 1, 2 and 3.
+        let pixels = decode_one([
+            0x42, 0x00, 0x00, 0xfe, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x27, 0x27,
+            0x27, 0x27,
+        ]);
+        for (i, pixel) in pixels.iter().enumerate() {
+            let value = [0, 84, 171, 255][i % 4];
+            assert_eq!(*pixel, [value, value, value, 255], "pixel {i}");
+        }
+    }
+
+    #[test]
+    fn rgba_checkerboard() {
+        // RGBA endpoints without blue contraction, weights alternating between 0 and 3.
+        let pixels = decode_one([
+            0x42, 0x80, 0x15, 0x90, 0x29, 0x2c, 0x3d, 0xc8, 0x50, 0xf4, 0x01, 0x00, 0xcc, 0x33,
+            0xcc, 0x33,
+        ]);
+        for (i, pixel) in pixels.iter().enumerate() {
+            let expected = if (i % 4 + i / 4) % 2 == 0 {
+                [10, 20, 30, 40]
+            } else {
+                [200, 150, 100, 250]
+            };
+            assert_eq!(*pixel, expected, "pixel {i}");
+        }
+    }
+
+    #[test]
+    fn reserved_block_mode() {
+        assert_eq!(decode_one([0; 16]), [ERROR_COLOR; 16]);
+    }
+
+    #[test]
+    fn huge_dimensions() {
+        let header = Header {
+            block_width: 4,
+            block_height: 4,
+            width: 0xff_ffff,
+            height: 0xff_ffff,
+        };
+        let err = decode_blocks(&header, &[]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut astc = vec![0u8; HEADER_SIZE];
+        astc[..4].copy_from_slice(&MAGIC);
+        astc[4..7].copy_from_slice(&[4, 4, 1]);
+        astc[7..13].fill(0xff);
+        astc[13] = 1;
+        let err = decode(&astc).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "encoding")]
+    #[test]
+    fn round_trip() {
+        let (width, height) = (13, 9);
+        // A diagonal gradient, with a hard edge through the middle.
+        let rgba: Vec<u8> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                let t = x + y;
+                let edge = if x > y { 60 } else { 0 };
+                [t * 11, 255 - t * 7 - edge, t * 5 + edge, 255 - t * 3]
+            })
+            .map(|value| value as u8)
+            .collect();
+        // Larger blocks spend fewer bits per pixel, and follow the edge less closely.
+        for (block_width, block_height, max_mse) in [(4, 4, 32.), (6, 6, 128.), (8, 8, 200.)] {
+            let astc = encode(
+                width,
+                height,
+                &rgba,
+                block_width,
+                block_height,
+                crate::Quality::Normal,
+            )
+            .unwrap();
+            let (decoded_width, decoded_height, decoded) = decode(&astc).unwrap();
+            assert_eq!((decoded_width, decoded_height), (width, height));
+            let mse = rgba
+                .iter()
+                .zip(&decoded)
+                .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+                .sum::<f64>()
+                / rgba.len() as f64;
+            assert!(
+                mse < max_mse,
+                "MSE {mse} with {block_width}×{block_height} blocks"
+            );
+        }
+    }
+}
@@ -0,0 +1,888 @@
+// Copyright (c) 2023 Emmanuel Gil Peyrot <linkmauve@linkmauve.fr>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! BC7 textures, stored in a DDS container with a DX10 header.
+
+use std::io;
+
+const DDS_MAGIC: &[u8] = b"DDS ";
+const DX10: &[u8] = b"DX10";
+const DXGI_FORMAT_BC7_TYPELESS: u32 = 97;
+const DXGI_FORMAT_BC7_UNORM: u32 = 98;
+const DXGI_FORMAT_BC7_UNORM_SRGB: u32 = 99;
+const HEADER_SIZE: usize = 4 + 124 + 20;
+/// As large as any GPU accepts, a header claiming more is more likely crafted than real.
+const MAX_DIMENSION: u32 = 16384;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Returns the size of the blocks and of the decoded RGBA pixels, in bytes.
+fn sizes(width: u32, height: u32) -> io::Result<(usize, usize)> {
+    if width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(invalid("BC7 texture too large"));
+    }
+    let (width, height) = (width as usize, height as usize);
+    let blocks = width
+        .div_ceil(4)
+        .checked_mul(height.div_ceil(4))
+        .and_then(|count| count.checked_mul(16));
+    let pixels = width
+        .checked_mul(height)
+        .and_then(|count| count.checked_mul(4));
+    blocks
+        .zip(pixels)
+        .ok_or_else(|| invalid("BC7 texture too large"))
+}
+
+fn read_le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// Returns the width, height and the blocks of the first mip level.
+pub fn parse(dds: &[u8]) -> io::Result<(u32, u32, &[u8])> {
+    if dds.len() < HEADER_SIZE || &dds[..4] != DDS_MAGIC {
+        return Err(invalid("Not a DDS file"));
+    }
+    if &dds[84..88] != DX10 {
+        return Err(invalid("Only DDS files with a DX10 header are supported"));
+    }
+    let height = read_le_u32(dds, 12);
+    let width = read_le_u32(dds, 16);
+    match read_le_u32(dds, 128) {
+        DXGI_FORMAT_BC7_TYPELESS | DXGI_FORMAT_BC7_UNORM | DXGI_FORMAT_BC7_UNORM_SRGB => (),
+        format => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported DXGI format {format}"),
+            ))
+        }
+    }
+    let (size, _) = sizes(width, height)?;
+    let blocks = dds[HEADER_SIZE..]
+        .get(..size)
+        .ok_or_else(|| invalid("Truncated BC7 data"))?;
+    Ok((width, height, blocks))
+}
+
+struct ModeInfo {
+    num_subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    index_bits2: u32,
+}
+
+const MODES: [ModeInfo; 8] = [
+    ModeInfo {
+        num_subsets: 3,
+        partition_bits: 4,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        color_bits: 4,
+        alpha_bits: 0,
+        endpoint_pbits: true,
+        shared_pbits: false,
+        index_bits: 3,
+        index_bits2: 0,
+    },
+    ModeInfo {
+        num_subsets: 2,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        color_bits: 6,
+        alpha_bits: 0,
+        endpoint_pbits: false,
+        shared_pbits: true,
+        index_bits: 3,
+        index_bits2: 0,
+    },
+    ModeInfo {
+        num_subsets: 3,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        color_bits: 5,
+        alpha_bits: 0,
+        endpoint_pbits: false,
+        shared_pbits: false,
+        index_bits: 2,
+        index_bits2: 0,
+    },
+    ModeInfo {
+        num_subsets: 2,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        color_bits: 7,
+        alpha_bits: 0,
+        endpoint_pbits: true,
+        shared_pbits: false,
+        index_bits: 2,
+        index_bits2: 0,
+    },
+    ModeInfo {
+        num_subsets: 1,
+        partition_bits: 0,
+        rotation_bits: 2,
+        index_selection_bits: 1,
+        color_bits: 5,
+        alpha_bits: 6,
+        endpoint_pbits: false,
+        shared_pbits: false,
+        index_bits: 2,
+        index_bits2: 3,
+    },
+    ModeInfo {
+        num_subsets: 1,
+        partition_bits: 0,
+        rotation_bits: 2,
+        index_selection_bits: 0,
+        color_bits: 7,
+        alpha_bits: 8,
+        endpoint_pbits: false,
+        shared_pbits: false,
+        index_bits: 2,
+        index_bits2: 2,
+    },
+    ModeInfo {
+        num_subsets: 1,
+        partition_bits: 0,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        color_bits: 7,
+        alpha_bits: 7,
+        endpoint_pbits: true,
+        shared_pbits: false,
+        index_bits: 4,
+        index_bits2: 0,
+    },
+    ModeInfo {
+        num_subsets: 2,
+        partition_bits: 6,
+        rotation_bits: 0,
+        index_selection_bits: 0,
+        color_bits: 5,
+        alpha_bits: 5,
+        endpoint_pbits: true,
+        shared_pbits: false,
+        index_bits: 2,
+        index_bits2: 0,
+    },
+];
+
+// Each entry is a bitmask of which pixels belong to the second subset.
+const PARTITIONS2: [u16; 64] = [
+    0xcccc, 0x8888, 0xeeee, 0xecc8, 0xc880, 0xfeec, 0xfec8, 0xec80, 0xc800, 0xffec, 0xfe80, 0xe800,
+    0xffe8, 0xff00, 0xfff0, 0xf000, 0xf710, 0x008e, 0x7100, 0x08ce, 0x008c, 0x7310, 0x3100, 0x8cce,
+    0x088c, 0x3110, 0x6666, 0x366c, 0x17e8, 0x0ff0, 0x718e, 0x399c, 0xaaaa, 0xf0f0, 0x5a5a, 0x33cc,
+    0x3c3c, 0x55aa, 0x9696, 0xa55a, 0x73ce, 0x13c8, 0x324c, 0x3bdc, 0x6996, 0xc33c, 0x9966, 0x0660,
+    0x0272, 0x04e4, 0x4e40, 0x2720, 0xc936, 0x936c, 0x39c6, 0x639c, 0x9336, 0x9cc6, 0x817e, 0xe718,
+    0xccf0, 0x0fcc, 0x7744, 0xee22,
+];
+
+const PARTITIONS3: [[u8; 16]; 64] = [
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 1, 2, 2, 2, 2],
+    [0, 0, 0, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 2, 0, 0, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 1, 0, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2],
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2],
+    [0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2, 1, 2, 2, 2],
+    [0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0, 2, 2, 2, 0],
+    [0, 0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2],
+    [0, 1, 1, 1, 0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0],
+    [0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2, 0, 2, 2, 2],
+    [0, 0, 0, 1, 0, 0, 0, 1, 2, 2, 2, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 0, 0, 1, 1, 0, 0, 2, 2, 1, 0, 2, 2, 1, 0],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1, 0, 0, 0, 0],
+    [0, 0, 1, 2, 0, 0, 1, 2, 1, 1, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1, 0, 1, 1, 0],
+    [0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1],
+    [0, 0, 2, 2, 1, 1, 0, 2, 1, 1, 0, 2, 0, 0, 2, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 0, 0, 2, 2, 2, 2, 2],
+    [0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 0, 0, 2, 0, 0, 0, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 2, 2, 2],
+    [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 2, 0, 0, 2, 2, 0, 2, 2, 2],
+    [0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0],
+    [0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0],
+    [0, 1, 2, 0, 2, 0, 1, 2, 1, 2, 0, 1, 0, 1, 2, 0],
+    [0, 0, 1, 1, 2, 2, 0, 0, 1, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0, 1, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 0, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 1, 1],
+    [0, 2, 2, 0, 1, 2, 2, 1, 0, 2, 2, 0, 1, 2, 2, 1],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 0, 1, 0, 1],
+    [0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 2, 2, 2, 0, 1, 1, 1],
+    [0, 0, 0, 2, 1, 1, 1, 2, 0, 0, 0, 2, 1, 1, 1, 2],
+    [0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2],
+    [0, 0, 0, 2, 1, 1, 1, 2, 1, 1, 1, 2, 0, 0, 0, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2],
+    [0, 0, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2],
+    [0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1],
+    [0, 2, 2, 2, 1, 2, 2, 2, 0, 2, 2, 2, 1, 2, 2, 2],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 1, 2, 0, 1, 1, 2, 2, 0, 1, 2, 2, 2, 0],
+];
+
+const ANCHORS2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2,
+    2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+const ANCHORS3_2: [u8; 64] = [
+    3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, 3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5,
+    15, 15, 8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, 3, 15, 5, 5, 5, 8, 5, 10, 5,
+    10, 8, 13, 15, 12, 3, 3,
+];
+
+const ANCHORS3_3: [u8; 64] = [
+    15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, 15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6,
+    10, 15, 15, 10, 8, 15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, 15, 3, 15, 15, 15,
+    15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+];
+
+const WEIGHTS2: [u16; 4] = [0, 21, 43, 64];
+const WEIGHTS3: [u16; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const WEIGHTS4: [u16; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+pub(crate) fn weights(bits: u32) -> &'static [u16] {
+    match bits {
+        2 => &WEIGHTS2,
+        3 => &WEIGHTS3,
+        4 => &WEIGHTS4,
+        _ => unreachable!(),
+    }
+}
+
+pub(crate) fn interpolate(e0: u8, e1: u8, weight: u16) -> u8 {
+    (((64 - weight) * e0 as u16 + weight * e1 as u16 + 32) >> 6) as u8
+}
+
+struct BitReader {
+    bits: u128,
+}
+
+impl BitReader {
+    fn read(&mut self, count: u32) -> u8 {
+        let value = (self.bits & ((1 << count) - 1)) as u8;
+        self.bits >>= count;
+        value
+    }
+}
+
+fn subset(num_subsets: usize, partition: usize, pixel: usize) -> usize {
+    match num_subsets {
+        1 => 0,
+        2 => ((PARTITIONS2[partition] >> pixel) & 1) as usize,
+        _ => PARTITIONS3[partition][pixel] as usize,
+    }
+}
+
+fn is_anchor(num_subsets: usize, partition: usize, pixel: usize) -> bool {
+    pixel == 0
+        || match num_subsets {
+            1 => false,
+            2 => ANCHORS2[partition] as usize == pixel,
+            _ => ANCHORS3_2[partition] as usize == pixel || ANCHORS3_3[partition] as usize == pixel,
+        }
+}
+
+fn expand(value: u8, bits: u32) -> u8 {
+    if bits == 8 {
+        return value;
+    }
+    let value = value << (8 - bits);
+    value | (value >> bits)
+}
+
+/// Decodes a single 4×4 block into 16 RGBA pixels.
+pub(crate) fn decode_block(block: &[u8; 16], out: &mut [[u8; 4]; 16]) {
+    let mut reader = BitReader {
+        bits: u128::from_le_bytes(*block),
+    };
+    let mode = block[0].trailing_zeros() as usize;
+    let Some(info) = MODES.get(mode) else {
+        // Reserved mode, the specification mandates transparent black.
+        *out = [[0; 4]; 16];
+        return;
+    };
+    reader.read(mode as u32 + 1);
+
+    let partition = reader.read(info.partition_bits) as usize;
+    let rotation = reader.read(info.rotation_bits);
+    let index_selection = reader.read(info.index_selection_bits);
+
+    let num_endpoints = info.num_subsets * 2;
+    let mut endpoints = [[0u8; 4]; 6];
+    for channel in 0..3 {
+        for endpoint in endpoints.iter_mut().take(num_endpoints) {
+            endpoint[channel] = reader.read(info.color_bits);
+        }
+    }
+    if info.alpha_bits > 0 {
+        for endpoint in endpoints.iter_mut().take(num_endpoints) {
+            endpoint[3] = reader.read(info.alpha_bits);
+        }
+    }
+
+    let mut color_bits = info.color_bits;
+    let mut alpha_bits = info.alpha_bits;
+    if info.endpoint_pbits || info.shared_pbits {
+        let mut pbits = [0u8; 6];
+        if info.endpoint_pbits {
+            for pbit in pbits.iter_mut().take(num_endpoints) {
+                *pbit = reader.read(1);
+            }
+        } else {
+            for subset in 0..info.num_subsets {
+                let pbit = reader.read(1);
+                pbits[subset * 2] = pbit;
+                pbits[subset * 2 + 1] = pbit;
+            }
+        }
+        for (endpoint, pbit) in endpoints.iter_mut().zip(pbits).take(num_endpoints) {
+            for channel in endpoint.iter_mut() {
+                *channel = (*channel << 1) | pbit;
+            }
+        }
+        color_bits += 1;
+        if alpha_bits > 0 {
+            alpha_bits += 1;
+        }
+    }
+    for endpoint in endpoints.iter_mut().take(num_endpoints) {
+        for channel in endpoint.iter_mut().take(3) {
+            *channel = expand(*channel, color_bits);
+        }
+        endpoint[3] = if alpha_bits > 0 {
+            expand(endpoint[3], alpha_bits)
+        } else {
+            255
+        };
+    }
+
+    let mut indices = [0u8; 16];
+    for (pixel, index) in indices.iter_mut().enumerate() {
+        let anchor = is_anchor(info.num_subsets, partition, pixel);
+        *index = reader.read(info.index_bits - anchor as u32);
+    }
+    let mut indices2 = [0u8; 16];
+    if info.index_bits2 > 0 {
+        for (pixel, index) in indices2.iter_mut().enumerate() {
+            *index = reader.read(info.index_bits2 - (pixel == 0) as u32);
+        }
+    }
+
+    let (color_indices, color_weights, alpha_indices, alpha_weights) = if info.index_bits2 == 0 {
+        let weights = weights(info.index_bits);
+        (&indices, weights, &indices, weights)
+    } else if index_selection == 0 {
+        (
+            &indices,
+            weights(info.index_bits),
+            &indices2,
+            weights(info.index_bits2),
+        )
+    } else {
+        (
+            &indices2,
+            weights(info.index_bits2),
+            &indices,
+            weights(info.index_bits),
+        )
+    };
+
+    for (pixel, out) in out.iter_mut().enumerate() {
+        let subset = subset(info.num_subsets, partition, pixel);
+        let e0 = endpoints[subset * 2];
+        let e1 = endpoints[subset * 2 + 1];
+        let weight = color_weights[color_indices[pixel] as usize];
+        for channel in 0..3 {
+            out[channel] = interpolate(e0[channel], e1[channel], weight);
+        }
+        let weight = alpha_weights[alpha_indices[pixel] as usize];
+        out[3] = interpolate(e0[3], e1[3], weight);
+        match rotation {
+            1 => out.swap(0, 3),
+            2 => out.swap(1, 3),
+            3 => out.swap(2, 3),
+            _ => (),
+        }
+    }
+}
+
+/// Decodes raw BC7 blocks into RGBA pixels.
+pub fn decode_blocks(width: u32, height: u32, blocks: &[u8]) -> io::Result<Vec<u8>> {
+    let (blocks_size, pixels_size) = sizes(width, height)?;
+    if blocks.len() < blocks_size {
+        return Err(invalid("Truncated BC7 data"));
+    }
+    let (width, height) = (width as usize, height as usize);
+    let blocks_x = width.div_ceil(4);
+    let blocks_y = height.div_ceil(4);
+    let mut data = vec![0u8; pixels_size];
+    let mut pixels = [[0u8; 4]; 16];
+    for (i, block) in blocks
+        .chunks_exact(16)
+        .take(blocks_x * blocks_y)
+        .enumerate()
+    {
+        let bx = (i % blocks_x) * 4;
+        let by = (i / blocks_x) * 4;
+        decode_block(block.try_into().unwrap(), &mut pixels);
+        for y in 0..4.min(height - by) {
+            for x in 0..4.min(width - bx) {
+                let offset = ((by + y) * width + bx + x) * 4;
+                data[offset..offset + 4].copy_from_slice(&pixels[y * 4 + x]);
+            }
+        }
+    }
+    Ok(data)
+}
+
+/// Decodes a DDS file containing BC7 blocks into RGBA pixels.
+pub fn decode(dds: &[u8]) -> io::Result<(u32, u32, Vec<u8>)> {
+    let (width, height, blocks) = parse(dds)?;
+    let data = decode_blocks(width, height, blocks)?;
+    Ok((width, height, data))
+}
+
+#[cfg(feature = "encoding")]
+pub use encoder::encode;
+
+#[cfg(feature = "encoding")]
+mod encoder {
+    use super::*;
+    use crate::blocks;
+    use crate::Quality;
+
+    struct BitWriter {
+        bits: u128,
+        pos: u32,
+    }
+
+    impl BitWriter {
+        fn new() -> BitWriter {
+            BitWriter { bits: 0, pos: 0 }
+        }
+
+        fn write(&mut self, value: u8, count: u32) {
+            self.bits |= (value as u128) << self.pos;
+            self.pos += count;
+        }
+
+        fn finish(self) -> [u8; 16] {
+            debug_assert_eq!(self.pos, 128);
+            self.bits.to_le_bytes()
+        }
+    }
+
+    fn to_float(pixels: &[[u8; 4]; 16]) -> [[f32; 4]; 16] {
+        pixels.map(|pixel| pixel.map(f32::from))
+    }
+
+    fn nearest_index(pixel: &[f32], e0: &[u8], e1: &[u8], weights: &[u16]) -> u8 {
+        let mut best = (f32::MAX, 0);
+        for (i, &weight) in weights.iter().enumerate() {
+            let error: f32 = pixel
+                .iter()
+                .zip(e0.iter().zip(e1))
+                .map(|(&p, (&a, &b))| {
+                    let diff = p - interpolate(a, b, weight) as f32;
+                    diff * diff
+                })
+                .sum();
+            if error < best.0 {
+                best = (error, i as u8);
+            }
+        }
+        best.1
+    }
+
+    /// Finds the endpoints minimising the squared error for these indices.
+    fn least_squares<const N: usize>(
+        pixels: &[[f32; 4]; 16],
+        channels: [usize; N],
+        indices: &[u8; 16],
+        weights: &[u16],
+    ) -> Option<([f32; N], [f32; N])> {
+        let (mut a, mut b, mut c) = (0f32, 0f32, 0f32);
+        let mut rhs0 = [0f32; N];
+        let mut rhs1 = [0f32; N];
+        for (pixel, &index) in pixels.iter().zip(indices) {
+            let w = weights[index as usize] as f32 / 64.;
+            a += (1. - w) * (1. - w);
+            b += (1. - w) * w;
+            c += w * w;
+            for (i, &channel) in channels.iter().enumerate() {
+                rhs0[i] += (1. - w) * pixel[channel];
+                rhs1[i] += w * pixel[channel];
+            }
+        }
+        let det = a * c - b * b;
+        if det.abs() < 1e-6 {
+            return None;
+        }
+        let mut e0 = [0f32; N];
+        let mut e1 = [0f32; N];
+        for i in 0..N {
+            e0[i] = ((c * rhs0[i] - b * rhs1[i]) / det).clamp(0., 255.);
+            e1[i] = ((a * rhs1[i] - b * rhs0[i]) / det).clamp(0., 255.);
+        }
+        Some((e0, e1))
+    }
+
+    /// Mode 6: a single subset with RGBA endpoints of 7 bits plus a p-bit, and 4-bit indices.
+    fn encode_mode6(
+        pixels: &[[u8; 4]; 16],
+        floats: &[[f32; 4]; 16],
+        iterations: usize,
+    ) -> [u8; 16] {
+        let (mean, axis) = blocks::principal_axis(floats);
+        let (mut e0, mut e1) = blocks::bounding_endpoints(floats, mean, axis);
+        let mut best = ([0u8; 16], u32::MAX);
+        let mut decoded = [[0u8; 4]; 16];
+        for _ in 0..=iterations {
+            // Pick, for each endpoint, the p-bit which gets closest to the wanted color.
+            let quantize = |endpoint: [f32; 4]| {
+                let mut best = ([0u8; 4], 0u8, f32::MAX);
+                for pbit in 0..2u8 {
+                    let q =
+                        endpoint.map(|v| ((v - pbit as f32) / 2.).round().clamp(0., 127.) as u8);
+                    let error: f32 = q
+                        .iter()
+                        .zip(endpoint)
+                        .map(|(&q, v)| {
+                            let diff = ((q << 1) | pbit) as f32 - v;
+                            diff * diff
+                        })
+                        .sum();
+                    if error < best.2 {
+                        best = (q, pbit, error);
+                    }
+                }
+                (best.0, best.1)
+            };
+            let (mut q0, mut p0) = quantize(e0);
+            let (mut q1, mut p1) = quantize(e1);
+            let expand = |q: [u8; 4], p: u8| q.map(|q| (q << 1) | p);
+            let mut indices = [0u8; 16];
+            for (index, pixel) in indices.iter_mut().zip(floats) {
+                *index = nearest_index(pixel, &expand(q0, p0), &expand(q1, p1), &WEIGHTS4);
+            }
+            // The anchor index has an implicit zero as its top bit.
+            if indices[0] >= 8 {
+                core::mem::swap(&mut q0, &mut q1);
+                core::mem::swap(&mut p0, &mut p1);
+                for index in indices.iter_mut() {
+                    *index = 15 - *index;
+                }
+            }
+
+            let mut writer = BitWriter::new();
+            writer.write(1 << 6, 7);
+            for channel in 0..4 {
+                writer.write(q0[channel], 7);
+                writer.write(q1[channel], 7);
+            }
+            writer.write(p0, 1);
+            writer.write(p1, 1);
+            writer.write(indices[0], 3);
+            for &index in &indices[1..] {
+                writer.write(index, 4);
+            }
+            let block = writer.finish();
+
+            decode_block(&block, &mut decoded);
+            let error = blocks::error(pixels, &decoded);
+            if error < best.1 {
+                best = (block, error);
+            }
+
+            match least_squares(floats, [0, 1, 2, 3], &indices, &WEIGHTS4) {
+                Some((a, b)) => (e0, e1) = (a, b),
+                None => break,
+            }
+        }
+        best.0
+    }
+
+    /// Mode 5: a single subset with separate 7-bit RGB and 8-bit alpha endpoints and indices.
+    fn encode_mode5(floats: &[[f32; 4]; 16]) -> [u8; 16] {
+        let (mean, axis) = blocks::principal_axis(floats);
+        let (c0, c1) = blocks::bounding_endpoints(floats, mean, [axis[0], axis[1], axis[2], 0.]);
+        let alpha = floats.iter().map(|pixel| pixel[3]);
+        let a0 = alpha.clone().fold(255f32, f32::min);
+        let a1 = alpha.fold(0f32, f32::max);
+
+        let quantize7 = |v: f32| {
+            let q = (v / 2.) as i32;
+            (q - 1..=q + 1)
+                .map(|q| q.clamp(0, 127) as u8)
+                .min_by_key(|&q| (expand(q, 7) as i32 - v.round() as i32).abs())
+                .unwrap()
+        };
+        let mut q0 = [c0[0], c0[1], c0[2]].map(quantize7);
+        let mut q1 = [c1[0], c1[1], c1[2]].map(quantize7);
+        let mut qa0 = a0.round() as u8;
+        let mut qa1 = a1.round() as u8;
+
+        let mut color_indices = [0u8; 16];
+        let mut alpha_indices = [0u8; 16];
+        for (i, pixel) in floats.iter().enumerate() {
+            color_indices[i] = nearest_index(
+                &pixel[..3],
+                &q0.map(|q| expand(q, 7)),
+                &q1.map(|q| expand(q, 7)),
+                &WEIGHTS2,
+            );
+            alpha_indices[i] = nearest_index(&pixel[3..], &[qa0], &[qa1], &WEIGHTS2);
+        }
+        if color_indices[0] >= 2 {
+            core::mem::swap(&mut q0, &mut q1);
+            for index in color_indices.iter_mut() {
+                *index = 3 - *index;
+            }
+        }
+        if alpha_indices[0] >= 2 {
+            core::mem::swap(&mut qa0, &mut qa1);
+            for index in alpha_indices.iter_mut() {
+                *index = 3 - *index;
+            }
+        }
+
+        let mut writer = BitWriter::new();
+        writer.write(1 << 5, 6);
+        // No rotation.
+        writer.write(0, 2);
+        for channel in 0..3 {
+            writer.write(q0[channel], 7);
+            writer.write(q1[channel], 7);
+        }
+        writer.write(qa0, 8);
+        writer.write(qa1, 8);
+        for indices in [color_indices, alpha_indices] {
+            writer.write(indices[0], 1);
+            for &index in &indices[1..] {
+                writer.write(index, 2);
+            }
+        }
+        writer.finish()
+    }
+
+    fn encode_block(pixels: &[[u8; 4]; 16], quality: Quality) -> [u8; 16] {
+        let floats = to_float(pixels);
+        let iterations = match quality {
+            Quality::Fast => 0,
+            Quality::Normal => 2,
+            Quality::Best => 8,
+        };
+        let block = encode_mode6(pixels, &floats, iterations);
+        if quality != Quality::Best {
+            return block;
+        }
+
+        let mut decoded = [[0u8; 4]; 16];
+        decode_block(&block, &mut decoded);
+        let error = blocks::error(pixels, &decoded);
+        let other = encode_mode5(&floats);
+        decode_block(&other, &mut decoded);
+        if blocks::error(pixels, &decoded) < error {
+            other
+        } else {
+            block
+        }
+    }
+
+    /// Encodes RGBA pixels into a DDS file containing BC7 blocks.
+    pub fn encode(width: u32, height: u32, rgba: &[u8], quality: Quality) -> Vec<u8> {
+        let blocks = blocks::encode_blocks(width, height, 4, 4, rgba, |pixels| {
+            encode_block(pixels.try_into().unwrap(), quality)
+        });
+
+        let mut dds = Vec::with_capacity(HEADER_SIZE + blocks.len());
+        dds.extend_from_slice(DDS_MAGIC);
+        let header: [u32; 31] = [
+            124,
+            // DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT | DDSD_LINEARSIZE
+            0x81007,
+            height,
+            width,
+            blocks.len() as u32,
+            0,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            // DDS_PIXELFORMAT, with only the fourCC set.
+            32,
+            0x4,
+            u32::from_le_bytes(DX10.try_into().unwrap()),
+            0,
+            0,
+            0,
+            0,
+            0,
+            // DDSCAPS_TEXTURE
+            0x1000,
+            0,
+            0,
+            0,
+            0,
+        ];
+        // DXGI_FORMAT_BC7_UNORM, D3D10_RESOURCE_DIMENSION_TEXTURE2D, no flags, one texture.
+        let dx10: [u32; 5] = [DXGI_FORMAT_BC7_UNORM, 3, 0, 1, 0];
+        for value in header.into_iter().chain(dx10) {
+            dds.extend_from_slice(&value.to_le_bytes());
+        }
+        dds.extend_from_slice(&blocks);
+        dds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_one(block: [u8; 16]) -> [[u8; 4]; 16] {
+        let mut pixels = [[0u8; 4]; 16];
+        decode_block(&block, &mut pixels);
+        pixels
+    }
+
+    #[test]
+    fn mode6_gradient() {
+        // Endpoints transparent black and opaque white, pixel i using index i.
+        let pixels = decode_one([
+            0x40, 0xc0, 0x1f, 0xf0, 0x07, 0xfc, 0x01, 0x7f, 0x11, 0x32, 0x54, 0x76, 0x98, 0xba,
+            0xdc, 0xfe,
+        ]);
+        let expected = [
+            0, 16, 36, 52, 68, 84, 104, 120, 135, 151, 171, 187, 203, 219, 239, 255,
+        ];
+        for (pixel, value) in pixels.iter().zip(expected) {
+            assert_eq!(*pixel, [value; 4]);
+        }
+    }
+
+    #[test]
+    fn mode1_partition() {
+        // Partition 0 splits the columns in halves, red with a p-bit of 0 on the left and blue
+        // with a p-bit of 1 on the right.
+        let pixels = decode_one([
+            0x02, 0xff, 0x0f, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf0, 0xff, 0x02, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ]);
+        for (i, pixel) in pixels.iter().enumerate() {
+            let expected = if i % 4 < 2 {
+                [253, 0, 0, 255]
+            } else {
+                [2, 2, 255, 255]
+            };
+            assert_eq!(*pixel, expected, "pixel {i}");
+        }
+    }
+
+    #[test]
+    fn reserved_mode() {
+        assert_eq!(decode_one([0; 16]), [[0; 4]; 16]);
+    }
+
+    fn dds_header(width: u32, height: u32) -> Vec<u8> {
+        let mut dds = vec![0u8; HEADER_SIZE];
+        dds[..4].copy_from_slice(DDS_MAGIC);
+        dds[12..16].copy_from_slice(&height.to_le_bytes());
+        dds[16..20].copy_from_slice(&width.to_le_bytes());
+        dds[84..88].copy_from_slice(DX10);
+        dds[128..132].copy_from_slice(&DXGI_FORMAT_BC7_UNORM.to_le_bytes());
+        dds
+    }
+
+    #[test]
+    fn huge_dimensions() {
+        let err = decode(&dds_header(u32::MAX, u32::MAX)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = decode_blocks(u32::MAX, u32::MAX, &[]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated() {
+        let err = decode(&dds_header(8, 8)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "encoding")]
+    #[test]
+    fn round_trip() {
+        let (width, height) = (13, 9);
+        // A diagonal gradient, with a hard edge through the middle.
+        let rgba: Vec<u8> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                let t = x + y;
+                let edge = if x > y { 60 } else { 0 };
+                [t * 11, 255 - t * 7 - edge, t * 5 + edge, 255 - t * 3]
+            })
+            .map(|value| value as u8)
+            .collect();
+        let dds = encode(width, height, &rgba, crate::Quality::Normal);
+        let (decoded_width, decoded_height, decoded) = decode(&dds).unwrap();
+        assert_eq!((decoded_width, decoded_height), (width, height));
+        let mse = rgba
+            .iter()
+            .zip(&decoded)
+            .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+            .sum::<f64>()
+            / rgba.len() as f64;
+        assert!(mse < 32., "MSE {mse}");
+    }
+}
@@ -45,9 +45,14 @@ fn main() {
     }
     let file = File::open(&args[1]).unwrap();
     let file = BufReader::new(file);
-    let mut model = inochi2d::Model::parse(file).unwrap();
+    let model = inochi2d::Model::parse(file).unwrap();
     print_info(&model.puppet.meta);
-    let textures = model.decode_textures();
+    // Models which don’t quite follow the format often render fine anyway.
+    if let Err(errors) = model.validate() {
+        for error in errors {
+            eprintln!("Warning: {error}");
+        }
+    }
 
     let size = if args.len() == 3 {
         args[2].split_once('x').map(|(width, height)| {
@@ -64,21 +69,39 @@ fn main() {
 
     let mut glfw = glfw::init(glfw::LOG_ERRORS).unwrap();
     glfw.window_hint(glfw::WindowHint::ClientApi(glfw::ClientApiHint::OpenGlEs));
-    glfw.window_hint(glfw::WindowHint::ContextVersion(2, 0));
     glfw.window_hint(glfw::WindowHint::TransparentFramebuffer(true));
 
+    // Prefer GLES 3, but GLES 2 is enough.
+    glfw.window_hint(glfw::WindowHint::ContextVersion(3, 0));
     let (mut window, events) = glfw
         .create_window(width, height, "inochi2d", glfw::WindowMode::Windowed)
+        .or_else(|| {
+            glfw.window_hint(glfw::WindowHint::ContextVersion(2, 0));
+            glfw.create_window(width, height, "inochi2d", glfw::WindowMode::Windowed)
+        })
         .unwrap();
     window.make_current();
     window.set_key_polling(true);
     window.set_framebuffer_size_polling(true);
 
-    let gl = inochi2d::glow::Context::new();
-    let mut renderer = inochi2d::gl::setup(&gl, &model.puppet.nodes, textures, width, height);
+    // Safety: GLFW returns the entry points of the context made current above, which stays
+    // current on this thread for as long as the window lives.
+    let gl = unsafe { inochi2d::glow::Context::from_loader(|name| window.get_proc_address(name)) };
+    let gl = match gl {
+        Ok(gl) => gl,
+        Err(err) => {
+            eprintln!("{err}");
+            return;
+        }
+    };
+    let support = inochi2d::gl::texture_support(&gl);
+    let textures = model.decode_textures_for(support);
+    let mut renderer =
+        inochi2d::gl::setup_progressive(&gl, &model.puppet.nodes, textures, width, height);
 
     let num_nodes = inochi2d::gl::count_nodes(&model.puppet.nodes);
     while !window.should_close() {
+        renderer.poll_textures().unwrap();
         renderer.clear();
         let order = inochi2d::gl::sort_nodes_by_zsort(num_nodes, &model.puppet.nodes);
         renderer.render_nodes(&order);
@@ -0,0 +1,84 @@
+// Copyright (c) 2022 Emmanuel Gil Peyrot <linkmauve@linkmauve.fr>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use inochi2d::{Quality, TextureFormat};
+use std::fs::File;
+use std::io::BufReader;
+use std::process::ExitCode;
+
+fn parse_format(format: &str) -> Option<TextureFormat> {
+    Some(match format {
+        "PNG" => TextureFormat::Png,
+        "TGA" => TextureFormat::Tga,
+        "BC7" => TextureFormat::Bc7,
+        "ASTC" => TextureFormat::Astc {
+            block_width: 4,
+            block_height: 4,
+        },
+        _ => {
+            let size = format.strip_prefix("ASTC:")?;
+            let (width, height) = size.split_once('x')?;
+            TextureFormat::Astc {
+                block_width: width.parse().ok()?,
+                block_height: height.parse().ok()?,
+            }
+        }
+    })
+}
+
+fn parse_quality(quality: &str) -> Option<Quality> {
+    Some(match quality {
+        "fast" => Quality::Fast,
+        "normal" => Quality::Normal,
+        "best" => Quality::Best,
+        _ => return None,
+    })
+}
+
+fn main() -> ExitCode {
+    let args: Vec<_> = std::env::args().collect();
+    if args.len() != 4 && args.len() != 5 {
+        eprintln!(
+            "Usage: {} <PNG|TGA|BC7|ASTC[:<width>x<height>]> <model.inp> <out.inp> [fast|normal|best]",
+            args[0]
+        );
+        return ExitCode::FAILURE;
+    }
+
+    let Some(format) = parse_format(&args[1]) else {
+        eprintln!("Unsupported format {}", args[1]);
+        return ExitCode::FAILURE;
+    };
+    let quality = match args.get(4) {
+        None => Quality::default(),
+        Some(quality) => match parse_quality(quality) {
+            Some(quality) => quality,
+            None => {
+                eprintln!("Unknown quality {quality}");
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+
+    let file = File::open(&args[2]).unwrap();
+    let file = BufReader::new(file);
+    // Keep whatever fields we don’t know about, so that they end up in the output unchanged.
+    let mut model = inochi2d::Model::parse_lenient(file).unwrap();
+
+    for (i, tex) in model.textures.iter_mut().enumerate() {
+        match tex.decode().and_then(|tex| tex.encode(format, quality)) {
+            Ok(encoded) => *tex = encoded,
+            Err(err) => {
+                eprintln!("Failed to transcode texture {i}: {err}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let out = File::create(&args[3]).unwrap();
+    model.serialize(out).unwrap();
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,161 @@
+// Copyright (c) 2023 Emmanuel Gil Peyrot <linkmauve@linkmauve.fr>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Helpers shared by the block-based texture encoders.
+
+struct Image<'a> {
+    width: usize,
+    height: usize,
+    block_width: usize,
+    block_height: usize,
+    rgba: &'a [u8],
+}
+
+impl Image<'_> {
+    fn encode_row<F>(&self, by: usize, out: &mut [u8], encode_block: &F)
+    where
+        F: Fn(&[[u8; 4]]) -> [u8; 16],
+    {
+        let mut pixels = vec![[0u8; 4]; self.block_width * self.block_height];
+        for (bx, block) in out.chunks_exact_mut(16).enumerate() {
+            for y in 0..self.block_height {
+                // Pixels past the edge of the image repeat the last row or column.
+                let sy = (by * self.block_height + y).min(self.height - 1);
+                for x in 0..self.block_width {
+                    let sx = (bx * self.block_width + x).min(self.width - 1);
+                    let offset = (sy * self.width + sx) * 4;
+                    pixels[y * self.block_width + x]
+                        .copy_from_slice(&self.rgba[offset..offset + 4]);
+                }
+            }
+            block.copy_from_slice(&encode_block(&pixels));
+        }
+    }
+}
+
+/// Splits an RGBA image into blocks, row by row, and concatenates what `encode_block` returns
+/// for each of them.
+pub fn encode_blocks<F>(
+    width: u32,
+    height: u32,
+    block_width: usize,
+    block_height: usize,
+    rgba: &[u8],
+    encode_block: F,
+) -> Vec<u8>
+where
+    F: Fn(&[[u8; 4]]) -> [u8; 16] + Sync,
+{
+    let image = Image {
+        width: width as usize,
+        height: height as usize,
+        block_width,
+        block_height,
+        rgba,
+    };
+    let blocks_x = image.width.div_ceil(block_width);
+    let blocks_y = image.height.div_ceil(block_height);
+    let mut out = vec![0u8; blocks_x * blocks_y * 16];
+    if out.is_empty() {
+        return out;
+    }
+    let row_size = blocks_x * 16;
+
+    #[cfg(feature = "parallel")]
+    {
+        let num_threads = std::thread::available_parallelism()
+            .map(|num| num.get())
+            .unwrap_or(1);
+        let rows_per_thread = blocks_y.div_ceil(num_threads);
+        std::thread::scope(|scope| {
+            for (i, rows) in out.chunks_mut(rows_per_thread * row_size).enumerate() {
+                let image = &image;
+                let encode_block = &encode_block;
+                scope.spawn(move || {
+                    for (j, row) in rows.chunks_exact_mut(row_size).enumerate() {
+                        image.encode_row(i * rows_per_thread + j, row, encode_block);
+                    }
+                });
+            }
+        });
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    for (by, row) in out.chunks_exact_mut(row_size).enumerate() {
+        image.encode_row(by, row, &encode_block);
+    }
+
+    out
+}
+
+/// Sum of the squared differences between two sets of pixels.
+pub fn error(a: &[[u8; 4]], b: &[[u8; 4]]) -> u32 {
+    a.iter()
+        .zip(b)
+        .flat_map(|(a, b)| a.iter().zip(b))
+        .map(|(&a, &b)| {
+            let diff = a as i32 - b as i32;
+            (diff * diff) as u32
+        })
+        .sum()
+}
+
+/// Returns the mean of these pixels and the axis along which they vary the most.
+pub fn principal_axis(pixels: &[[f32; 4]]) -> ([f32; 4], [f32; 4]) {
+    let count = pixels.len() as f32;
+    let mut mean = [0f32; 4];
+    for pixel in pixels {
+        for c in 0..4 {
+            mean[c] += pixel[c] / count;
+        }
+    }
+    let mut covariance = [[0f32; 4]; 4];
+    for pixel in pixels {
+        for i in 0..4 {
+            for j in 0..4 {
+                covariance[i][j] += (pixel[i] - mean[i]) * (pixel[j] - mean[j]);
+            }
+        }
+    }
+    // Power iteration, starting from the row of the channel with the largest variance so that
+    // we don’t start orthogonal to the answer, as could happen with anti-correlated channels.
+    let largest = (0..4)
+        .max_by(|&a, &b| covariance[a][a].total_cmp(&covariance[b][b]))
+        .unwrap();
+    let mut axis = covariance[largest];
+    for _ in 0..8 {
+        let mut next = [0f32; 4];
+        for i in 0..4 {
+            for j in 0..4 {
+                next[i] += covariance[i][j] * axis[j];
+            }
+        }
+        let length = next.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if length < 1e-6 {
+            return (mean, [0.; 4]);
+        }
+        axis = next.map(|x| x / length);
+    }
+    (mean, axis)
+}
+
+/// Projects the pixels on the axis, and returns the two extremities of the segment they cover.
+pub fn bounding_endpoints(
+    pixels: &[[f32; 4]],
+    mean: [f32; 4],
+    axis: [f32; 4],
+) -> ([f32; 4], [f32; 4]) {
+    let mut min = f32::MAX;
+    let mut max = f32::MIN;
+    for pixel in pixels {
+        let t: f32 = (0..4).map(|c| (pixel[c] - mean[c]) * axis[c]).sum();
+        min = min.min(t);
+        max = max.max(t);
+    }
+    let e0 = [0, 1, 2, 3].map(|c| (mean[c] + min * axis[c]).clamp(0., 255.));
+    let e1 = [0, 1, 2, 3].map(|c| (mean[c] + max * axis[c]).clamp(0., 255.));
+    (e0, e1)
+}
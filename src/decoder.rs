@@ -0,0 +1,288 @@
+// Copyright (c) 2023 Emmanuel Gil Peyrot <linkmauve@linkmauve.fr>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Decoding of textures without blocking the caller, either on a pool of threads or step by
+//! step on the calling thread.
+
+use crate::parser::missing_texture;
+use crate::{CompressedTexture, Model, Texture, TextureReceiver, TextureSupport};
+use std::io;
+#[cfg(feature = "parallel")]
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
+#[cfg(feature = "parallel")]
+use std::sync::{Arc, Mutex};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+
+type TextureSender = mpsc::Sender<(usize, io::Result<Texture>)>;
+
+/// Looks up the textures at these indices, reporting missing ones right away.
+fn lookup<'a>(
+    model: &'a Model,
+    indices: &[usize],
+    sender: &TextureSender,
+) -> Vec<(usize, &'a CompressedTexture)> {
+    let mut textures = Vec::with_capacity(indices.len());
+    for &index in indices {
+        match model.textures.get(index) {
+            Some(texture) => textures.push((index, texture)),
+            None => sender.send((index, Err(missing_texture(index)))).unwrap(),
+        }
+    }
+    textures
+}
+
+#[cfg(feature = "parallel")]
+struct Task {
+    index: usize,
+    texture: CompressedTexture,
+    support: TextureSupport,
+    job: Arc<JobState>,
+    sender: TextureSender,
+}
+
+#[cfg(feature = "parallel")]
+#[derive(Default)]
+struct JobState {
+    decoded_bytes: AtomicU64,
+    total_bytes: u64,
+    cancelled: AtomicBool,
+}
+
+/// How far along a decoding job is, counted in bytes of compressed texture data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub decoded_bytes: u64,
+    pub total_bytes: u64,
+}
+
+impl Progress {
+    pub fn is_done(&self) -> bool {
+        self.decoded_bytes >= self.total_bytes
+    }
+}
+
+#[cfg(feature = "parallel")]
+/// Lets the caller follow or cancel a decoding job, while the renderer consumes its textures.
+#[derive(Clone)]
+pub struct JobHandle {
+    state: Arc<JobState>,
+}
+
+#[cfg(feature = "parallel")]
+impl JobHandle {
+    pub fn progress(&self) -> Progress {
+        Progress {
+            decoded_bytes: self.state.decoded_bytes.load(Ordering::Relaxed),
+            total_bytes: self.state.total_bytes,
+        }
+    }
+
+    /// Skips every texture which hasn’t started decoding yet, the channel then gets closed
+    /// once the ones in flight are done.
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(feature = "parallel")]
+/// A reusable pool of texture decoding threads.
+///
+/// Dropping the pool doesn’t wait for the textures still queued, its threads exit once they
+/// are done with them.
+pub struct TextureDecoder {
+    queue: mpsc::Sender<Task>,
+}
+
+#[cfg(feature = "parallel")]
+impl Default for TextureDecoder {
+    /// Uses every core but one, which is left for rendering.
+    fn default() -> TextureDecoder {
+        let num_threads = std::thread::available_parallelism()
+            .map(|num| num.get())
+            .unwrap_or(1);
+        TextureDecoder::new(num_threads.saturating_sub(1).max(1))
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl TextureDecoder {
+    pub fn new(num_threads: usize) -> TextureDecoder {
+        let (queue, receiver) = mpsc::channel::<Task>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..num_threads.max(1) {
+            let receiver = Arc::clone(&receiver);
+            std::thread::Builder::new()
+                .name(String::from("Texture Decoder"))
+                .spawn(move || worker(&receiver))
+                .unwrap();
+        }
+        TextureDecoder { queue }
+    }
+
+    /// Decodes every texture of the model, keeping them compressed whenever `support` allows
+    /// it.
+    pub fn decode(&self, model: &Model, support: TextureSupport) -> (JobHandle, TextureReceiver) {
+        let indices: Vec<_> = (0..model.textures.len()).collect();
+        self.decode_some(model, &indices, support)
+    }
+
+    /// Decodes the textures at these indices, largest first since they take the longest.
+    ///
+    /// An index without a texture gets reported as an error on the channel.
+    pub fn decode_some(
+        &self,
+        model: &Model,
+        indices: &[usize],
+        support: TextureSupport,
+    ) -> (JobHandle, TextureReceiver) {
+        let (sender, receiver) = mpsc::channel();
+        let mut textures = lookup(model, indices, &sender);
+        textures.sort_by_key(|(_, texture)| std::cmp::Reverse(texture.as_bytes().len()));
+
+        let job = Arc::new(JobState {
+            total_bytes: textures
+                .iter()
+                .map(|(_, texture)| texture.as_bytes().len() as u64)
+                .sum(),
+            ..JobState::default()
+        });
+        for (index, texture) in textures {
+            // Only the reference to the data gets cloned, not the data itself.
+            let task = Task {
+                index,
+                texture: texture.clone(),
+                support,
+                job: Arc::clone(&job),
+                sender: sender.clone(),
+            };
+            self.queue.send(task).unwrap();
+        }
+        (JobHandle { state: job }, (indices.len(), receiver))
+    }
+}
+
+#[cfg(feature = "parallel")]
+fn worker(queue: &Mutex<mpsc::Receiver<Task>>) {
+    loop {
+        let task = match queue.lock().unwrap().recv() {
+            Ok(task) => task,
+            Err(_) => break,
+        };
+        let job = &task.job;
+        if job.cancelled.load(Ordering::Relaxed) {
+            continue;
+        }
+        let size = task.texture.as_bytes().len() as u64;
+        let texture = task.texture.decode_for(task.support);
+        job.decoded_bytes.fetch_add(size, Ordering::Relaxed);
+        // Nobody is listening anymore, no need to decode the rest of this job.
+        if task.sender.send((task.index, texture)).is_err() {
+            job.cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Decodes textures one by one on the calling thread, whenever it has some time to spare.
+///
+/// This is meant for platforms without threads, like wasm32, where decoding every texture at
+/// once would freeze the page for seconds: call one of the step functions once per frame
+/// instead.
+pub struct IncrementalDecoder {
+    /// In reverse order, so that the next one can be popped.
+    pending: Vec<(usize, CompressedTexture)>,
+    support: TextureSupport,
+    sender: TextureSender,
+    decoded_bytes: u64,
+    total_bytes: u64,
+}
+
+impl IncrementalDecoder {
+    /// Prepares to decode every texture of the model, keeping them compressed whenever
+    /// `support` allows it.
+    pub fn new(model: &Model, support: TextureSupport) -> (IncrementalDecoder, TextureReceiver) {
+        let indices: Vec<_> = (0..model.textures.len()).collect();
+        IncrementalDecoder::with_indices(model, &indices, support)
+    }
+
+    /// Prepares to decode only the textures at these indices, in this order.
+    ///
+    /// An index without a texture gets reported as an error on the channel.
+    pub fn with_indices(
+        model: &Model,
+        indices: &[usize],
+        support: TextureSupport,
+    ) -> (IncrementalDecoder, TextureReceiver) {
+        let (sender, receiver) = mpsc::channel();
+        let pending: Vec<_> = lookup(model, indices, &sender)
+            .into_iter()
+            .rev()
+            .map(|(index, texture)| (index, texture.clone()))
+            .collect();
+        let total_bytes = pending
+            .iter()
+            .map(|(_, texture)| texture.as_bytes().len() as u64)
+            .sum();
+        let decoder = IncrementalDecoder {
+            pending,
+            support,
+            sender,
+            decoded_bytes: 0,
+            total_bytes,
+        };
+        (decoder, (indices.len(), receiver))
+    }
+
+    /// Decodes textures until `out_of_time` returns true, and returns whether every texture
+    /// has been decoded.
+    ///
+    /// Textures can’t be split, so at least one gets decoded on each call even if it takes
+    /// longer than the caller wanted.
+    pub fn step_until<F: FnMut() -> bool>(&mut self, mut out_of_time: F) -> bool {
+        while let Some((index, texture)) = self.pending.pop() {
+            let size = texture.as_bytes().len() as u64;
+            let texture = texture.decode_for(self.support);
+            self.decoded_bytes += size;
+            // Nobody is listening anymore, no need to decode the rest.
+            if self.sender.send((index, texture)).is_err() {
+                self.cancel();
+            }
+            if out_of_time() {
+                break;
+            }
+        }
+        self.is_done()
+    }
+
+    /// Decodes textures for about `budget`, see [`step_until`](Self::step_until).
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn step(&mut self, budget: Duration) -> bool {
+        let start = Instant::now();
+        self.step_until(|| start.elapsed() >= budget)
+    }
+
+    /// Drops every texture not decoded yet, the channel then gets closed along with the
+    /// decoder.
+    pub fn cancel(&mut self) {
+        self.pending.clear();
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn progress(&self) -> Progress {
+        Progress {
+            decoded_bytes: self.decoded_bytes,
+            total_bytes: self.total_bytes,
+        }
+    }
+}
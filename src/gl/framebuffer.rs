@@ -7,21 +7,23 @@
 use super::texture::Texture;
 use crate::glow;
 
+/// Binds the previous framebuffer back once dropped.
 #[must_use]
 pub struct BindGuard<'a> {
-    gl: &'a glow::Context,
+    gl: &'a dyn glow::Gl,
+    previous: Option<glow::NativeFramebuffer>,
 }
 
 impl<'a> Drop for BindGuard<'a> {
     fn drop(&mut self) {
         let gl = self.gl;
-        gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+        gl.bind_framebuffer(glow::FRAMEBUFFER, self.previous.as_ref());
     }
 }
 
 #[derive(Clone)]
 pub struct Framebuffer<'a> {
-    gl: &'a glow::Context,
+    gl: &'a dyn glow::Gl,
     fbo: glow::NativeFramebuffer,
 }
 
@@ -33,14 +35,14 @@ impl<'a> Drop for Framebuffer<'a> {
 }
 
 impl<'a> Framebuffer<'a> {
-    pub fn new(gl: &'a glow::Context) -> Framebuffer<'a> {
+    pub fn new(gl: &'a dyn glow::Gl) -> Framebuffer<'a> {
         let fbo = gl.create_framebuffer().unwrap();
         Framebuffer { gl, fbo }
     }
 
     pub fn attach_texture(&self, texture: &Texture) {
         let gl = self.gl;
-        let _guard = self.bind();
+        let _guard = self.bind(None);
         gl.framebuffer_texture_2d(
             glow::FRAMEBUFFER,
             glow::COLOR_ATTACHMENT0,
@@ -54,9 +56,120 @@ impl<'a> Framebuffer<'a> {
         );
     }
 
-    pub fn bind(&self) -> BindGuard {
+    pub fn attach_renderbuffer(&self, attachment: u32, renderbuffer: &Renderbuffer) {
+        let gl = self.gl;
+        let _guard = self.bind(None);
+        gl.framebuffer_renderbuffer(
+            glow::FRAMEBUFFER,
+            attachment,
+            glow::RENDERBUFFER,
+            Some(&renderbuffer.renderbuffer),
+        );
+        assert_eq!(
+            gl.check_framebuffer_status(glow::FRAMEBUFFER),
+            glow::FRAMEBUFFER_COMPLETE
+        );
+    }
+
+    /// Renders into this framebuffer until the guard drops, which binds `previous` back.
+    pub fn bind(&self, previous: Option<glow::NativeFramebuffer>) -> BindGuard<'_> {
         let gl = self.gl;
         gl.bind_framebuffer(glow::FRAMEBUFFER, Some(&self.fbo));
-        BindGuard { gl }
+        BindGuard { gl, previous }
+    }
+
+    /// Copies the colors of this framebuffer into `destination`, resolving every sample into a
+    /// single one. Leaves both bound, until a bind guard drops.
+    ///
+    /// Requires GLES 3.
+    pub fn blit_to(&self, destination: &Framebuffer, width: i32, height: i32) {
+        let gl = self.gl;
+        gl.bind_framebuffer(glow::READ_FRAMEBUFFER, Some(&self.fbo));
+        gl.bind_framebuffer(glow::DRAW_FRAMEBUFFER, Some(&destination.fbo));
+        gl.blit_framebuffer(
+            0,
+            0,
+            width,
+            height,
+            0,
+            0,
+            width,
+            height,
+            glow::COLOR_BUFFER_BIT,
+            glow::NEAREST,
+        );
+    }
+}
+
+pub struct Renderbuffer<'a> {
+    gl: &'a dyn glow::Gl,
+    renderbuffer: glow::NativeRenderbuffer,
+}
+
+impl<'a> Drop for Renderbuffer<'a> {
+    fn drop(&mut self) {
+        let gl = self.gl;
+        gl.delete_renderbuffer(Some(&self.renderbuffer));
+    }
+}
+
+impl<'a> Renderbuffer<'a> {
+    pub fn new(gl: &'a dyn glow::Gl) -> Renderbuffer<'a> {
+        let renderbuffer = gl.create_renderbuffer().unwrap();
+        Renderbuffer { gl, renderbuffer }
+    }
+
+    /// Requires GLES 3.
+    pub fn storage_multisample(&self, samples: i32, internalformat: u32, width: i32, height: i32) {
+        let gl = self.gl;
+        gl.bind_renderbuffer(glow::RENDERBUFFER, Some(&self.renderbuffer));
+        gl.renderbuffer_storage_multisample(
+            glow::RENDERBUFFER,
+            samples,
+            internalformat,
+            width,
+            height,
+        );
+    }
+}
+
+/// A framebuffer keeping several samples per pixel, with a stencil buffer, to render
+/// composites with antialiased edges and masks before resolving them into a texture.
+pub struct Multisample<'a> {
+    pub fbo: Framebuffer<'a>,
+    color: Renderbuffer<'a>,
+    depth_stencil: Renderbuffer<'a>,
+    samples: i32,
+}
+
+impl<'a> Multisample<'a> {
+    /// Only on GLES 3, since GLES 2 can’t resolve samples into a texture.
+    pub fn new(gl: &'a dyn glow::Gl, width: i32, height: i32) -> Option<Multisample<'a>> {
+        if !gl.is_gles3() {
+            return None;
+        }
+        let samples = gl.get_parameter_i32(glow::MAX_SAMPLES).min(4);
+        let multisample = Multisample {
+            fbo: Framebuffer::new(gl),
+            color: Renderbuffer::new(gl),
+            depth_stencil: Renderbuffer::new(gl),
+            samples,
+        };
+        // Attachments need their storage to make the framebuffer complete.
+        multisample.resize(width, height);
+        multisample
+            .fbo
+            .attach_renderbuffer(glow::COLOR_ATTACHMENT0, &multisample.color);
+        multisample
+            .fbo
+            .attach_renderbuffer(glow::DEPTH_STENCIL_ATTACHMENT, &multisample.depth_stencil);
+        Some(multisample)
+    }
+
+    pub fn resize(&self, width: i32, height: i32) {
+        self.color
+            .storage_multisample(self.samples, glow::RGBA8, width, height);
+        self.depth_stencil
+            .storage_multisample(self.samples, glow::DEPTH24_STENCIL8, width, height);
     }
 }
@@ -0,0 +1,169 @@
+// Copyright (c) 2023 Emmanuel Gil Peyrot <linkmauve@linkmauve.fr>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Saving the GL state of the application embedding the renderer, to give it back untouched.
+
+use crate::glow;
+
+/// The capabilities the renderer either uses or needs disabled.
+pub const CAPABILITIES: [u32; 5] = [
+    glow::BLEND,
+    glow::STENCIL_TEST,
+    glow::DEPTH_TEST,
+    glow::CULL_FACE,
+    glow::SCISSOR_TEST,
+];
+
+/// The vertex attributes the renderer uses, position, UVs and deform.
+pub const ATTRIBUTES: u32 = 3;
+
+/// Where vertices come from, which on GLES 3 the vertex array holds entirely.
+enum Vertices {
+    VertexArray(Option<glow::NativeVertexArray>),
+    Attributes {
+        element_array_buffer: Option<glow::NativeBuffer>,
+        attributes: Vec<glow::VertexAttrib>,
+    },
+}
+
+/// Every piece of GL state the renderer touches, as the host left it.
+pub struct HostState {
+    capabilities: [bool; 5],
+    blend_func: [u32; 4],
+    blend_equation: [u32; 2],
+    stencil_func: (u32, i32, u32),
+    stencil_op: [u32; 3],
+    stencil_writemask: u32,
+    color_writemask: [bool; 4],
+    clear_color: [f32; 4],
+    viewport: [i32; 4],
+    framebuffer: Option<glow::NativeFramebuffer>,
+    /// Only distinct from the draw framebuffer on GLES 3, where composites get resolved by
+    /// blitting.
+    read_framebuffer: Option<Option<glow::NativeFramebuffer>>,
+    renderbuffer: Option<glow::NativeRenderbuffer>,
+    program: Option<glow::NativeProgram>,
+    active_texture: u32,
+    /// Bound to the first texture unit, the only one the renderer uses.
+    texture: Option<glow::NativeTexture>,
+    array_buffer: Option<glow::NativeBuffer>,
+    vertices: Vertices,
+}
+
+impl HostState {
+    /// Only leaves the first texture unit active, which the renderer sets anyway.
+    pub fn save(gl: &dyn glow::Gl) -> HostState {
+        let get = |pname| gl.get_parameter_i32(pname) as u32;
+        let active_texture = get(glow::ACTIVE_TEXTURE);
+        gl.active_texture(glow::TEXTURE0);
+        let vertices = if gl.is_gles3() {
+            Vertices::VertexArray(gl.get_parameter_vertex_array())
+        } else {
+            Vertices::Attributes {
+                element_array_buffer: gl.get_parameter_buffer(glow::ELEMENT_ARRAY_BUFFER_BINDING),
+                attributes: (0..ATTRIBUTES)
+                    .map(|index| gl.get_vertex_attrib_state(index))
+                    .collect(),
+            }
+        };
+        HostState {
+            capabilities: CAPABILITIES.map(|cap| gl.is_enabled(cap)),
+            blend_func: [
+                glow::BLEND_SRC_RGB,
+                glow::BLEND_DST_RGB,
+                glow::BLEND_SRC_ALPHA,
+                glow::BLEND_DST_ALPHA,
+            ]
+            .map(get),
+            blend_equation: [glow::BLEND_EQUATION_RGB, glow::BLEND_EQUATION_ALPHA].map(get),
+            stencil_func: (
+                get(glow::STENCIL_FUNC),
+                gl.get_parameter_i32(glow::STENCIL_REF),
+                get(glow::STENCIL_VALUE_MASK),
+            ),
+            stencil_op: [
+                glow::STENCIL_FAIL,
+                glow::STENCIL_PASS_DEPTH_FAIL,
+                glow::STENCIL_PASS_DEPTH_PASS,
+            ]
+            .map(get),
+            stencil_writemask: get(glow::STENCIL_WRITEMASK),
+            color_writemask: gl.get_parameter_color_writemask(),
+            clear_color: gl.get_parameter_color_clear_value(),
+            viewport: gl.get_parameter_viewport(),
+            framebuffer: gl.get_parameter_framebuffer(),
+            read_framebuffer: gl.is_gles3().then(|| gl.get_parameter_read_framebuffer()),
+            renderbuffer: gl.get_parameter_renderbuffer(),
+            program: gl.get_parameter_program(),
+            active_texture,
+            texture: gl.get_parameter_texture_binding_2d(),
+            array_buffer: gl.get_parameter_buffer(glow::ARRAY_BUFFER_BINDING),
+            vertices,
+        }
+    }
+
+    pub fn restore(&self, gl: &dyn glow::Gl) {
+        for (cap, enabled) in CAPABILITIES.into_iter().zip(self.capabilities) {
+            if enabled {
+                gl.enable(cap);
+            } else {
+                gl.disable(cap);
+            }
+        }
+        let [src_rgb, dst_rgb, src_alpha, dst_alpha] = self.blend_func;
+        gl.blend_func_separate(src_rgb, dst_rgb, src_alpha, dst_alpha);
+        let [mode_rgb, mode_alpha] = self.blend_equation;
+        gl.blend_equation_separate(mode_rgb, mode_alpha);
+        let (func, ref_, mask) = self.stencil_func;
+        gl.stencil_func(func, ref_, mask);
+        let [fail, zfail, zpass] = self.stencil_op;
+        gl.stencil_op(fail, zfail, zpass);
+        gl.stencil_mask(self.stencil_writemask);
+        let [r, g, b, a] = self.color_writemask;
+        gl.color_mask(r, g, b, a);
+        let [r, g, b, a] = self.clear_color;
+        gl.clear_color(r, g, b, a);
+        let [x, y, width, height] = self.viewport;
+        gl.viewport(x, y, width, height);
+        gl.bind_framebuffer(glow::FRAMEBUFFER, self.framebuffer.as_ref());
+        if let Some(read_framebuffer) = &self.read_framebuffer {
+            gl.bind_framebuffer(glow::READ_FRAMEBUFFER, read_framebuffer.as_ref());
+        }
+        gl.bind_renderbuffer(glow::RENDERBUFFER, self.renderbuffer.as_ref());
+        gl.use_program(self.program.as_ref());
+        gl.active_texture(glow::TEXTURE0);
+        gl.bind_texture(glow::TEXTURE_2D, self.texture.as_ref());
+        gl.active_texture(self.active_texture);
+
+        match &self.vertices {
+            Vertices::VertexArray(vao) => gl.bind_vertex_array(vao.as_ref()),
+            Vertices::Attributes {
+                element_array_buffer,
+                attributes,
+            } => {
+                gl.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, element_array_buffer.as_ref());
+                for (index, attribute) in (0..).zip(attributes) {
+                    gl.bind_buffer(glow::ARRAY_BUFFER, attribute.buffer.as_ref());
+                    gl.vertex_attrib_pointer_with_i32(
+                        index,
+                        attribute.size,
+                        attribute.type_,
+                        attribute.normalized,
+                        attribute.stride,
+                        attribute.offset,
+                    );
+                    if attribute.enabled {
+                        gl.enable_vertex_attrib_array(index);
+                    } else {
+                        gl.disable_vertex_attrib_array(index);
+                    }
+                }
+            }
+        }
+        // Last, since restoring the attributes binds their buffers.
+        gl.bind_buffer(glow::ARRAY_BUFFER, self.array_buffer.as_ref());
+    }
+}
@@ -6,12 +6,19 @@
 
 use crate::glow;
 use crate::ParamValues;
-use crate::{Anim, BlendMode, Mask, Node, Texture, TextureReceiver, Transform};
-use std::cell::RefCell;
-use std::collections::BTreeMap;
+use crate::{
+    AlphaMode, Anim, BlendMode, Mask, Node, PixelFormat, Texture, TextureReceiver, TextureSupport,
+    Transform,
+};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::ops::Range;
+use std::sync::mpsc;
+use std::time::Duration;
 
 mod vbo;
-use vbo::Vbo;
+use vbo::{Ibo, Vbo, VertexArray};
 
 mod program;
 use program::Program;
@@ -20,19 +27,29 @@ mod texture;
 use texture::Texture as GlTexture;
 
 mod framebuffer;
-use framebuffer::Framebuffer;
+use framebuffer::{Framebuffer, Multisample};
+
+mod host;
+use host::HostState;
+
+mod timer;
+use timer::Timer;
+
+#[cfg(feature = "parallel")]
+mod workers;
+#[cfg(feature = "parallel")]
+use workers::Workers;
 
 const VERTEX: &str = "#version 100
 precision mediump float;
 uniform float ratio;
-uniform vec2 trans;
 attribute vec2 pos;
 attribute vec2 uvs;
 attribute vec2 deform;
 varying vec2 texcoord;
 
 void main() {
-    vec2 pos2 = pos + trans + deform;
+    vec2 pos2 = pos + deform;
     texcoord = vec2(uvs.x, -uvs.y);
     gl_Position = vec4(pos2.x * ratio / 2560.0, -pos2.y / 2560.0, 0.0, 1.0);
 }
@@ -41,6 +58,7 @@ void main() {
 const FRAGMENT: &str = "#version 100
 precision mediump float;
 uniform sampler2D texture;
+uniform float straight_alpha;
 varying vec2 texcoord;
 
 void main() {
@@ -48,7 +66,8 @@ void main() {
     if (color.a < 0.05) {
         discard;
     }
-    gl_FragColor = color.bgra;
+    color.rgb *= mix(1.0, color.a, straight_alpha);
+    gl_FragColor = color;
 }
 ";
 
@@ -74,74 +93,231 @@ void main() {
 }
 ";
 
+// The same shaders for GLES 3, with explicit attribute locations.
+
+const VERTEX_ES3: &str = "#version 300 es
+precision mediump float;
+uniform float ratio;
+layout(location = 0) in vec2 pos;
+layout(location = 1) in vec2 uvs;
+layout(location = 2) in vec2 deform;
+out vec2 texcoord;
+
+void main() {
+    vec2 pos2 = pos + deform;
+    texcoord = vec2(uvs.x, -uvs.y);
+    gl_Position = vec4(pos2.x * ratio / 2560.0, -pos2.y / 2560.0, 0.0, 1.0);
+}
+";
+
+const FRAGMENT_ES3: &str = "#version 300 es
+precision mediump float;
+uniform sampler2D tex;
+uniform float straight_alpha;
+in vec2 texcoord;
+out vec4 frag_color;
+
+void main() {
+    vec4 color = texture(tex, texcoord);
+    if (color.a < 0.05) {
+        discard;
+    }
+    color.rgb *= mix(1.0, color.a, straight_alpha);
+    frag_color = color;
+}
+";
+
+const VERTEX_PASSTHROUGH_ES3: &str = "#version 300 es
+precision mediump float;
+layout(location = 0) in vec2 pos;
+
+void main() {
+    gl_Position = vec4(pos, 0.0, 1.0);
+}
+";
+
+/// Fetches the exact texel of the composite under each pixel, instead of filtering it.
+///
+/// The composite starts at the origin of the viewport, not of the window.
+const FRAGMENT_PASSTHROUGH_ES3: &str = "#version 300 es
+precision mediump float;
+uniform sampler2D tex;
+uniform vec2 offset;
+out vec4 frag_color;
+
+void main() {
+    frag_color = texelFetch(tex, ivec2(gl_FragCoord.xy - offset), 0);
+}
+";
+
 struct Locations {
     ratio: Option<glow::NativeUniformLocation>,
-    trans: Option<glow::NativeUniformLocation>,
+    straight_alpha: Option<glow::NativeUniformLocation>,
+    /// Only in the GLES 3 composite program.
+    composite_offset: Option<glow::NativeUniformLocation>,
 }
 
+/// The GL state last set, `None` when unknown, to skip redundant calls.
 struct MutableStuff {
     prev_program: Option<glow::NativeProgram>,
-    prev_stencil: bool,
+    prev_stencil: Option<bool>,
     prev_blend_mode: Option<(u32, u32)>,
     prev_texture: Option<glow::NativeTexture>,
+    prev_straight_alpha: Option<bool>,
     prev_masks: Vec<Mask>,
+    prev_base_vertex: Option<u32>,
+    stats: FrameStats,
+}
+
+impl MutableStuff {
+    /// Forgets the state, for when something else may have changed it.
+    fn invalidate(&mut self) {
+        self.prev_program = None;
+        self.prev_stencil = None;
+        self.prev_blend_mode = None;
+        self.prev_texture = None;
+        self.prev_straight_alpha = None;
+        // Only parts with masks look at them, so none matches.
+        self.prev_masks.clear();
+        self.prev_base_vertex = None;
+    }
+}
+
+/// Counters for the work done by a [`GlRenderer`] since the last
+/// [`take_stats`](GlRenderer::take_stats), to find out why a puppet renders slowly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Parts drawn, not counting the mask sources.
+    pub parts: u32,
+    pub draw_calls: u32,
+    pub program_changes: u32,
+    pub texture_changes: u32,
+    pub blend_changes: u32,
+    /// Enabling or disabling the stencil test.
+    pub stencil_changes: u32,
+    /// Times the stencil buffer got cleared and the mask sources drawn into it.
+    pub mask_recomputations: u32,
+    /// Composites drawn into their framebuffer.
+    pub composite_passes: u32,
+    /// Vertices of the parts evaluated by [`animate`](GlRenderer::animate).
+    pub vertices_deformed: u32,
+    /// Deforms and textures sent to the GPU.
+    pub bytes_uploaded: u64,
+    /// Spent in [`animate`](GlRenderer::animate), only measured once enabled with
+    /// [`set_profiling`](GlRenderer::set_profiling).
+    pub animate_time: Duration,
+    /// Spent in [`render_nodes`](GlRenderer::render_nodes), on the CPU side only.
+    pub render_time: Duration,
 }
 
 pub struct GlRenderer<'a> {
-    gl: &'a glow::Context,
+    gl: &'a dyn glow::Gl,
     nodes: BTreeMap<u32, EnumNode>,
     mutable: RefCell<MutableStuff>,
-    current_ibo_offset: u16,
+    current_ibo_offset: u32,
+    /// First vertex of the batch parts currently get added to, when 16-bit indices can’t
+    /// address every vertex of the puppet.
+    base_vertex: u32,
     verts: Vbo<'a, f32>,
     uvs: Vbo<'a, f32>,
     deform: Vbo<'a, f32>,
-    ibo: Vbo<'a, u16>,
-    textures: Vec<GlTexture<'a>>,
+    /// What the deform buffer contains, updated by [`animate`](Self::animate) and then
+    /// uploaded in as few calls as possible.
+    deform_mirror: Vec<f32>,
+    /// The ranges of the mirror which changed since the last upload.
+    dirty_deforms: Vec<Range<usize>>,
+    /// The indices of every part, relative to its base vertex, until they get laid out in draw
+    /// order into the index buffer.
+    indices: Vec<u32>,
+    ibo: Ibo<'a>,
+    vao: Option<VertexArray<'a>>,
+    textures: Vec<Option<GlTexture<'a>>>,
+    /// Drawn instead of the textures which haven’t arrived yet.
+    placeholder: GlTexture<'a>,
+    pending_textures: Option<mpsc::Receiver<(usize, io::Result<Texture>)>>,
     part_program: Program<'a>,
     locations: Locations,
     composite_program: Program<'a>,
     composite_fbo: Framebuffer<'a>,
     composite_texture: GlTexture<'a>,
+    /// Where composites get drawn before being resolved into their texture, on GLES 3.
+    msaa: Option<Multisample<'a>>,
+    bindings: Option<BindingTable>,
+    /// Started the first time a frame has enough parts to animate.
+    #[cfg(feature = "parallel")]
+    workers: Option<Workers>,
+    /// Whether to measure the time spent animating and rendering.
+    profiling: bool,
+    /// The framebuffer to render into, `None` being the default one.
+    target: Option<glow::NativeFramebuffer>,
+    /// The rectangle of the target to render into, as x, y, width and height.
+    viewport: [i32; 4],
+    /// Whether the target changed since the context last got set up for it.
+    target_changed: Cell<bool>,
+    /// Saved by [`begin_frame`](Self::begin_frame), until [`end_frame`](Self::end_frame)
+    /// restores it.
+    host_state: Option<HostState>,
 }
 
 impl<'a> GlRenderer<'a> {
-    fn new(gl: &'a glow::Context, width: u32, height: u32) -> Result<GlRenderer, String> {
+    fn new(gl: &'a dyn glow::Gl, width: u32, height: u32) -> Result<GlRenderer<'a>, String> {
+        let gles3 = gl.is_gles3();
+        let shaders = if gles3 {
+            [VERTEX_ES3, FRAGMENT_ES3, VERTEX_PASSTHROUGH_ES3, FRAGMENT_PASSTHROUGH_ES3]
+        } else {
+            [VERTEX, FRAGMENT, VERTEX_PASSTHROUGH, FRAGMENT_PASSTHROUGH]
+        };
         let part_program = Program::builder(gl)?
-            .shader(glow::VERTEX_SHADER, VERTEX)?
-            .shader(glow::FRAGMENT_SHADER, FRAGMENT)?
+            .shader(glow::VERTEX_SHADER, shaders[0])?
+            .shader(glow::FRAGMENT_SHADER, shaders[1])?
+            .link()?;
+        let composite_program = Program::builder(gl)?
+            .shader(glow::VERTEX_SHADER, shaders[2])?
+            .shader(glow::FRAGMENT_SHADER, shaders[3])?
             .link()?;
         let locations = Locations {
             ratio: part_program.get_uniform_location("ratio"),
-            trans: part_program.get_uniform_location("trans"),
+            straight_alpha: part_program.get_uniform_location("straight_alpha"),
+            composite_offset: if gles3 {
+                composite_program.get_uniform_location("offset")
+            } else {
+                None
+            },
         };
 
         part_program.use_();
         gl.uniform1f(locations.ratio.as_ref(), height as f32 / width as f32);
 
-        let composite_program = Program::builder(gl)?
-            .shader(glow::VERTEX_SHADER, VERTEX_PASSTHROUGH)?
-            .shader(glow::FRAGMENT_SHADER, FRAGMENT_PASSTHROUGH)?
-            .link()?;
+        // Keeps the vertex attributes and the index buffer together, on GLES 3.
+        let vao = VertexArray::new(gl);
+        if let Some(vao) = &vao {
+            vao.bind();
+        }
 
         let verts = Vbo::from(gl, vec![-1., -1., -1., 1., 1., -1., 1., 1.]);
         let uvs = Vbo::from(gl, vec![0., 0., 0., 1., 1., 0., 1., 1.]);
         let deform = Vbo::from(gl, vec![0., 0., 0., 0., 0., 0., 0., 0.]);
-        let ibo = Vbo::new(gl);
+        let ibo = Ibo::new(gl, supports_uint_indices(gl));
 
         gl.clear_color(0.0, 0.0, 0.0, 0.0);
         gl.enable(glow::BLEND);
         gl.stencil_mask(0xff);
 
         let composite_texture = GlTexture::from_data(gl, width, height, None)?;
+        let placeholder = GlTexture::from_data(gl, 1, 1, Some(&[0, 0, 0, 0]))?;
         let composite_fbo = Framebuffer::new(gl);
         composite_fbo.attach_texture(&composite_texture);
+        let msaa = Multisample::new(gl, width as i32, height as i32);
 
         let mutable = RefCell::new(MutableStuff {
             prev_program: None,
-            prev_stencil: false,
+            prev_stencil: Some(false),
             prev_blend_mode: None,
             prev_texture: None,
+            prev_straight_alpha: None,
             prev_masks: Vec::new(),
+            prev_base_vertex: Some(0),
+            stats: FrameStats::default(),
         });
 
         let nodes = BTreeMap::new();
@@ -150,25 +326,122 @@ impl<'a> GlRenderer<'a> {
             nodes,
             mutable,
             current_ibo_offset: 4,
+            base_vertex: 0,
             verts,
             uvs,
             deform,
+            deform_mirror: Vec::new(),
+            dirty_deforms: Vec::new(),
+            indices: Vec::new(),
             ibo,
+            vao,
             locations,
             textures: Vec::new(),
+            placeholder,
+            pending_textures: None,
             part_program,
             composite_program,
             composite_texture,
             composite_fbo,
+            msaa,
+            bindings: None,
+            #[cfg(feature = "parallel")]
+            workers: None,
+            profiling: false,
+            target: None,
+            viewport: [0, 0, width as i32, height as i32],
+            target_changed: Cell::new(false),
+            host_state: None,
         })
     }
 
     pub fn set_size(&mut self, width: i32, height: i32) {
+        self.set_target(self.target.clone(), [0, 0, width, height]);
+    }
+
+    /// Renders into this framebuffer, `None` being the default one, within this rectangle of
+    /// it given as x, y, width and height, such as a widget of the UI of a game.
+    ///
+    /// This doesn’t touch the context, so it can get called outside of a frame: the target gets
+    /// set up by the next [`begin_frame`](Self::begin_frame), or the next
+    /// [`clear`](Self::clear) or [`render_nodes`](Self::render_nodes) when the host doesn’t
+    /// share the context.
+    pub fn set_target(
+        &mut self,
+        framebuffer: Option<glow::NativeFramebuffer>,
+        viewport: [i32; 4],
+    ) {
+        self.target = framebuffer;
+        self.viewport = viewport;
+        self.target_changed.set(true);
+    }
+
+    /// Sets the context up for the target passed to [`set_target`](Self::set_target), if it
+    /// changed since the last time.
+    fn apply_target(&self) {
+        if !self.target_changed.replace(false) {
+            return;
+        }
         let gl = self.gl;
-        gl.viewport(0, 0, width, height);
+        let [x, y, width, height] = self.viewport;
+        gl.bind_framebuffer(glow::FRAMEBUFFER, self.target.as_ref());
+        gl.viewport(x, y, width, height);
+        self.use_program(&self.part_program);
         gl.uniform1f(self.locations.ratio.as_ref(), height as f32 / width as f32);
+        if let Some(offset) = &self.locations.composite_offset {
+            self.use_program(&self.composite_program);
+            gl.uniform2f(Some(offset), x as f32, y as f32);
+        }
         self.bind_texture(&self.composite_texture);
         self.composite_texture.resize(width, height);
+        if let Some(msaa) = &self.msaa {
+            msaa.resize(width, height);
+        }
+    }
+
+    /// Saves every piece of GL state the renderer touches and sets up its own, to share the
+    /// context with a host application which also renders with it.
+    ///
+    /// Whatever got cached from the previous frame is forgotten, since the host may have
+    /// changed anything in between. Every call using the context, such as
+    /// [`poll_textures`](Self::poll_textures), [`render_nodes`](Self::render_nodes) or
+    /// [`clear`](Self::clear), then has to happen before [`end_frame`](Self::end_frame).
+    pub fn begin_frame(&mut self) {
+        let gl = self.gl;
+        self.host_state = Some(HostState::save(gl));
+        self.mutable.get_mut().invalidate();
+
+        gl.bind_framebuffer(glow::FRAMEBUFFER, self.target.as_ref());
+        let [x, y, width, height] = self.viewport;
+        gl.viewport(x, y, width, height);
+        for cap in [glow::DEPTH_TEST, glow::CULL_FACE, glow::SCISSOR_TEST] {
+            gl.disable(cap);
+        }
+        gl.enable(glow::BLEND);
+        gl.blend_equation_separate(glow::FUNC_ADD, glow::FUNC_ADD);
+        gl.stencil_mask(0xff);
+        gl.color_mask(true, true, true, true);
+        gl.clear_color(0.0, 0.0, 0.0, 0.0);
+        gl.active_texture(glow::TEXTURE0);
+        match &self.vao {
+            Some(vao) => vao.bind(),
+            None => {
+                for index in 0..host::ATTRIBUTES {
+                    gl.enable_vertex_attrib_array(index);
+                }
+                self.ibo.bind(gl);
+            }
+        }
+        self.apply_target();
+    }
+
+    /// Restores the GL state saved by [`begin_frame`](Self::begin_frame), handing the context
+    /// back to the host.
+    pub fn end_frame(&mut self) {
+        if let Some(host_state) = self.host_state.take() {
+            host_state.restore(self.gl);
+        }
+        self.mutable.get_mut().invalidate();
     }
 
     fn flatten_nodes(&mut self, node: &Node, parent: Option<u32>) {
@@ -205,39 +478,50 @@ impl<'a> GlRenderer<'a> {
                 let num_verts = mesh.verts.len();
                 assert_eq!(num_verts, mesh.uvs.len());
 
-                let start_index = self.ibo.len() as u16 * 2;
-                let num_indices = mesh.indices.len() as u16;
-                let start_deform = self.current_ibo_offset * 2;
-                let deform = vec![0.; num_verts];
+                let num_vertices = (num_verts / 2) as u32;
+                if !self.ibo.is_wide()
+                    && self.current_ibo_offset + num_vertices - self.base_vertex > 0x10000
+                {
+                    // 16-bit indices can’t reach that far, start a new batch with this part.
+                    self.base_vertex = self.current_ibo_offset;
+                }
+
+                let indices = self.indices.len()..self.indices.len() + mesh.indices.len();
+                let start_deform = self.current_ibo_offset as usize * 2;
                 self.verts.extend_from_slice(mesh.verts.as_slice());
                 self.uvs.extend_from_slice(mesh.uvs.as_slice());
-                self.deform.extend_from_slice(deform.as_slice());
-                self.ibo.extend(
-                    mesh.indices
-                        .iter()
-                        .map(|index| index + self.current_ibo_offset),
-                );
-                self.current_ibo_offset += (num_verts / 2) as u16;
+                let offset = self.current_ibo_offset - self.base_vertex;
+                self.indices
+                    .extend(mesh.indices.iter().map(|&index| index as u32 + offset));
+                self.current_ibo_offset += num_vertices;
 
                 let parent = parent.unwrap();
                 let transform = transform.clone();
                 let masks = masks.clone();
                 let blend_mode = blend_mode.into();
 
-                let part = Part {
-                    start_index,
-                    num_indices,
-                    start_deform,
+                let mut part = Part {
+                    indices,
+                    start_index: 0,
+                    deform: start_deform..start_deform + num_verts,
+                    base_vertex: self.base_vertex,
                     transform,
                     blend_mode,
                     textures,
                     parent,
                     masks,
-                    anim: Vec::new(),
-                    deform,
+                    bindings: Vec::new(),
+                    origin: [0.; 2],
+                    translation: [0.; 2],
                     #[cfg(feature = "debug")]
                     name: name.clone(),
                 };
+                // The parents are already there, and nothing animates them yet, so their
+                // translation can be baked in the deform buffer once and for all.
+                let [x, y, _] = part.trans(self);
+                part.origin = [x, y];
+                self.deform
+                    .extend((0..num_verts / 2).flat_map(|_| part.origin));
                 self.push(uuid, EnumNode::Part(part));
                 for child in children.iter() {
                     self.flatten_nodes(child, Some(uuid));
@@ -274,26 +558,176 @@ impl<'a> GlRenderer<'a> {
     }
 
     fn load_texture(&self, tex: &Texture) -> Result<GlTexture<'a>, String> {
-        match tex {
+        let (mut texture, alpha) = match tex {
             Texture::Rgba {
                 width,
                 height,
+                format,
+                alpha,
                 data,
-            } => GlTexture::from_data(self.gl, *width, *height, Some(data)),
+            } => {
+                // GLES 2 can’t upload BGRA without an extension, and premultiplying is cheaper
+                // here than on every sample.
+                if (*format, *alpha) != (PixelFormat::Rgba8, AlphaMode::Premultiplied) {
+                    let mut normalized = Texture::Rgba {
+                        width: *width,
+                        height: *height,
+                        format: *format,
+                        alpha: *alpha,
+                        data: data.clone(),
+                    };
+                    normalized.normalize();
+                    return self.load_texture(&normalized);
+                }
+                (
+                    GlTexture::from_data(self.gl, *width, *height, Some(data))?,
+                    *alpha,
+                )
+            }
+            Texture::Bc7 {
+                width,
+                height,
+                alpha,
+                data,
+            } => (
+                GlTexture::from_compressed_data(
+                    self.gl,
+                    glow::COMPRESSED_RGBA_BPTC_UNORM_EXT,
+                    *width,
+                    *height,
+                    data,
+                ),
+                *alpha,
+            ),
+            Texture::Astc {
+                block_width,
+                block_height,
+                width,
+                height,
+                alpha,
+                data,
+            } => {
+                let index = crate::astc::BLOCK_SIZES
+                    .iter()
+                    .position(|&size| size == (*block_width, *block_height))
+                    .ok_or_else(|| {
+                        format!("Unsupported ASTC block size {block_width}×{block_height}")
+                    })?;
+                (
+                    GlTexture::from_compressed_data(
+                        self.gl,
+                        glow::COMPRESSED_RGBA_ASTC_4X4_KHR + index as u32,
+                        *width,
+                        *height,
+                        data,
+                    ),
+                    *alpha,
+                )
+            }
+        };
+        texture.straight_alpha = alpha == AlphaMode::Straight;
+        Ok(texture)
+    }
+
+    fn receive_texture(&mut self, i: usize, tex: io::Result<Texture>) -> Result<(), String> {
+        let tex = tex.map_err(|err| format!("Failed to decode texture {i}: {err}"))?;
+        let texture = self.load_texture(&tex)?;
+        let (Texture::Rgba { data, .. } | Texture::Bc7 { data, .. } | Texture::Astc { data, .. }) =
+            &tex;
+        self.mutable.get_mut().stats.bytes_uploaded += data.len() as u64;
+        if i >= self.textures.len() {
+            self.textures.resize_with(i + 1, || None);
         }
+        self.textures[i] = Some(texture);
+        Ok(())
     }
 
     fn upload_textures(&mut self, (num_textures, rx): TextureReceiver) -> Result<(), String> {
-        let mut vec = vec![None; num_textures];
+        self.textures = vec![None; num_textures];
         while let Ok((i, tex)) = rx.recv() {
-            let texture = self.load_texture(&tex)?;
-            vec[i] = Some(texture);
+            self.receive_texture(i, tex)?;
         }
-        self.textures = vec.into_iter().map(Option::unwrap).collect();
         Ok(())
     }
 
-    fn upload_buffers(&mut self) {
+    /// Replaces every texture with a transparent placeholder, until the real one arrives on
+    /// this channel and gets uploaded by [`poll_textures`](Self::poll_textures).
+    pub fn set_textures(&mut self, (num_textures, rx): TextureReceiver) {
+        self.textures = vec![None; num_textures];
+        self.pending_textures = Some(rx);
+    }
+
+    /// Uploads the textures which have been decoded since the last call, without waiting for
+    /// the others, and returns whether every texture has arrived.
+    ///
+    /// Meant to be called once per frame, so that parts appear as soon as their texture is
+    /// ready.
+    pub fn poll_textures(&mut self) -> Result<bool, String> {
+        let Some(rx) = self.pending_textures.take() else {
+            return Ok(true);
+        };
+        loop {
+            match rx.try_recv() {
+                Ok((i, tex)) => {
+                    if let Err(err) = self.receive_texture(i, tex) {
+                        // Keep the other textures coming.
+                        self.pending_textures = Some(rx);
+                        return Err(err);
+                    }
+                }
+                Err(mpsc::TryRecvError::Empty) => {
+                    self.pending_textures = Some(rx);
+                    return Ok(false);
+                }
+                Err(mpsc::TryRecvError::Disconnected) => return Ok(true),
+            }
+        }
+    }
+
+    fn texture(&self, index: usize) -> &GlTexture<'a> {
+        match self.textures.get(index) {
+            Some(Some(texture)) => texture,
+            _ => &self.placeholder,
+        }
+    }
+
+    /// Lists the parts in the order they get drawn, including those inside composites.
+    fn collect_parts(&self, order: &[u32], parts: &mut Vec<u32>) {
+        for &uuid in order {
+            match self.get(uuid) {
+                Some(EnumNode::Part(_)) => parts.push(uuid),
+                Some(EnumNode::Composite(composite)) => {
+                    self.collect_parts(&composite.children, parts)
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Fills the index buffer with the indices of each part in this draw order, so that
+    /// consecutive parts sharing the same state can be drawn in a single call.
+    fn layout_indices(&mut self, order: &[u32]) {
+        let mut parts = Vec::new();
+        self.collect_parts(order, &mut parts);
+        // Any part missing from the order still needs its indices somewhere.
+        parts.extend(self.nodes.keys().copied());
+
+        let mut placed = BTreeSet::new();
+        for uuid in parts {
+            if !placed.insert(uuid) {
+                continue;
+            }
+            if let Some(EnumNode::Part(part)) = self.nodes.get_mut(&uuid) {
+                part.start_index = self.ibo.len() * self.ibo.index_size();
+                self.ibo
+                    .extend(self.indices[part.indices.clone()].iter().copied());
+            }
+        }
+        self.indices = Vec::new();
+    }
+
+    fn upload_buffers(&mut self, order: &[u32]) {
+        self.layout_indices(order);
         let gl = &self.gl;
 
         self.verts.upload(glow::ARRAY_BUFFER, glow::STATIC_DRAW);
@@ -304,12 +738,27 @@ impl<'a> GlRenderer<'a> {
         gl.vertex_attrib_pointer_with_i32(1, 2, glow::FLOAT, false, 8, 0);
         gl.enable_vertex_attrib_array(1);
 
+        self.deform_mirror = self.deform.as_slice().to_vec();
         self.deform.upload(glow::ARRAY_BUFFER, glow::DYNAMIC_DRAW);
         gl.vertex_attrib_pointer_with_i32(2, 2, glow::FLOAT, false, 8, 0);
         gl.enable_vertex_attrib_array(2);
 
-        self.ibo
-            .upload(glow::ELEMENT_ARRAY_BUFFER, glow::STATIC_DRAW);
+        self.ibo.upload(glow::STATIC_DRAW);
+    }
+
+    /// Points the vertex attributes at this vertex, which 16-bit indices are relative to.
+    fn set_base_vertex(&self, base_vertex: u32) {
+        let prev = &mut self.mutable.borrow_mut().prev_base_vertex;
+        if *prev == Some(base_vertex) {
+            return;
+        }
+        let gl = self.gl;
+        let offset = base_vertex as i32 * 8;
+        for (index, vbo) in [&self.verts, &self.uvs, &self.deform].into_iter().enumerate() {
+            vbo.bind(gl, glow::ARRAY_BUFFER);
+            gl.vertex_attrib_pointer_with_i32(index as u32, 2, glow::FLOAT, false, 8, offset);
+        }
+        *prev = Some(base_vertex);
     }
 
     fn push(&mut self, uuid: u32, buf: EnumNode) {
@@ -325,8 +774,8 @@ impl<'a> GlRenderer<'a> {
     }
 
     fn set_stencil(&self, stencil: bool) {
-        let prev = &mut self.mutable.borrow_mut().prev_stencil;
-        if *prev == stencil {
+        let mut mutable = self.mutable.borrow_mut();
+        if mutable.prev_stencil == Some(stencil) {
             return;
         }
         let gl = &self.gl;
@@ -335,41 +784,58 @@ impl<'a> GlRenderer<'a> {
         } else {
             gl.disable(glow::STENCIL_TEST);
         }
-        *prev = stencil;
+        mutable.prev_stencil = Some(stencil);
+        mutable.stats.stencil_changes += 1;
     }
 
     fn use_program(&self, program: &Program) {
-        let prev = &mut self.mutable.borrow_mut().prev_program;
-        if *prev == Some(program.program.clone()) {
+        let mut mutable = self.mutable.borrow_mut();
+        if mutable.prev_program == Some(program.program.clone()) {
             return;
         }
         program.use_();
-        *prev = Some(program.program.clone());
+        mutable.prev_program = Some(program.program.clone());
+        mutable.stats.program_changes += 1;
     }
 
     fn bind_texture(&self, texture: &GlTexture) {
-        let prev = &mut self.mutable.borrow_mut().prev_texture;
-        if *prev == Some(texture.texture.clone()) {
+        let mut mutable = self.mutable.borrow_mut();
+        if mutable.prev_texture == Some(texture.texture.clone()) {
             return;
         }
         texture.bind();
-        *prev = Some(texture.texture.clone());
+        mutable.prev_texture = Some(texture.texture.clone());
+        mutable.stats.texture_changes += 1;
+    }
+
+    /// Only valid while the part program is in use.
+    fn set_straight_alpha(&self, straight_alpha: bool) {
+        let prev = &mut self.mutable.borrow_mut().prev_straight_alpha;
+        if *prev == Some(straight_alpha) {
+            return;
+        }
+        let value = if straight_alpha { 1. } else { 0. };
+        self.gl
+            .uniform1f(self.locations.straight_alpha.as_ref(), value);
+        *prev = Some(straight_alpha);
     }
 
     fn set_blend_mode(&self, mode: (u32, u32)) {
-        let prev = &mut self.mutable.borrow_mut().prev_blend_mode;
-        if *prev == Some(mode) {
+        let mut mutable = self.mutable.borrow_mut();
+        if mutable.prev_blend_mode == Some(mode) {
             return;
         }
         let gl = &self.gl;
         gl.blend_func(mode.0, mode.1);
-        *prev = Some(mode);
+        mutable.prev_blend_mode = Some(mode);
+        mutable.stats.blend_changes += 1;
     }
 
     fn recompute_masks(&self, part: &Part) {
         if self.mutable.borrow().prev_masks == part.masks {
             return;
         }
+        self.mutable.borrow_mut().stats.mask_recomputations += 1;
 
         let gl = &self.gl;
         self.set_stencil(true);
@@ -379,7 +845,7 @@ impl<'a> GlRenderer<'a> {
         gl.clear(glow::STENCIL_BUFFER_BIT);
         for mask in part.masks.iter() {
             match self.get(mask.source).unwrap() {
-                EnumNode::Part(part) => self.render_part(part),
+                EnumNode::Part(part) => self.draw_part(part, part.indices.len()),
                 _ => panic!("Only parts allowed in masks, for now."),
             }
         }
@@ -390,126 +856,384 @@ impl<'a> GlRenderer<'a> {
         self.mutable.borrow_mut().prev_masks = part.masks.clone();
     }
 
-    fn render_part(&self, part: &Part) {
+    /// Draws this many indices from the start of this part, with its texture and blend mode
+    /// but whichever stencil state is current.
+    fn draw_part(&self, part: &Part, num_indices: usize) {
         self.use_program(&self.part_program);
 
-        if !part.masks.is_empty() {
-            self.recompute_masks(part);
-        }
-
-        let trans = part.trans(self);
-
         let gl = &self.gl;
-        self.bind_texture(&self.textures[part.textures[0]]);
+        let texture = self.texture(part.textures[0]);
+        self.bind_texture(texture);
+        self.set_straight_alpha(texture.straight_alpha);
         self.set_blend_mode(part.blend_mode);
-        let mut tx = 0.;
-        let mut ty = 0.;
-        for anim in part.anim.iter() {
-            match anim {
-                Anim::TransformTX(f) => tx += f,
-                Anim::TransformTY(f) => ty += f,
-                _ => (),
-            }
-        }
-        gl.uniform2f(self.locations.trans.as_ref(), trans[0] + tx, trans[1] + ty);
 
+        self.set_base_vertex(part.base_vertex);
         gl.draw_elements_with_i32(
             glow::TRIANGLES,
-            part.num_indices as i32,
-            glow::UNSIGNED_SHORT,
+            num_indices as i32,
+            self.ibo.index_type(),
             part.start_index as i32,
         );
+        self.mutable.borrow_mut().stats.draw_calls += 1;
+    }
+
+    fn render_batch(&self, batch: &Batch) {
+        let part = batch.first;
+        #[cfg(feature = "debug")]
+        self.gl
+            .push_debug_group(glow::DEBUG_SOURCE_APPLICATION, 0, &part.name);
+        if part.masks.is_empty() {
+            self.set_stencil(false);
+        } else {
+            self.recompute_masks(part);
+            self.set_stencil(true);
+        }
+        self.draw_part(part, batch.num_indices);
+        #[cfg(feature = "debug")]
+        self.gl.pop_debug_group();
     }
 
     fn render_composite(&self, composite: &Composite) {
         let gl = &self.gl;
+        // The composite texture is only as large as the viewport.
+        let [x, y, width, height] = self.viewport;
+        let offset = (x, y) != (0, 0);
         {
-            let _guard = self.composite_fbo.bind();
+            let fbo = match &self.msaa {
+                Some(msaa) => &msaa.fbo,
+                None => &self.composite_fbo,
+            };
+            let _guard = fbo.bind(self.target.clone());
+            // The masks in the stencil buffer of the target don’t apply to another framebuffer.
+            self.mutable.borrow_mut().prev_masks.clear();
+            if offset {
+                gl.viewport(0, 0, width, height);
+            }
             gl.clear(glow::COLOR_BUFFER_BIT);
-            self.render_nodes(&composite.children);
+            self.render_order(&composite.children);
+            if let Some(msaa) = &self.msaa {
+                msaa.fbo.blit_to(&self.composite_fbo, width, height);
+            }
+            self.mutable.borrow_mut().prev_masks.clear();
+        }
+        if offset {
+            gl.viewport(x, y, width, height);
         }
+        self.mutable.borrow_mut().stats.composite_passes += 1;
 
         self.bind_texture(&self.composite_texture);
         self.set_blend_mode(composite.blend_mode);
         self.use_program(&self.composite_program);
+        self.set_base_vertex(0);
         gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
+        self.mutable.borrow_mut().stats.draw_calls += 1;
     }
 
+    /// Draws these nodes in order, merging consecutive parts which share the same texture,
+    /// blend mode and masks into a single draw call.
     pub fn render_nodes(&self, order: &[u32]) {
-        #[cfg(feature = "debug")]
-        let gl = self.gl;
+        let timer = self.profiling.then(Timer::start);
+        self.apply_target();
+        if let Some(vao) = &self.vao {
+            // Cheap, and the attributes stay right even if something else bound another one.
+            vao.bind();
+        }
+        self.render_order(order);
+        if let Some(timer) = timer {
+            self.mutable.borrow_mut().stats.render_time += timer.elapsed();
+        }
+    }
+
+    fn render_order(&self, order: &[u32]) {
+        let mut batch: Option<Batch> = None;
         for &uuid in order {
             match self.get(uuid) {
                 Some(EnumNode::Part(part)) => {
-                    #[cfg(feature = "debug")]
-                    gl.push_debug_group(glow::DEBUG_SOURCE_APPLICATION, 0, &part.name);
-                    self.set_stencil(false);
-                    self.render_part(part);
-                    #[cfg(feature = "debug")]
-                    gl.pop_debug_group();
+                    self.mutable.borrow_mut().stats.parts += 1;
+                    if let Some(batch) = &mut batch {
+                        if batch.try_extend(self, part) {
+                            continue;
+                        }
+                    }
+                    if let Some(batch) = batch.replace(Batch::new(part)) {
+                        self.render_batch(&batch);
+                    }
                 }
                 Some(EnumNode::Composite(composite)) => {
+                    if let Some(batch) = batch.take() {
+                        self.render_batch(&batch);
+                    }
                     #[cfg(feature = "debug")]
-                    gl.push_debug_group(glow::DEBUG_SOURCE_APPLICATION, 0, &composite.name);
+                    self.gl
+                        .push_debug_group(glow::DEBUG_SOURCE_APPLICATION, 0, &composite.name);
                     self.render_composite(composite);
                     #[cfg(feature = "debug")]
-                    gl.pop_debug_group();
+                    self.gl.pop_debug_group();
                 }
                 Some(EnumNode::Node(_)) => (),
                 None => (),
             }
         }
+        if let Some(batch) = batch {
+            self.render_batch(&batch);
+        }
     }
 
     pub fn clear(&self) {
         let gl = &self.gl;
+        self.apply_target();
         gl.clear(glow::COLOR_BUFFER_BIT);
     }
 
-    pub fn animate(&mut self, params: &ParamValues) {
+    /// The work done since the last call to [`take_stats`](Self::take_stats).
+    pub fn stats(&self) -> FrameStats {
+        self.mutable.borrow().stats
+    }
+
+    /// Returns the work done since the previous call and starts counting again, meant to be
+    /// called once per frame.
+    pub fn take_stats(&self) -> FrameStats {
+        std::mem::take(&mut self.mutable.borrow_mut().stats)
+    }
+
+    /// Enables measuring how long [`animate`](Self::animate) and
+    /// [`render_nodes`](Self::render_nodes) take, which has a small cost of its own.
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profiling = profiling;
+    }
+
+    /// Finds which bindings drive each part, so that animating doesn’t have to go through
+    /// every binding of the puppet.
+    fn compile_bindings(&mut self, values: &ParamValues) {
+        let params = &values.params;
         for node in self.nodes.values_mut() {
-            match node {
-                EnumNode::Part(part) => {
-                    part.anim.clear();
-                    part.deform.fill(0.);
-                }
-                _ => (),
+            if let EnumNode::Part(part) = node {
+                part.bindings.clear();
             }
         }
-        for (param, value) in params.iter() {
-            for binding in &param.bindings {
+        let mut parts_by_param = vec![Vec::new(); params.len()];
+        for (i, param) in params.iter().enumerate() {
+            for (j, binding) in param.bindings.iter().enumerate() {
                 match self.get_mut(binding.node) {
-                    Some(EnumNode::Part(part)) => part
-                        .anim
-                        .push(binding.interpolate(&param.axis_points, value)),
+                    Some(EnumNode::Part(part)) => {
+                        part.bindings.push((i, j));
+                        parts_by_param[i].push(binding.node);
+                    }
                     Some(EnumNode::Composite(_)) => (), // ! TODO
                     Some(EnumNode::Node(_)) => (), // ! TODO
                     None => (), // We don’t create SimplePhysics or disabled nodes.
                 }
             }
         }
-        for node in self.nodes.values_mut() {
-            match node {
-                EnumNode::Part(part) => {
-                    let mut dirty = false;
-                    for anim in part.anim.iter() {
-                        if let Anim::Deform(values) = anim {
-                            dirty = true;
-                            part.deform
-                                .iter_mut()
-                                .enumerate()
-                                .for_each(|(i, x)| *x += values[i]);
-                        }
-                    }
-                    if dirty {
-                        self.deform
-                            .update(self.gl, part.start_deform as i32, &part.deform);
+        self.bindings = Some(BindingTable {
+            params: values.id,
+            parts_by_param,
+            evaluated: None,
+            affected: Vec::new(),
+        });
+    }
+
+    /// Evaluates the bindings of the puppet for these values of its params.
+    ///
+    /// Only the parts driven by a param which changed since the previous call get evaluated
+    /// and uploaded again. The bindings get compiled on the first call, or when passed another
+    /// instance of [`ParamValues`].
+    pub fn animate(&mut self, params: &ParamValues) {
+        let timer = self.profiling.then(Timer::start);
+        if self.bindings.as_ref().map(|table| table.params) != Some(params.id) {
+            self.compile_bindings(params);
+        }
+        let mut table = self.bindings.take().unwrap();
+        table.affected.clear();
+        for i in params.changed_since(table.evaluated) {
+            table.affected.extend_from_slice(&table.parts_by_param[i]);
+        }
+        table.affected.sort_unstable();
+        table.affected.dedup();
+        if !table.affected.is_empty() {
+            let mut parts: Vec<&mut Part> = self
+                .nodes
+                .iter_mut()
+                .filter(|(uuid, _)| table.affected.binary_search(uuid).is_ok())
+                .filter_map(|(_, node)| match node {
+                    EnumNode::Part(part) => Some(part),
+                    _ => None,
+                })
+                .collect();
+
+            // Give each part its own slice of the mirror, so that they can be evaluated in any
+            // order, or at the same time.
+            parts.sort_unstable_by_key(|part| part.deform.start);
+            let mut jobs = Vec::with_capacity(parts.len());
+            let mut rest = &mut self.deform_mirror[..];
+            let mut offset = 0;
+            for part in parts {
+                let tail = std::mem::take(&mut rest).split_at_mut(part.deform.start - offset).1;
+                let (deform, tail) = tail.split_at_mut(part.deform.len());
+                offset = part.deform.end;
+                rest = tail;
+                jobs.push(Job { part, deform });
+            }
+
+            #[cfg(feature = "parallel")]
+            evaluate_parts(&mut jobs, params, &mut self.workers);
+            #[cfg(not(feature = "parallel"))]
+            evaluate_parts(&mut jobs, params);
+            let num_floats: usize = jobs.iter().map(|job| job.deform.len()).sum();
+            self.mutable.get_mut().stats.vertices_deformed += (num_floats / 2) as u32;
+            self.dirty_deforms
+                .extend(jobs.iter().map(|job| job.part.deform.clone()));
+        }
+        table.evaluated = Some(params.generation());
+        self.bindings = Some(table);
+        self.upload_deforms();
+        if let Some(timer) = timer {
+            self.mutable.get_mut().stats.animate_time += timer.elapsed();
+        }
+    }
+
+    /// Uploads the parts of the deform buffer which changed, merging the ranges close enough
+    /// to each other that uploading what’s between them is cheaper than another call.
+    fn upload_deforms(&mut self) {
+        if self.dirty_deforms.is_empty() {
+            return;
+        }
+        /// In floats, so 4 KiB.
+        const MAX_GAP: usize = 1024;
+
+        let gl = self.gl;
+        self.dirty_deforms.sort_unstable_by_key(|range| range.start);
+        self.deform.bind(gl, glow::ARRAY_BUFFER);
+        let mut ranges = self.dirty_deforms.drain(..);
+        let mut current = ranges.next().unwrap();
+        let mut uploaded = 0;
+        for range in ranges {
+            if range.start <= current.end + MAX_GAP {
+                current.end = current.end.max(range.end);
+            } else {
+                let data = &self.deform_mirror[current.clone()];
+                self.deform.update(gl, current.start as i32, data);
+                uploaded += current.len();
+                current = range;
+            }
+        }
+        uploaded += current.len();
+        self.deform
+            .update(gl, current.start as i32, &self.deform_mirror[current]);
+        self.mutable.get_mut().stats.bytes_uploaded +=
+            (uploaded * std::mem::size_of::<f32>()) as u64;
+    }
+}
+
+/// A part to evaluate, along with its slice of the deform buffer.
+struct Job<'p> {
+    part: &'p mut Part,
+    deform: &'p mut [f32],
+}
+
+impl Job<'_> {
+    fn evaluate(&mut self, params: &ParamValues) {
+        self.part.evaluate(params, self.deform);
+    }
+}
+
+/// Below this many floats of deform, handing parts to other threads costs more than it saves.
+#[cfg(feature = "parallel")]
+const PARALLEL_THRESHOLD: usize = 16 * 1024;
+
+/// Evaluates these parts, spread over every core if there is enough work.
+///
+/// Each part only touches its own data and sums its bindings in the same order either way, so
+/// the results are identical to evaluating them one after the other.
+fn evaluate_parts(
+    jobs: &mut [Job],
+    params: &ParamValues,
+    #[cfg(feature = "parallel")] workers: &mut Option<Workers>,
+) {
+    #[cfg(feature = "parallel")]
+    {
+        let total: usize = jobs.iter().map(|job| job.deform.len()).sum();
+        if jobs.len() > 1 && total >= PARALLEL_THRESHOLD {
+            let workers = workers.get_or_insert_with(|| {
+                let num_threads = std::thread::available_parallelism()
+                    .map(|num| num.get())
+                    .unwrap_or(1);
+                Workers::new(num_threads)
+            });
+            if workers.num_threads() > 1 {
+                // Split by amount of deform rather than by number of parts, which vary a lot
+                // in size.
+                let per_thread = total.div_ceil(workers.num_threads());
+                let mut tasks: Vec<Box<dyn FnOnce() + Send + '_>> = Vec::new();
+                let mut rest = jobs;
+                while !rest.is_empty() {
+                    let mut size = 0;
+                    let mut count = 0;
+                    while count < rest.len() && size < per_thread {
+                        size += rest[count].deform.len();
+                        count += 1;
                     }
+                    let (chunk, remaining) = std::mem::take(&mut rest).split_at_mut(count);
+                    rest = remaining;
+                    tasks.push(Box::new(move || {
+                        for job in chunk {
+                            job.evaluate(params);
+                        }
+                    }));
                 }
-                _ => (),
+                workers.run(tasks);
+                return;
             }
         }
     }
+
+    for job in jobs {
+        job.evaluate(params);
+    }
+}
+
+/// Consecutive parts drawn with a single call.
+struct Batch<'p> {
+    /// Its texture, blend mode and masks apply to the whole batch.
+    first: &'p Part,
+    num_indices: usize,
+}
+
+impl<'p> Batch<'p> {
+    fn new(part: &'p Part) -> Batch<'p> {
+        Batch {
+            first: part,
+            num_indices: part.indices.len(),
+        }
+    }
+
+    /// Adds this part to the batch if it follows it in the index buffer and draws the same way.
+    fn try_extend(&mut self, renderer: &GlRenderer, part: &Part) -> bool {
+        let first = self.first;
+        let end = first.start_index + self.num_indices * renderer.ibo.index_size();
+        if part.start_index != end
+            || part.base_vertex != first.base_vertex
+            || part.blend_mode != first.blend_mode
+            || part.masks != first.masks
+            || renderer.texture(part.textures[0]).texture
+                != renderer.texture(first.textures[0]).texture
+        {
+            return false;
+        }
+        self.num_indices += part.indices.len();
+        true
+    }
+}
+
+/// Which parts each param drives, compiled the first time the renderer gets animated.
+struct BindingTable {
+    /// The id of the [`ParamValues`] the table got compiled for.
+    params: u64,
+    parts_by_param: Vec<Vec<u32>>,
+    /// The generation of the values the parts currently reflect.
+    evaluated: Option<(u64, u64)>,
+    /// The parts to evaluate again, kept around to avoid allocating each frame.
+    affected: Vec<u32>,
 }
 
 impl From<BlendMode> for (u32, u32) {
@@ -547,21 +1271,53 @@ enum EnumNode {
 
 #[derive(Debug)]
 struct Part {
-    start_index: u16,
-    num_indices: u16,
-    start_deform: u16,
+    /// Where the indices of this part were in the list built while flattening the nodes.
+    indices: Range<usize>,
+    /// In bytes, into the index buffer once laid out in draw order.
+    start_index: usize,
+    /// Where the deform of this part lives in the deform buffer, in floats.
+    deform: Range<usize>,
+    base_vertex: u32,
     transform: Transform,
     textures: [usize; 3],
     blend_mode: (u32, u32),
     parent: u32,
     masks: Vec<Mask>,
-    anim: Vec<Anim>,
-    deform: Vec<f32>,
+    /// The bindings driving this part, as indices of the param and of the binding in it.
+    bindings: Vec<(usize, usize)>,
+    /// The translation of this part and of its parents.
+    origin: [f32; 2],
+    /// The sum of the translations of its bindings, the other transforms aren’t supported yet.
+    translation: [f32; 2],
     #[cfg(feature = "debug")]
     name: String,
 }
 
 impl Part {
+    /// Sums every binding of this part for these values of the params, and writes its
+    /// vertex offsets, translation included, into its slice of the deform buffer.
+    fn evaluate(&mut self, params: &ParamValues, deform: &mut [f32]) {
+        self.translation = [0.; 2];
+        deform.fill(0.);
+        for &(i, j) in self.bindings.iter() {
+            let param = &params.params[i];
+            let value = params.values[i];
+            let binding = &param.bindings[j];
+            match binding.interpolate_into(&param.axis_points, value, deform) {
+                Some(Anim::TransformTX(x)) => self.translation[0] += x,
+                Some(Anim::TransformTY(y)) => self.translation[1] += y,
+                _ => (),
+            }
+        }
+        // Baking the translation into the vertices lets parts get drawn together.
+        let x = self.origin[0] + self.translation[0];
+        let y = self.origin[1] + self.translation[1];
+        for offset in deform.chunks_exact_mut(2) {
+            offset[0] += x;
+            offset[1] += y;
+        }
+    }
+
     fn trans(&self, nodes: &GlRenderer) -> [f32; 3] {
         let mut trans = self.transform.trans;
         let mut parent_uuid = self.parent;
@@ -627,8 +1383,24 @@ pub fn sort_nodes_by_zsort(capacity: usize, node: &Node) -> Vec<u32> {
     uuids.into_iter().map(|(uuid, _)| uuid).collect()
 }
 
+/// Whether the context can draw with 32-bit indices, enabling them on WebGL 1.
+fn supports_uint_indices(gl: &dyn glow::Gl) -> bool {
+    gl.is_gles3() || gl.enable_extension("OES_element_index_uint")
+}
+
+/// Queries which compressed texture formats the current context can sample from.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn texture_support(gl: &dyn glow::Gl) -> TextureSupport {
+    TextureSupport {
+        bptc: gl.enable_extension("EXT_texture_compression_bptc")
+            || gl.enable_extension("ARB_texture_compression_bptc"),
+        astc: gl.enable_extension("KHR_texture_compression_astc_ldr")
+            || gl.enable_extension("OES_texture_compression_astc"),
+    }
+}
+
 pub fn setup<'a>(
-    gl: &'a glow::Context,
+    gl: &'a dyn glow::Gl,
     nodes: &Node,
     textures: TextureReceiver,
     width: u32,
@@ -636,7 +1408,653 @@ pub fn setup<'a>(
 ) -> GlRenderer<'a> {
     let mut renderer = GlRenderer::new(gl, width, height).unwrap();
     renderer.flatten_nodes(nodes, None);
-    renderer.upload_buffers();
+    renderer.upload_buffers(&sort_nodes_by_zsort(count_nodes(nodes), nodes));
     renderer.upload_textures(textures).unwrap();
     renderer
 }
+
+/// Like [`setup`], but returns before any texture has been decoded, they then have to be
+/// uploaded with [`GlRenderer::poll_textures`] as they arrive.
+pub fn setup_progressive<'a>(
+    gl: &'a dyn glow::Gl,
+    nodes: &Node,
+    textures: TextureReceiver,
+    width: u32,
+    height: u32,
+) -> GlRenderer<'a> {
+    let mut renderer = GlRenderer::new(gl, width, height).unwrap();
+    renderer.flatten_nodes(nodes, None);
+    renderer.upload_buffers(&sort_nodes_by_zsort(count_nodes(nodes), nodes));
+    renderer.set_textures(textures);
+    renderer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glow::recording::{Call, RecordingContext};
+    use crate::parser::Param;
+    use serde_json::json;
+
+    /// A part at the origin, with this many vertices but only drawing its first triangle
+    /// unless it is a quad.
+    fn part(uuid: u32, zsort: f32, texture: usize, num_vertices: usize, masks: &[u32]) -> Node {
+        let verts: Vec<f32> = (0..num_vertices * 2).map(|i| (i % 4) as f32).collect();
+        let indices: &[u16] = if num_vertices == 4 {
+            &[0, 1, 2, 2, 1, 3]
+        } else {
+            &[0, 1, 2]
+        };
+        let masks: Vec<_> = masks
+            .iter()
+            .map(|&source| json!({ "source": source, "mode": "Mask" }))
+            .collect();
+        serde_json::from_value(json!({
+            "type": "Part",
+            "uuid": uuid,
+            "name": format!("Part {uuid}"),
+            "enabled": true,
+            "zsort": zsort,
+            "transform": { "trans": [0., 0., 0.], "rot": [0., 0., 0.], "scale": [1., 1.] },
+            "lockToRoot": false,
+            "textures": [texture, u32::MAX, u32::MAX],
+            "mesh": { "verts": verts, "uvs": verts, "indices": indices, "origin": [0., 0.] },
+            "blend_mode": "Normal",
+            "opacity": 1.,
+            "tint": [1., 1., 1.],
+            "screenTint": [0., 0., 0.],
+            "mask_threshold": 0.5,
+            "masks": masks,
+            "psdLayerPath": "",
+        }))
+        .unwrap()
+    }
+
+    /// Two quads sharing a texture with a large part between them, drawn before a quad with
+    /// another texture masked by the first one.
+    fn puppet() -> Node {
+        serde_json::from_value(json!({
+            "type": "Node",
+            "uuid": 1,
+            "name": "Root",
+            "enabled": true,
+            "zsort": 0.,
+            "transform": { "trans": [0., 0., 0.], "rot": [0., 0., 0.], "scale": [1., 1.] },
+            "lockToRoot": false,
+            "children": [],
+        }))
+        .map(|mut root: Node| {
+            if let Node::Node { children, .. } = &mut root {
+                children.push(part(2, 0.3, 0, 4, &[]));
+                children.push(part(3, 0.2, 0, 600, &[]));
+                children.push(part(4, 0.1, 1, 4, &[2]));
+            }
+            root
+        })
+        .unwrap()
+    }
+
+    /// A composite at the origin, drawing these children.
+    fn composite(uuid: u32, zsort: f32, children: Vec<Node>) -> Node {
+        serde_json::from_value(json!({
+            "type": "Composite",
+            "uuid": uuid,
+            "name": format!("Composite {uuid}"),
+            "enabled": true,
+            "zsort": zsort,
+            "transform": { "trans": [0., 0., 0.], "rot": [0., 0., 0.], "scale": [1., 1.] },
+            "lockToRoot": false,
+            "children": [],
+            "blend_mode": "Normal",
+            "opacity": 1.,
+            "tint": [1., 1., 1.],
+            "screenTint": [0., 0., 0.],
+            "mask_threshold": 0.5,
+        }))
+        .map(|mut node: Node| {
+            if let Node::Composite { children: c, .. } = &mut node {
+                *c = children;
+            }
+            node
+        })
+        .unwrap()
+    }
+
+    /// A param translating these parts by 10 along x at its maximum.
+    fn param(name: &str, nodes: &[u32]) -> Param {
+        let bindings: Vec<_> = nodes
+            .iter()
+            .map(|&node| {
+                json!({
+                    "node": node,
+                    "param_name": "transform.t.x",
+                    "values": [[0.], [10.]],
+                    "isSet": [[true], [true]],
+                    "interpolate_mode": "Linear",
+                })
+            })
+            .collect();
+        serde_json::from_value(json!({
+            "uuid": 100,
+            "name": name,
+            "is_vec2": false,
+            "min": [0., 0.],
+            "max": [1., 1.],
+            "defaults": [0., 0.],
+            "axis_points": [[0., 1.], [0.]],
+            "bindings": bindings,
+        }))
+        .unwrap()
+    }
+
+    fn textures() -> TextureReceiver {
+        let (sender, receiver) = mpsc::channel();
+        for (i, size) in [(0, 2), (1, 4)] {
+            let texture = Texture::Rgba {
+                width: size,
+                height: size,
+                format: PixelFormat::Rgba8,
+                alpha: AlphaMode::Premultiplied,
+                data: vec![0xff; (size * size * 4) as usize],
+            };
+            sender.send((i, Ok(texture))).unwrap();
+        }
+        (2, receiver)
+    }
+
+    /// The name of the texture uploaded with this width, and with data.
+    fn texture_name(calls: &[Call], width: i32) -> u32 {
+        let upload = calls
+            .iter()
+            .position(|call| {
+                matches!(call, Call::TexImage2d { width: w, pixels: Some(_), .. } if *w == width)
+            })
+            .unwrap();
+        calls[..upload]
+            .iter()
+            .rev()
+            .find_map(|call| match call {
+                Call::BindTexture {
+                    texture: Some(texture),
+                    ..
+                } => Some(*texture),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    fn uniform_location(calls: &[Call], uniform: &str) -> i32 {
+        calls
+            .iter()
+            .find_map(|call| match call {
+                Call::GetUniformLocation { name, location, .. } if name == uniform => {
+                    Some(*location)
+                }
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    /// The calls made since the last time, without the debug groups which only get pushed
+    /// with the `debug` feature.
+    fn take_calls(gl: &RecordingContext) -> Vec<Call> {
+        #[allow(unused_mut)]
+        let mut calls = gl.take_calls();
+        #[cfg(feature = "debug")]
+        calls.retain(|call| !matches!(call, Call::PushDebugGroup { .. } | Call::PopDebugGroup));
+        calls
+    }
+
+    #[test]
+    fn batches_and_masks() {
+        let gl = RecordingContext::new();
+        let nodes = puppet();
+        let renderer = setup(&gl, &nodes, textures(), 800, 600);
+        let calls = gl.take_calls();
+        let texture0 = texture_name(&calls, 2);
+        let texture1 = texture_name(&calls, 4);
+        let straight_alpha = uniform_location(&calls, "straight_alpha");
+        let order = sort_nodes_by_zsort(count_nodes(&nodes), &nodes);
+        assert_eq!(order, [2, 3, 4, 1]);
+
+        renderer.render_nodes(&order);
+        let calls = take_calls(&gl);
+        let program = match calls[0] {
+            Call::UseProgram(Some(program)) => program,
+            ref call => panic!("Unexpected {call:?}"),
+        };
+        // The first two parts follow each other in the index buffer and share everything,
+        // while the third one needs the stencil buffer filled with the first one.
+        assert_eq!(
+            calls,
+            [
+                Call::UseProgram(Some(program)),
+                Call::BindTexture {
+                    target: glow::TEXTURE_2D,
+                    texture: Some(texture0),
+                },
+                Call::Uniform1f {
+                    location: Some(straight_alpha),
+                    v0: 0.,
+                },
+                Call::BlendFunc {
+                    sfactor: glow::ONE,
+                    dfactor: glow::ONE_MINUS_SRC_ALPHA,
+                },
+                Call::DrawElements {
+                    mode: glow::TRIANGLES,
+                    count: 9,
+                    type_: glow::UNSIGNED_SHORT,
+                    offset: 0,
+                },
+                Call::Enable(glow::STENCIL_TEST),
+                Call::ColorMask([false; 4]),
+                Call::StencilOp {
+                    fail: glow::KEEP,
+                    zfail: glow::KEEP,
+                    zpass: glow::REPLACE,
+                },
+                Call::StencilFunc {
+                    func: glow::ALWAYS,
+                    ref_: 0xff,
+                    mask: 0xff,
+                },
+                Call::Clear(glow::STENCIL_BUFFER_BIT),
+                Call::DrawElements {
+                    mode: glow::TRIANGLES,
+                    count: 6,
+                    type_: glow::UNSIGNED_SHORT,
+                    offset: 0,
+                },
+                Call::ColorMask([true; 4]),
+                Call::StencilFunc {
+                    func: glow::EQUAL,
+                    ref_: 0xff,
+                    mask: 0xff,
+                },
+                Call::StencilOp {
+                    fail: glow::KEEP,
+                    zfail: glow::KEEP,
+                    zpass: glow::KEEP,
+                },
+                Call::BindTexture {
+                    target: glow::TEXTURE_2D,
+                    texture: Some(texture1),
+                },
+                Call::DrawElements {
+                    mode: glow::TRIANGLES,
+                    count: 6,
+                    type_: glow::UNSIGNED_SHORT,
+                    offset: 18,
+                },
+            ]
+        );
+        let stats = renderer.take_stats();
+        assert_eq!(stats.parts, 3);
+        assert_eq!(stats.draw_calls, 3);
+        assert_eq!(stats.mask_recomputations, 1);
+
+        // The stencil buffer still contains the same mask, and only what differs between
+        // batches gets set again.
+        renderer.render_nodes(&order);
+        assert_eq!(
+            take_calls(&gl),
+            [
+                Call::Disable(glow::STENCIL_TEST),
+                Call::BindTexture {
+                    target: glow::TEXTURE_2D,
+                    texture: Some(texture0),
+                },
+                Call::DrawElements {
+                    mode: glow::TRIANGLES,
+                    count: 9,
+                    type_: glow::UNSIGNED_SHORT,
+                    offset: 0,
+                },
+                Call::Enable(glow::STENCIL_TEST),
+                Call::BindTexture {
+                    target: glow::TEXTURE_2D,
+                    texture: Some(texture1),
+                },
+                Call::DrawElements {
+                    mode: glow::TRIANGLES,
+                    count: 6,
+                    type_: glow::UNSIGNED_SHORT,
+                    offset: 18,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn base_vertex_split() {
+        // Without 32-bit indices, the second large part can’t be reached from the first vertex
+        // of the buffer, after the quad of the composites.
+        let gl = RecordingContext::new();
+        let mut nodes = puppet();
+        if let Node::Node { children, .. } = &mut nodes {
+            children.truncate(1);
+            children.push(part(3, 0.2, 0, 40000, &[]));
+            children.push(part(4, 0.1, 0, 40000, &[]));
+        }
+        let renderer = setup(&gl, &nodes, textures(), 800, 600);
+        let order = sort_nodes_by_zsort(count_nodes(&nodes), &nodes);
+        gl.take_calls();
+
+        let attributes_and_draws = |calls: Vec<Call>| -> Vec<Call> {
+            calls
+                .into_iter()
+                .filter(|call| {
+                    matches!(
+                        call,
+                        Call::VertexAttribPointer { .. } | Call::DrawElements { .. }
+                    )
+                })
+                .collect()
+        };
+        let attributes = |base_vertex: i32| {
+            (0..3).map(move |index| Call::VertexAttribPointer {
+                index,
+                size: 2,
+                type_: glow::FLOAT,
+                normalized: false,
+                stride: 8,
+                offset: base_vertex * 8,
+            })
+        };
+        let draw = |count, offset| Call::DrawElements {
+            mode: glow::TRIANGLES,
+            count,
+            type_: glow::UNSIGNED_SHORT,
+            offset,
+        };
+        // Same texture and blend mode, but only the first two parts get merged.
+        let first_frame: Vec<_> = [draw(9, 0)]
+            .into_iter()
+            .chain(attributes(40008))
+            .chain([draw(3, 18)])
+            .collect();
+        renderer.render_nodes(&order);
+        assert_eq!(attributes_and_draws(take_calls(&gl)), first_frame);
+        assert_eq!(renderer.take_stats().draw_calls, 2);
+
+        // The attributes then have to go back to the start of the buffer.
+        renderer.render_nodes(&order);
+        let second_frame: Vec<_> = attributes(0).chain(first_frame).collect();
+        assert_eq!(attributes_and_draws(take_calls(&gl)), second_frame);
+    }
+
+    /// The deform uploads, as the range of floats they cover and their first value.
+    fn uploads(calls: &[Call]) -> Vec<(Range<usize>, f32)> {
+        calls
+            .iter()
+            .filter_map(|call| match call {
+                Call::BufferSubData {
+                    target: glow::ARRAY_BUFFER,
+                    offset,
+                    data,
+                } => {
+                    let start = *offset as usize / 4;
+                    let first = f32::from_ne_bytes(data[..4].try_into().unwrap());
+                    Some((start..start + data.len() / 4, first))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn animate_uploads_changed_ranges() {
+        let gl = RecordingContext::new();
+        let nodes = puppet();
+        let mut renderer = setup(&gl, &nodes, textures(), 800, 600);
+        // The deforms of the parts start after the quad of the composites, in tree order.
+        let (first, large, last) = (8..16, 16..1216, 1216..1224);
+        let params = [param("near", &[2, 3]), param("far", &[2, 4])];
+        let mut values = ParamValues::new(&params);
+        gl.take_calls();
+
+        // Everything gets evaluated the first time, close enough to upload it all at once.
+        renderer.animate(&values);
+        assert_eq!(uploads(&gl.take_calls()), [(first.start..last.end, 0.)]);
+
+        renderer.animate(&values);
+        assert_eq!(uploads(&gl.take_calls()), []);
+
+        // Too far apart to upload the large part between them.
+        values.set("far", [1., 0.]);
+        renderer.animate(&values);
+        assert_eq!(
+            uploads(&gl.take_calls()),
+            [(first.clone(), 10.), (last.clone(), 10.)]
+        );
+
+        values.set("near", [1., 0.]);
+        renderer.animate(&values);
+        assert_eq!(uploads(&gl.take_calls()), [(first.start..large.end, 20.)]);
+        assert_eq!(
+            renderer.take_stats().vertices_deformed as usize,
+            (first.len() + large.len() + last.len() + first.len() + last.len()) / 2
+                + (first.len() + large.len()) / 2
+        );
+    }
+
+    #[test]
+    fn frame_restores_host_state() {
+        let gl = RecordingContext::gles3(&[]);
+        let nodes = puppet();
+        let mut renderer = setup(&gl, &nodes, textures(), 800, 600);
+        let order = sort_nodes_by_zsort(count_nodes(&nodes), &nodes);
+        gl.take_calls();
+
+        // Nothing happens on the context outside of a frame.
+        renderer.set_target(None, [10, 20, 300, 200]);
+        assert_eq!(gl.take_calls(), []);
+
+        renderer.begin_frame();
+        let calls = gl.take_calls();
+        assert!(calls.contains(&Call::Viewport {
+            x: 10,
+            y: 20,
+            width: 300,
+            height: 200,
+        }));
+        let resizes = |calls: &[Call]| {
+            calls
+                .iter()
+                .filter(|call| matches!(call, Call::TexImage2d { pixels: None, .. }))
+                .count()
+        };
+        assert_eq!(resizes(&calls), 1);
+        renderer.render_nodes(&order);
+        renderer.end_frame();
+
+        // Every piece of state goes back to what a fresh context starts with.
+        let calls = gl.take_calls();
+        let restore = calls
+            .iter()
+            .rposition(|call| *call == Call::Disable(glow::BLEND))
+            .unwrap();
+        assert_eq!(
+            calls[restore..],
+            [
+                Call::Disable(glow::BLEND),
+                Call::Disable(glow::STENCIL_TEST),
+                Call::Disable(glow::DEPTH_TEST),
+                Call::Disable(glow::CULL_FACE),
+                Call::Disable(glow::SCISSOR_TEST),
+                Call::BlendFuncSeparate {
+                    src_rgb: glow::ONE,
+                    dst_rgb: glow::ZERO,
+                    src_alpha: glow::ONE,
+                    dst_alpha: glow::ZERO,
+                },
+                Call::BlendEquationSeparate {
+                    mode_rgb: glow::FUNC_ADD,
+                    mode_alpha: glow::FUNC_ADD,
+                },
+                Call::StencilFunc {
+                    func: glow::ALWAYS,
+                    ref_: 0,
+                    mask: u32::MAX,
+                },
+                Call::StencilOp {
+                    fail: glow::KEEP,
+                    zfail: glow::KEEP,
+                    zpass: glow::KEEP,
+                },
+                Call::StencilMask(u32::MAX),
+                Call::ColorMask([true; 4]),
+                Call::ClearColor([0.; 4]),
+                Call::Viewport {
+                    x: 0,
+                    y: 0,
+                    width: 0,
+                    height: 0,
+                },
+                Call::BindFramebuffer {
+                    target: glow::FRAMEBUFFER,
+                    framebuffer: None,
+                },
+                Call::BindFramebuffer {
+                    target: glow::READ_FRAMEBUFFER,
+                    framebuffer: None,
+                },
+                Call::BindRenderbuffer {
+                    target: glow::RENDERBUFFER,
+                    renderbuffer: None,
+                },
+                Call::UseProgram(None),
+                Call::ActiveTexture(glow::TEXTURE0),
+                Call::BindTexture {
+                    target: glow::TEXTURE_2D,
+                    texture: None,
+                },
+                Call::ActiveTexture(glow::TEXTURE0),
+                Call::BindVertexArray(None),
+                Call::BindBuffer {
+                    target: glow::ARRAY_BUFFER,
+                    buffer: None,
+                },
+            ]
+        );
+
+        // The next frame sets everything up again, but the target is already right.
+        renderer.begin_frame();
+        let calls = gl.take_calls();
+        assert!(calls.contains(&Call::Viewport {
+            x: 10,
+            y: 20,
+            width: 300,
+            height: 200,
+        }));
+        assert_eq!(resizes(&calls), 0);
+        renderer.render_nodes(&order);
+        let calls = gl.take_calls();
+        assert!(calls.contains(&Call::UseProgram(Some(1))));
+        renderer.end_frame();
+    }
+
+    #[test]
+    fn multisampled_composite() {
+        let gl = RecordingContext::gles3(&[]);
+        let mut nodes = puppet();
+        if let Node::Node { children, .. } = &mut nodes {
+            // The same mask before, within and after the composite.
+            children.truncate(1);
+            children.push(part(3, 0.2, 0, 4, &[2]));
+            children.push(composite(5, 0.1, vec![part(4, 0.1, 1, 4, &[2])]));
+            children.push(part(6, 0.05, 0, 4, &[2]));
+        }
+        let renderer = setup(&gl, &nodes, textures(), 800, 600);
+        let calls = gl.take_calls();
+        let attached_fbo = |attachment: fn(&Call) -> bool| {
+            let attach = calls.iter().position(attachment).unwrap();
+            calls[..attach]
+                .iter()
+                .rev()
+                .find_map(|call| match call {
+                    Call::BindFramebuffer {
+                        framebuffer: Some(framebuffer),
+                        ..
+                    } => Some(*framebuffer),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        let composite_fbo = attached_fbo(|call| matches!(call, Call::FramebufferTexture2d { .. }));
+        let msaa_fbo = attached_fbo(|call| {
+            matches!(
+                call,
+                Call::FramebufferRenderbuffer {
+                    attachment: glow::DEPTH_STENCIL_ATTACHMENT,
+                    ..
+                }
+            )
+        });
+        assert!(calls.contains(&Call::RenderbufferStorageMultisample {
+            target: glow::RENDERBUFFER,
+            samples: 4,
+            internalformat: glow::DEPTH24_STENCIL8,
+            width: 800,
+            height: 600,
+        }));
+        let order = sort_nodes_by_zsort(count_nodes(&nodes), &nodes);
+        assert_eq!(order, [2, 3, 5, 6, 1]);
+
+        renderer.render_nodes(&order);
+        let calls: Vec<_> = take_calls(&gl)
+            .into_iter()
+            .filter(|call| {
+                matches!(
+                    call,
+                    Call::BindFramebuffer { .. }
+                        | Call::Clear(_)
+                        | Call::BlitFramebuffer { .. }
+                        | Call::DrawArrays { .. }
+                )
+            })
+            .collect();
+        // The composite gets drawn with its own stencil buffer, and the mask gets drawn again
+        // into it and then into the target.
+        assert_eq!(
+            calls,
+            [
+                Call::Clear(glow::STENCIL_BUFFER_BIT),
+                Call::BindFramebuffer {
+                    target: glow::FRAMEBUFFER,
+                    framebuffer: Some(msaa_fbo),
+                },
+                Call::Clear(glow::COLOR_BUFFER_BIT),
+                Call::Clear(glow::STENCIL_BUFFER_BIT),
+                Call::BindFramebuffer {
+                    target: glow::READ_FRAMEBUFFER,
+                    framebuffer: Some(msaa_fbo),
+                },
+                Call::BindFramebuffer {
+                    target: glow::DRAW_FRAMEBUFFER,
+                    framebuffer: Some(composite_fbo),
+                },
+                Call::BlitFramebuffer {
+                    src: [0, 0, 800, 600],
+                    dst: [0, 0, 800, 600],
+                    mask: glow::COLOR_BUFFER_BIT,
+                    filter: glow::NEAREST,
+                },
+                Call::BindFramebuffer {
+                    target: glow::FRAMEBUFFER,
+                    framebuffer: None,
+                },
+                Call::DrawArrays {
+                    mode: glow::TRIANGLE_STRIP,
+                    first: 0,
+                    count: 4,
+                },
+                Call::Clear(glow::STENCIL_BUFFER_BIT),
+            ]
+        );
+        let stats = renderer.take_stats();
+        assert_eq!(stats.composite_passes, 1);
+        assert_eq!(stats.mask_recomputations, 3);
+    }
+}
@@ -7,17 +7,17 @@
 use crate::glow;
 
 pub struct ProgramBuilder<'a> {
-    gl: &'a glow::Context,
+    gl: &'a dyn glow::Gl,
     program: glow::NativeProgram,
 }
 
 pub struct Program<'a> {
-    gl: &'a glow::Context,
+    gl: &'a dyn glow::Gl,
     pub program: glow::NativeProgram,
 }
 
 impl<'a> Program<'a> {
-    pub fn builder(gl: &'a glow::Context) -> Result<ProgramBuilder<'a>, String> {
+    pub fn builder(gl: &'a dyn glow::Gl) -> Result<ProgramBuilder<'a>, String> {
         let program = gl.create_program().unwrap();
         Ok(ProgramBuilder { gl, program })
     }
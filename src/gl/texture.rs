@@ -8,8 +8,10 @@ use crate::glow;
 
 #[derive(Clone)]
 pub struct Texture<'a> {
-    gl: &'a glow::Context,
+    gl: &'a dyn glow::Gl,
     pub texture: glow::NativeTexture,
+    /// Whether the color channels still have to be multiplied by alpha when sampling.
+    pub straight_alpha: bool,
 }
 
 impl<'a> Drop for Texture<'a> {
@@ -21,7 +23,7 @@ impl<'a> Drop for Texture<'a> {
 
 impl<'a> Texture<'a> {
     pub fn from_data(
-        gl: &'a glow::Context,
+        gl: &'a dyn glow::Gl,
         width: u32,
         height: u32,
         data: Option<&[u8]>,
@@ -61,8 +63,47 @@ impl<'a> Texture<'a> {
             glow::UNSIGNED_BYTE,
             data,
         )
-        .map_err(|_| String::from("Empty texture allocation failed"))?;
-        Ok(Texture { gl, texture })
+        .map_err(|err| format!("Empty texture allocation failed: {err}"))?;
+        Ok(Texture {
+            gl,
+            texture,
+            straight_alpha: false,
+        })
+    }
+
+    pub fn from_compressed_data(
+        gl: &'a dyn glow::Gl,
+        internalformat: u32,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Texture<'a> {
+        let texture = gl.create_texture().unwrap();
+        gl.bind_texture(glow::TEXTURE_2D, Some(&texture));
+        gl.tex_parameteri(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_MIN_FILTER,
+            glow::LINEAR as i32,
+        );
+        gl.tex_parameteri(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_MAG_FILTER,
+            glow::LINEAR as i32,
+        );
+        gl.compressed_tex_image_2d_with_u8_array(
+            glow::TEXTURE_2D,
+            0,
+            internalformat,
+            width as i32,
+            height as i32,
+            0,
+            data,
+        );
+        Texture {
+            gl,
+            texture,
+            straight_alpha: false,
+        }
     }
 
     pub fn bind(&self) {
@@ -7,16 +7,16 @@
 use crate::glow;
 
 pub enum Vbo<'a, T: Copy> {
-    Buffering(&'a glow::Context, Vec<T>),
+    Buffering(&'a dyn glow::Gl, Vec<T>),
     Uploaded(glow::NativeBuffer),
 }
 
 impl<'a, T: Copy> Vbo<'a, T> {
-    pub fn new(gl: &'a glow::Context) -> Vbo<'a, T> {
+    pub fn new(gl: &'a dyn glow::Gl) -> Vbo<'a, T> {
         Vbo::Buffering(gl, Vec::new())
     }
 
-    pub fn from(gl: &'a glow::Context, vec: Vec<T>) -> Vbo<'a, T> {
+    pub fn from(gl: &'a dyn glow::Gl, vec: Vec<T>) -> Vbo<'a, T> {
         Vbo::Buffering(gl, vec)
     }
 
@@ -27,6 +27,13 @@ impl<'a, T: Copy> Vbo<'a, T> {
         }
     }
 
+    pub fn as_slice(&self) -> &[T] {
+        match self {
+            Vbo::Buffering(_, vec) => vec,
+            _ => panic!("Vbo must not be uploaded yet!"),
+        }
+    }
+
     pub fn extend_from_slice(&mut self, other: &[T]) {
         match self {
             Vbo::Buffering(_, vec) => vec.extend_from_slice(other),
@@ -60,10 +67,110 @@ impl<'a, T: Copy> Vbo<'a, T> {
         }
     }
 
-    pub fn update(&self, gl: &glow::Context, offset: i32, slice: &[T]) {
+    pub fn bind(&self, gl: &dyn glow::Gl, target: u32) {
+        match self {
+            Vbo::Uploaded(vbo) => gl.bind_buffer(target, Some(vbo)),
+            _ => panic!("Vbo must be uploaded first!"),
+        }
+    }
+
+    pub fn update(&self, gl: &dyn glow::Gl, offset: i32, slice: &[T]) {
         let size = core::mem::size_of::<T>();
-        let bytes: &[u8] =
-            unsafe { core::slice::from_raw_parts(slice.as_ptr() as *const u8, slice.len() * size) };
+        let bytes: &[u8] = unsafe {
+            core::slice::from_raw_parts(slice.as_ptr() as *const u8, core::mem::size_of_val(slice))
+        };
         gl.buffer_sub_data_with_i32_and_u8_array(glow::ARRAY_BUFFER, offset * size as i32, bytes);
     }
 }
+
+/// An index buffer, with 32-bit indices if the context supports them, 16-bit otherwise.
+pub enum Ibo<'a> {
+    Short(Vbo<'a, u16>),
+    Int(Vbo<'a, u32>),
+}
+
+impl<'a> Ibo<'a> {
+    pub fn new(gl: &'a dyn glow::Gl, wide: bool) -> Ibo<'a> {
+        if wide {
+            Ibo::Int(Vbo::new(gl))
+        } else {
+            Ibo::Short(Vbo::new(gl))
+        }
+    }
+
+    pub fn is_wide(&self) -> bool {
+        matches!(self, Ibo::Int(_))
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Ibo::Short(vbo) => vbo.len(),
+            Ibo::Int(vbo) => vbo.len(),
+        }
+    }
+
+    /// Size of one index, in bytes.
+    pub fn index_size(&self) -> usize {
+        match self {
+            Ibo::Short(_) => core::mem::size_of::<u16>(),
+            Ibo::Int(_) => core::mem::size_of::<u32>(),
+        }
+    }
+
+    pub fn index_type(&self) -> u32 {
+        match self {
+            Ibo::Short(_) => glow::UNSIGNED_SHORT,
+            Ibo::Int(_) => glow::UNSIGNED_INT,
+        }
+    }
+
+    /// The indices have to fit in 16 bits unless the buffer is wide.
+    pub fn extend<I: IntoIterator<Item = u32>>(&mut self, other: I) {
+        match self {
+            Ibo::Short(vbo) => vbo.extend(other.into_iter().map(|index| index as u16)),
+            Ibo::Int(vbo) => vbo.extend(other),
+        }
+    }
+
+    pub fn upload(&mut self, usage: u32) {
+        match self {
+            Ibo::Short(vbo) => vbo.upload(glow::ELEMENT_ARRAY_BUFFER, usage),
+            Ibo::Int(vbo) => vbo.upload(glow::ELEMENT_ARRAY_BUFFER, usage),
+        }
+    }
+
+    /// Nothing to bind until the indices got uploaded.
+    pub fn bind(&self, gl: &dyn glow::Gl) {
+        match self {
+            Ibo::Short(vbo @ Vbo::Uploaded(_)) => vbo.bind(gl, glow::ELEMENT_ARRAY_BUFFER),
+            Ibo::Int(vbo @ Vbo::Uploaded(_)) => vbo.bind(gl, glow::ELEMENT_ARRAY_BUFFER),
+            _ => (),
+        }
+    }
+}
+
+/// Holds the vertex attributes and the index buffer, only available on GLES 3.
+pub struct VertexArray<'a> {
+    gl: &'a dyn glow::Gl,
+    vao: glow::NativeVertexArray,
+}
+
+impl<'a> VertexArray<'a> {
+    pub fn new(gl: &'a dyn glow::Gl) -> Option<VertexArray<'a>> {
+        if !gl.is_gles3() {
+            return None;
+        }
+        let vao = gl.create_vertex_array()?;
+        Some(VertexArray { gl, vao })
+    }
+
+    pub fn bind(&self) {
+        self.gl.bind_vertex_array(Some(&self.vao));
+    }
+}
+
+impl<'a> Drop for VertexArray<'a> {
+    fn drop(&mut self) {
+        self.gl.delete_vertex_array(Some(&self.vao));
+    }
+}
@@ -0,0 +1,159 @@
+// Copyright (c) 2023 Emmanuel Gil Peyrot <linkmauve@linkmauve.fr>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Threads kept around between frames, so that spreading work over every core doesn’t cost
+//! starting new ones each time.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// A task, along with where to report whether it completed without panicking.
+type Message = (Task, mpsc::Sender<bool>);
+
+pub struct Workers {
+    queue: mpsc::Sender<Message>,
+    /// Including the calling thread.
+    num_threads: usize,
+}
+
+impl Workers {
+    /// Spawns one thread less than `num_threads`, since the calling thread takes a share of the
+    /// work too.
+    pub fn new(num_threads: usize) -> Workers {
+        let (queue, receiver) = mpsc::channel::<Message>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 1..num_threads {
+            let receiver = Arc::clone(&receiver);
+            std::thread::Builder::new()
+                .name(String::from("Animation"))
+                .spawn(move || worker(&receiver))
+                .unwrap();
+        }
+        Workers {
+            queue,
+            num_threads: num_threads.max(1),
+        }
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.num_threads
+    }
+
+    /// Runs the last task on the calling thread and the other ones on the workers, returning
+    /// once they are all done.
+    ///
+    /// The tasks can borrow from the caller, since none of them outlives this call.
+    pub fn run<'s>(&self, mut tasks: Vec<Box<dyn FnOnce() + Send + 's>>) {
+        let Some(last) = tasks.pop() else {
+            return;
+        };
+        let (done_sender, done) = mpsc::channel();
+        let mut wait = Wait {
+            done,
+            pending: 0,
+            panicked: false,
+        };
+        for task in tasks {
+            // Safety: `wait` blocks until the task is done before returning, even when
+            // unwinding, so whatever it borrows outlives it.
+            let task = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 's>, Task>(task) };
+            self.queue.send((task, done_sender.clone())).unwrap();
+            wait.pending += 1;
+        }
+        // Otherwise waiting would never end if a task got dropped without running.
+        drop(done_sender);
+        last();
+        if wait.finish() {
+            panic!("A task panicked on an animation worker");
+        }
+    }
+}
+
+/// Waits for the tasks sent to the workers, once dropped at the latest.
+struct Wait {
+    done: mpsc::Receiver<bool>,
+    pending: usize,
+    panicked: bool,
+}
+
+impl Wait {
+    /// Returns whether any of the tasks panicked.
+    fn finish(&mut self) -> bool {
+        while self.pending > 0 {
+            self.pending -= 1;
+            match self.done.recv() {
+                Ok(completed) => self.panicked |= !completed,
+                // Every task has been either run or dropped.
+                Err(_) => break,
+            }
+        }
+        self.panicked
+    }
+}
+
+impl Drop for Wait {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+fn worker(queue: &Mutex<mpsc::Receiver<Message>>) {
+    loop {
+        let (task, done) = match queue.lock().unwrap().recv() {
+            Ok(message) => message,
+            Err(_) => break,
+        };
+        let completed = panic::catch_unwind(AssertUnwindSafe(task)).is_ok();
+        // The caller may only have stopped listening if it panicked too.
+        let _ = done.send(completed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn borrowed_tasks() {
+        let workers = Workers::new(4);
+        let mut values = [0u32; 64];
+        let tasks = values
+            .chunks_mut(16)
+            .enumerate()
+            .map(|(i, chunk)| -> Box<dyn FnOnce() + Send + '_> {
+                Box::new(move || chunk.fill(i as u32 + 1))
+            })
+            .collect();
+        workers.run(tasks);
+        for (i, chunk) in values.chunks(16).enumerate() {
+            assert!(chunk.iter().all(|&value| value == i as u32 + 1));
+        }
+
+        // The same threads get reused for the next call.
+        let mut sum = [0u32; 2];
+        let (first, second) = sum.split_at_mut(1);
+        workers.run(vec![
+            Box::new(|| first[0] = values.iter().sum()),
+            Box::new(|| second[0] = values.len() as u32),
+        ]);
+        assert_eq!(sum, [160, 64]);
+    }
+
+    #[test]
+    fn worker_panic() {
+        let workers = Workers::new(2);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            workers.run(vec![Box::new(|| panic!("expected")), Box::new(|| ())]);
+        }));
+        assert!(result.is_err());
+        // The worker survived it.
+        let mut done = false;
+        workers.run(vec![Box::new(|| done = true), Box::new(|| ())]);
+        assert!(done);
+    }
+}
@@ -4,13 +4,18 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+#[cfg(not(target_arch = "wasm32"))]
+use core::ffi::c_void;
 #[cfg(not(target_arch = "wasm32"))]
 use core::num::{NonZeroI32, NonZeroU32};
 #[cfg(not(target_arch = "wasm32"))]
 use core::ptr::null;
 #[cfg(not(target_arch = "wasm32"))]
 use std::ffi::CString;
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::JsCast;
 
+pub const ZERO: u32 = 0;
 pub const ONE: u32 = 1;
 pub const TRIANGLES: u32 = 0x0004;
 pub const TRIANGLE_STRIP: u32 = 0x0005;
@@ -20,34 +25,91 @@ pub const ONE_MINUS_SRC_COLOR: u32 = 0x0301;
 pub const ONE_MINUS_SRC_ALPHA: u32 = 0x0303;
 pub const DST_ALPHA: u32 = 0x0304;
 pub const DST_COLOR: u32 = 0x0306;
+pub const CULL_FACE: u32 = 0x0B44;
+pub const DEPTH_TEST: u32 = 0x0B71;
 pub const STENCIL_TEST: u32 = 0x0B90;
+pub const STENCIL_FUNC: u32 = 0x0B92;
+pub const STENCIL_VALUE_MASK: u32 = 0x0B93;
+pub const STENCIL_FAIL: u32 = 0x0B94;
+pub const STENCIL_PASS_DEPTH_FAIL: u32 = 0x0B95;
+pub const STENCIL_PASS_DEPTH_PASS: u32 = 0x0B96;
+pub const STENCIL_REF: u32 = 0x0B97;
+pub const STENCIL_WRITEMASK: u32 = 0x0B98;
+pub const VIEWPORT: u32 = 0x0BA2;
 pub const BLEND: u32 = 0x0BE2;
+pub const SCISSOR_TEST: u32 = 0x0C11;
+pub const COLOR_CLEAR_VALUE: u32 = 0x0C22;
+pub const COLOR_WRITEMASK: u32 = 0x0C23;
 pub const TEXTURE_2D: u32 = 0x0DE1;
 pub const UNSIGNED_BYTE: u32 = 0x1401;
 pub const UNSIGNED_SHORT: u32 = 0x1403;
+pub const UNSIGNED_INT: u32 = 0x1405;
 pub const FLOAT: u32 = 0x1406;
 pub const RGBA: u32 = 0x1908;
 pub const KEEP: u32 = 0x1E00;
+#[cfg(not(target_arch = "wasm32"))]
+const VERSION: u32 = 0x1F02;
+#[cfg(not(target_arch = "wasm32"))]
+const EXTENSIONS: u32 = 0x1F03;
 pub const REPLACE: u32 = 0x1E01;
+pub const NEAREST: u32 = 0x2600;
 pub const LINEAR: u32 = 0x2601;
 pub const TEXTURE_MAG_FILTER: u32 = 0x2800;
 pub const TEXTURE_MIN_FILTER: u32 = 0x2801;
 pub const TEXTURE_WRAP_S: u32 = 0x2802;
 pub const TEXTURE_WRAP_T: u32 = 0x2803;
+pub const FUNC_ADD: u32 = 0x8006;
+pub const BLEND_EQUATION_RGB: u32 = 0x8009;
+pub const RGBA8: u32 = 0x8058;
+pub const TEXTURE_BINDING_2D: u32 = 0x8069;
+pub const BLEND_DST_RGB: u32 = 0x80C8;
+pub const BLEND_SRC_RGB: u32 = 0x80C9;
+pub const BLEND_DST_ALPHA: u32 = 0x80CA;
+pub const BLEND_SRC_ALPHA: u32 = 0x80CB;
 pub const CLAMP_TO_EDGE: u32 = 0x812F;
+pub const DEPTH_STENCIL_ATTACHMENT: u32 = 0x821A;
+pub const TEXTURE0: u32 = 0x84C0;
+pub const ACTIVE_TEXTURE: u32 = 0x84E0;
+pub const VERTEX_ARRAY_BINDING: u32 = 0x85B5;
+const VERTEX_ATTRIB_ARRAY_ENABLED: u32 = 0x8622;
+const VERTEX_ATTRIB_ARRAY_SIZE: u32 = 0x8623;
+const VERTEX_ATTRIB_ARRAY_STRIDE: u32 = 0x8624;
+const VERTEX_ATTRIB_ARRAY_TYPE: u32 = 0x8625;
+const VERTEX_ATTRIB_ARRAY_POINTER: u32 = 0x8645;
+pub const BLEND_EQUATION_ALPHA: u32 = 0x883D;
+const VERTEX_ATTRIB_ARRAY_NORMALIZED: u32 = 0x886A;
 pub const ARRAY_BUFFER: u32 = 0x8892;
 pub const ELEMENT_ARRAY_BUFFER: u32 = 0x8893;
+pub const ARRAY_BUFFER_BINDING: u32 = 0x8894;
+pub const ELEMENT_ARRAY_BUFFER_BINDING: u32 = 0x8895;
+const VERTEX_ATTRIB_ARRAY_BUFFER_BINDING: u32 = 0x889F;
 pub const STATIC_DRAW: u32 = 0x88E4;
 pub const DYNAMIC_DRAW: u32 = 0x88E8;
+pub const DEPTH24_STENCIL8: u32 = 0x88F0;
 pub const FRAGMENT_SHADER: u32 = 0x8B30;
 pub const VERTEX_SHADER: u32 = 0x8B31;
 pub const COMPILE_STATUS: u32 = 0x8B81;
 pub const LINK_STATUS: u32 = 0x8B82;
 #[cfg(not(target_arch = "wasm32"))]
 const INFO_LOG_LENGTH: u32 = 0x8B84;
+const CURRENT_PROGRAM: u32 = 0x8B8D;
+const FRAMEBUFFER_BINDING: u32 = 0x8CA6;
+const RENDERBUFFER_BINDING: u32 = 0x8CA7;
+pub const READ_FRAMEBUFFER: u32 = 0x8CA8;
+pub const DRAW_FRAMEBUFFER: u32 = 0x8CA9;
+const READ_FRAMEBUFFER_BINDING: u32 = 0x8CAA;
 pub const FRAMEBUFFER_COMPLETE: u32 = 0x8CD5;
 pub const COLOR_ATTACHMENT0: u32 = 0x8CE0;
 pub const FRAMEBUFFER: u32 = 0x8D40;
+pub const RENDERBUFFER: u32 = 0x8D41;
+pub const MAX_SAMPLES: u32 = 0x8D57;
+
+// GL_EXT_texture_compression_bptc
+pub const COMPRESSED_RGBA_BPTC_UNORM_EXT: u32 = 0x8E8C;
+
+// GL_KHR_texture_compression_astc_ldr, the block sizes follow in the same order as
+// astc::BLOCK_SIZES.
+pub const COMPRESSED_RGBA_ASTC_4X4_KHR: u32 = 0x93B0;
 
 pub const STENCIL_BUFFER_BIT: u32 = 0x00000400;
 pub const COLOR_BUFFER_BIT: u32 = 0x00004000;
@@ -67,6 +129,12 @@ pub type NativeUniformLocation = web_sys::WebGlUniformLocation;
 #[cfg(target_arch = "wasm32")]
 pub type NativeFramebuffer = web_sys::WebGlFramebuffer;
 #[cfg(target_arch = "wasm32")]
+pub type NativeRenderbuffer = web_sys::WebGlRenderbuffer;
+#[cfg(target_arch = "wasm32")]
+pub type NativeShader = web_sys::WebGlShader;
+#[cfg(target_arch = "wasm32")]
+pub type NativeVertexArray = web_sys::WebGlVertexArrayObject;
+#[cfg(target_arch = "wasm32")]
 pub type Context = web_sys::WebGlRenderingContext;
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -86,10 +154,71 @@ pub struct NativeUniformLocation(NonZeroI32);
 #[cfg(not(target_arch = "wasm32"))]
 #[derive(Clone)]
 pub struct NativeFramebuffer(NonZeroU32);
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone)]
+pub struct NativeRenderbuffer(NonZeroU32);
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone)]
+pub struct NativeVertexArray(NonZeroU32);
 
 #[cfg(not(target_arch = "wasm32"))]
-#[link(name = "GLESv2")]
-extern "C" {
+impl NativeFramebuffer {
+    /// Wraps the name of a framebuffer created outside of this crate.
+    pub fn from_raw(name: NonZeroU32) -> NativeFramebuffer {
+        NativeFramebuffer(name)
+    }
+}
+
+/// Everything about a vertex attribute, as queried from the context.
+#[derive(Clone)]
+pub struct VertexAttrib {
+    pub enabled: bool,
+    pub buffer: Option<NativeBuffer>,
+    pub size: i32,
+    pub type_: u32,
+    pub normalized: bool,
+    pub stride: i32,
+    /// Only an offset into the buffer, client-side arrays can’t be represented.
+    pub offset: i32,
+}
+
+/// Declares the GL functions the renderer needs, and how to load them.
+#[cfg(not(target_arch = "wasm32"))]
+macro_rules! functions {
+    ($(fn $name:ident($($arg:ident: $type:ty),* $(,)?) $(-> $ret:ty)?;)*) => {
+        #[allow(non_snake_case)]
+        struct Functions {
+            $($name: unsafe extern "system" fn($($type),*) $(-> $ret)?,)*
+        }
+
+        impl Functions {
+            fn load(loader: &mut dyn FnMut(&str) -> *const c_void) -> Result<Functions, String> {
+                Ok(Functions {
+                    $($name: {
+                        let pointer = loader(stringify!($name));
+                        if pointer.is_null() {
+                            return Err(format!(
+                                "The GL function {} is missing from this context",
+                                stringify!($name)
+                            ));
+                        }
+                        // SAFETY: the loader gave us this function, which has this signature
+                        // in the GLES 2 specification.
+                        unsafe {
+                            core::mem::transmute::<
+                                *const c_void,
+                                unsafe extern "system" fn($($type),*) $(-> $ret)?,
+                            >(pointer)
+                        }
+                    },)*
+                })
+            }
+        }
+    };
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+functions! {
     fn glEnable(cap: u32);
     fn glDisable(cap: u32);
     fn glViewport(x: i32, y: i32, width: i32, height: i32);
@@ -100,21 +229,25 @@ extern "C" {
     fn glStencilMask(mask: u32);
     fn glColorMask(r: bool, g: bool, b: bool, a: bool);
     fn glBlendFunc(sfactor: u32, dfactor: u32);
+    fn glBlendFuncSeparate(src_rgb: u32, dst_rgb: u32, src_alpha: u32, dst_alpha: u32);
+    fn glBlendEquationSeparate(mode_rgb: u32, mode_alpha: u32);
+    fn glActiveTexture(texture: u32);
     fn glEnableVertexAttribArray(index: u32);
+    fn glDisableVertexAttribArray(index: u32);
     fn glVertexAttribPointer(
         index: u32,
         size: i32,
         type_: u32,
         normalized: bool,
         stride: i32,
-        pointer: u32,
+        pointer: *const c_void,
     );
     fn glGenBuffers(n: i32, out: *mut u32);
     fn glBindBuffer(target: u32, buffer: u32);
     fn glBufferData(target: u32, size: isize, data: *const u8, usage: u32);
     fn glBufferSubData(target: u32, offset: i32, size: isize, data: *const u8);
     fn glDrawArrays(mode: u32, first: i32, count: i32);
-    fn glDrawElements(mode: u32, count: i32, type_: u32, indices: i32);
+    fn glDrawElements(mode: u32, count: i32, type_: u32, indices: *const c_void);
     fn glGenTextures(n: i32, out: *mut u32);
     fn glBindTexture(target: u32, tex: u32);
     fn glDeleteTextures(n: i32, textures: *const u32);
@@ -129,6 +262,16 @@ extern "C" {
         type_: u32,
         pixels: *const u8,
     );
+    fn glCompressedTexImage2D(
+        target: u32,
+        level: i32,
+        internalformat: u32,
+        width: i32,
+        height: i32,
+        border: i32,
+        image_size: i32,
+        data: *const u8,
+    );
     fn glTexParameteri(target: u32, pname: u32, param: i32);
     fn glGenFramebuffers(n: i32, out: *mut u32);
     fn glBindFramebuffer(target: u32, fbo: u32);
@@ -141,6 +284,15 @@ extern "C" {
         level: i32,
     );
     fn glCheckFramebufferStatus(target: u32) -> u32;
+    fn glGenRenderbuffers(n: i32, out: *mut u32);
+    fn glBindRenderbuffer(target: u32, renderbuffer: u32);
+    fn glDeleteRenderbuffers(n: i32, renderbuffers: *const u32);
+    fn glFramebufferRenderbuffer(
+        target: u32,
+        attachment: u32,
+        renderbuffertarget: u32,
+        renderbuffer: u32,
+    );
     fn glCreateShader(type_: u32) -> u32;
     fn glShaderSource(shader: u32, count: i32, string: *const *const u8, length: *const i32);
     fn glCompileShader(shader: u32);
@@ -157,136 +309,462 @@ extern "C" {
     fn glGetUniformLocation(program: u32, name: *const u8) -> i32;
     fn glUniform1f(location: i32, v0: f32);
     fn glUniform2f(location: i32, v0: f32, v1: f32);
+    fn glGetString(name: u32) -> *const std::ffi::c_char;
+    fn glIsEnabled(cap: u32) -> u8;
+    fn glGetIntegerv(pname: u32, params: *mut i32);
+    fn glGetFloatv(pname: u32, params: *mut f32);
+    fn glGetBooleanv(pname: u32, params: *mut u8);
+    fn glGetVertexAttribiv(index: u32, pname: u32, params: *mut i32);
+    fn glGetVertexAttribPointerv(index: u32, pname: u32, pointer: *mut *mut c_void);
+}
+
+/// The GL calls the renderer makes, named after their WebGL counterparts.
+///
+/// [`Context`] implements them on top of the real GL, and
+/// [`RecordingContext`](recording::RecordingContext) logs them without needing a GPU.
+#[allow(clippy::too_many_arguments)]
+pub trait Gl {
+    fn enable(&self, cap: u32);
+    fn disable(&self, cap: u32);
+    fn viewport(&self, x: i32, y: i32, width: i32, height: i32);
+    fn clear_color(&self, r: f32, g: f32, b: f32, a: f32);
+    fn clear(&self, mask: u32);
+    fn stencil_op(&self, fail: u32, zfail: u32, zpass: u32);
+    fn stencil_func(&self, func: u32, ref_: i32, mask: u32);
+    fn stencil_mask(&self, mask: u32);
+    fn color_mask(&self, r: bool, g: bool, b: bool, a: bool);
+    fn blend_func(&self, sfactor: u32, dfactor: u32);
+    fn blend_func_separate(&self, src_rgb: u32, dst_rgb: u32, src_alpha: u32, dst_alpha: u32);
+    fn blend_equation_separate(&self, mode_rgb: u32, mode_alpha: u32);
+    fn active_texture(&self, texture: u32);
+    fn enable_vertex_attrib_array(&self, index: u32);
+    fn disable_vertex_attrib_array(&self, index: u32);
+    fn vertex_attrib_pointer_with_i32(
+        &self,
+        index: u32,
+        size: i32,
+        type_: u32,
+        normalized: bool,
+        stride: i32,
+        offset: i32,
+    );
+    fn create_buffer(&self) -> Option<NativeBuffer>;
+    fn bind_buffer(&self, target: u32, buffer: Option<&NativeBuffer>);
+    fn buffer_data_with_u8_array(&self, target: u32, bytes: &[u8], usage: u32);
+    fn buffer_sub_data_with_i32_and_u8_array(&self, target: u32, offset: i32, bytes: &[u8]);
+    fn draw_arrays(&self, mode: u32, first: i32, count: i32);
+    fn draw_elements_with_i32(&self, mode: u32, count: i32, type_: u32, indices: i32);
+    fn create_texture(&self) -> Option<NativeTexture>;
+    fn bind_texture(&self, target: u32, texture: Option<&NativeTexture>);
+    fn delete_texture(&self, texture: Option<&NativeTexture>);
+    /// Only WebGL reports errors, for pixels which don’t match the size and format.
+    fn tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+        &self,
+        target: u32,
+        level: i32,
+        internalformat: i32,
+        width: i32,
+        height: i32,
+        border: i32,
+        format: u32,
+        type_: u32,
+        pixels: Option<&[u8]>,
+    ) -> Result<(), String>;
+    fn compressed_tex_image_2d_with_u8_array(
+        &self,
+        target: u32,
+        level: i32,
+        internalformat: u32,
+        width: i32,
+        height: i32,
+        border: i32,
+        data: &[u8],
+    );
+    fn tex_parameteri(&self, target: u32, pname: u32, param: i32);
+    fn create_framebuffer(&self) -> Option<NativeFramebuffer>;
+    fn bind_framebuffer(&self, target: u32, fbo: Option<&NativeFramebuffer>);
+    fn delete_framebuffer(&self, framebuffer: Option<&NativeFramebuffer>);
+    fn framebuffer_texture_2d(
+        &self,
+        target: u32,
+        attachment: u32,
+        textarget: u32,
+        texture: Option<&NativeTexture>,
+        level: i32,
+    );
+    fn check_framebuffer_status(&self, target: u32) -> u32;
+    fn create_renderbuffer(&self) -> Option<NativeRenderbuffer>;
+    fn bind_renderbuffer(&self, target: u32, renderbuffer: Option<&NativeRenderbuffer>);
+    fn delete_renderbuffer(&self, renderbuffer: Option<&NativeRenderbuffer>);
+    fn framebuffer_renderbuffer(
+        &self,
+        target: u32,
+        attachment: u32,
+        renderbuffertarget: u32,
+        renderbuffer: Option<&NativeRenderbuffer>,
+    );
+    fn create_shader(&self, type_: u32) -> Option<NativeShader>;
+    fn shader_source(&self, shader: &NativeShader, source: &str);
+    fn compile_shader(&self, shader: &NativeShader);
+    fn get_shader_parameter(&self, shader: &NativeShader, pname: u32) -> bool;
+    fn get_shader_info_log(&self, shader: &NativeShader) -> Option<String>;
+    fn delete_shader(&self, shader: Option<&NativeShader>);
+    fn create_program(&self) -> Option<NativeProgram>;
+    fn attach_shader(&self, program: &NativeProgram, shader: &NativeShader);
+    fn link_program(&self, program: &NativeProgram);
+    fn get_program_parameter(&self, program: &NativeProgram, pname: u32) -> bool;
+    fn get_program_info_log(&self, program: &NativeProgram) -> Option<String>;
+    fn use_program(&self, program: Option<&NativeProgram>);
+    fn delete_program(&self, program: Option<&NativeProgram>);
+    fn get_uniform_location(
+        &self,
+        program: &NativeProgram,
+        name: &str,
+    ) -> Option<NativeUniformLocation>;
+    fn uniform1f(&self, location: Option<&NativeUniformLocation>, v0: f32);
+    fn uniform2f(&self, location: Option<&NativeUniformLocation>, v0: f32, v1: f32);
+    /// Enables this extension if the context supports it, and returns whether it does.
+    ///
+    /// The name is the WebGL one, native GL names have a `GL_` prefix on top.
+    fn enable_extension(&self, name: &str) -> bool;
+
+    // Queries, to save the state of whoever else uses the context.
+
+    fn is_enabled(&self, cap: u32) -> bool;
+    /// For the parameters made of a single integer or enum.
+    fn get_parameter_i32(&self, pname: u32) -> i32;
+    fn get_parameter_viewport(&self) -> [i32; 4];
+    fn get_parameter_color_clear_value(&self) -> [f32; 4];
+    fn get_parameter_color_writemask(&self) -> [bool; 4];
+    fn get_parameter_program(&self) -> Option<NativeProgram>;
+    fn get_parameter_texture_binding_2d(&self) -> Option<NativeTexture>;
+    /// For `ARRAY_BUFFER_BINDING` and `ELEMENT_ARRAY_BUFFER_BINDING`.
+    fn get_parameter_buffer(&self, pname: u32) -> Option<NativeBuffer>;
+    fn get_parameter_framebuffer(&self) -> Option<NativeFramebuffer>;
+    fn get_parameter_renderbuffer(&self) -> Option<NativeRenderbuffer>;
+    /// Requires GLES 3.
+    fn get_parameter_read_framebuffer(&self) -> Option<NativeFramebuffer>;
+    /// Requires GLES 3.
+    fn get_parameter_vertex_array(&self) -> Option<NativeVertexArray>;
+    fn get_vertex_attrib_state(&self, index: u32) -> VertexAttrib;
+
+    // GLES 3 and WebGL 2
+
+    /// Whether this is a GLES 3 or WebGL 2 context, which the functions below require.
+    fn is_gles3(&self) -> bool;
+    fn create_vertex_array(&self) -> Option<NativeVertexArray>;
+    fn bind_vertex_array(&self, vao: Option<&NativeVertexArray>);
+    fn delete_vertex_array(&self, vao: Option<&NativeVertexArray>);
+    fn renderbuffer_storage_multisample(
+        &self,
+        target: u32,
+        samples: i32,
+        internalformat: u32,
+        width: i32,
+        height: i32,
+    );
+    fn blit_framebuffer(
+        &self,
+        src_x0: i32,
+        src_y0: i32,
+        src_x1: i32,
+        src_y1: i32,
+        dst_x0: i32,
+        dst_y0: i32,
+        dst_x1: i32,
+        dst_y1: i32,
+        mask: u32,
+        filter: u32,
+    );
 
     // GL_KHR_debug
+
     #[cfg(feature = "debug")]
-    fn glPushDebugGroup(source: u32, id: u32, length: i32, message: *const u8);
+    fn push_debug_group(&self, source: u32, id: u32, message: &str);
     #[cfg(feature = "debug")]
-    fn glPopDebugGroup();
+    fn pop_debug_group(&self);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod recording;
+
+/// Looks up a function which may only be exposed under the name of an extension.
+#[cfg(all(not(target_arch = "wasm32"), feature = "debug"))]
+fn load_either(
+    loader: &mut dyn FnMut(&str) -> *const c_void,
+    name: &str,
+    fallback: &str,
+) -> Option<*const c_void> {
+    [name, fallback]
+        .into_iter()
+        .map(loader)
+        .find(|pointer| !pointer.is_null())
 }
 
+/// The functions GLES 3 adds which the renderer uses.
 #[cfg(not(target_arch = "wasm32"))]
-pub struct Context;
+struct Gles3Functions {
+    gen_vertex_arrays: unsafe extern "system" fn(i32, *mut u32),
+    bind_vertex_array: unsafe extern "system" fn(u32),
+    delete_vertex_arrays: unsafe extern "system" fn(i32, *const u32),
+    renderbuffer_storage_multisample: unsafe extern "system" fn(u32, i32, u32, i32, i32),
+    #[allow(clippy::type_complexity)]
+    blit_framebuffer: unsafe extern "system" fn(i32, i32, i32, i32, i32, i32, i32, i32, u32, u32),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Gles3Functions {
+    /// Only succeeds on a GLES 3 context, on which every one of these functions exists.
+    fn load(
+        loader: &mut dyn FnMut(&str) -> *const c_void,
+        functions: &Functions,
+    ) -> Option<Gles3Functions> {
+        let version = unsafe { (functions.glGetString)(VERSION) };
+        if version.is_null() {
+            return None;
+        }
+        // “OpenGL ES 3.0 …”, desktop GL doesn’t accept the GLSL ES 3.00 shaders.
+        let version = unsafe { std::ffi::CStr::from_ptr(version) }.to_string_lossy();
+        let major = version.strip_prefix("OpenGL ES ")?.split('.').next()?;
+        if major.parse::<u32>().ok()? < 3 {
+            return None;
+        }
+        let mut load = |name| Some(loader(name)).filter(|pointer| !pointer.is_null());
+        let gen_vertex_arrays = load("glGenVertexArrays")?;
+        let bind_vertex_array = load("glBindVertexArray")?;
+        let delete_vertex_arrays = load("glDeleteVertexArrays")?;
+        let renderbuffer_storage_multisample = load("glRenderbufferStorageMultisample")?;
+        let blit_framebuffer = load("glBlitFramebuffer")?;
+        // SAFETY: these signatures come from the GLES 3 specification.
+        unsafe {
+            Some(Gles3Functions {
+                gen_vertex_arrays: core::mem::transmute::<
+                    *const c_void,
+                    unsafe extern "system" fn(i32, *mut u32),
+                >(gen_vertex_arrays),
+                bind_vertex_array: core::mem::transmute::<
+                    *const c_void,
+                    unsafe extern "system" fn(u32),
+                >(bind_vertex_array),
+                delete_vertex_arrays: core::mem::transmute::<
+                    *const c_void,
+                    unsafe extern "system" fn(i32, *const u32),
+                >(delete_vertex_arrays),
+                renderbuffer_storage_multisample: core::mem::transmute::<
+                    *const c_void,
+                    unsafe extern "system" fn(u32, i32, u32, i32, i32),
+                >(
+                    renderbuffer_storage_multisample
+                ),
+                blit_framebuffer: core::mem::transmute::<
+                    *const c_void,
+                    unsafe extern "system" fn(i32, i32, i32, i32, i32, i32, i32, i32, u32, u32),
+                >(blit_framebuffer),
+            })
+        }
+    }
+}
+
+/// A GLES 2 or 3 context, whose functions got loaded at runtime.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Context {
+    functions: Functions,
+    gles3: Option<Gles3Functions>,
+    #[cfg(feature = "debug")]
+    push_debug_group: Option<unsafe extern "system" fn(u32, u32, i32, *const u8)>,
+    #[cfg(feature = "debug")]
+    pop_debug_group: Option<unsafe extern "system" fn()>,
+}
 
 #[cfg(not(target_arch = "wasm32"))]
 impl Context {
-    pub fn new() -> Context {
-        Context
+    /// Resolves every GL function with this loader, typically `eglGetProcAddress` or the
+    /// `get_proc_address` of the windowing library, which has to return null for the
+    /// functions it doesn’t know.
+    ///
+    /// # Safety
+    ///
+    /// Every non-null pointer the loader returns has to be the GLES entry point of that name,
+    /// with the signature declared here, since the safe methods of the returned context call
+    /// them as such. The context they belong to has to be current, on the calling thread,
+    /// during this call and whenever the returned one gets used.
+    pub unsafe fn from_loader<F>(mut loader: F) -> Result<Context, String>
+    where
+        F: FnMut(&str) -> *const c_void,
+    {
+        let functions = Functions::load(&mut loader)?;
+        let gles3 = Gles3Functions::load(&mut loader, &functions);
+        Ok(Context {
+            functions,
+            gles3,
+            // These are optional, debug groups only help when a debugger is attached.
+            #[cfg(feature = "debug")]
+            push_debug_group: load_either(&mut loader, "glPushDebugGroup", "glPushDebugGroupKHR")
+                .map(|pointer| unsafe {
+                    core::mem::transmute::<
+                        *const c_void,
+                        unsafe extern "system" fn(u32, u32, i32, *const u8),
+                    >(pointer)
+                }),
+            #[cfg(feature = "debug")]
+            pop_debug_group: load_either(&mut loader, "glPopDebugGroup", "glPopDebugGroupKHR").map(
+                |pointer| unsafe {
+                    core::mem::transmute::<*const c_void, unsafe extern "system" fn()>(pointer)
+                },
+            ),
+        })
     }
 
-    pub fn enable(&self, cap: u32) {
-        unsafe { glEnable(cap) };
+    pub fn get_supported_extensions(&self) -> Vec<String> {
+        let extensions = unsafe { (self.functions.glGetString)(EXTENSIONS) };
+        if extensions.is_null() {
+            return Vec::new();
+        }
+        let extensions = unsafe { std::ffi::CStr::from_ptr(extensions) };
+        extensions
+            .to_string_lossy()
+            .split_ascii_whitespace()
+            .map(String::from)
+            .collect()
     }
+}
 
-    pub fn disable(&self, cap: u32) {
-        unsafe { glDisable(cap) };
+#[cfg(not(target_arch = "wasm32"))]
+impl Gl for Context {
+    fn enable(&self, cap: u32) {
+        unsafe { (self.functions.glEnable)(cap) };
     }
 
-    pub fn viewport(&self, x: i32, y: i32, width: i32, height: i32) {
-        unsafe { glViewport(x, y, width, height) };
+    fn disable(&self, cap: u32) {
+        unsafe { (self.functions.glDisable)(cap) };
     }
 
-    pub fn clear_color(&self, r: f32, g: f32, b: f32, a: f32) {
-        unsafe { glClearColor(r, g, b, a) };
+    fn viewport(&self, x: i32, y: i32, width: i32, height: i32) {
+        unsafe { (self.functions.glViewport)(x, y, width, height) };
     }
 
-    pub fn clear(&self, mask: u32) {
-        unsafe { glClear(mask) };
+    fn clear_color(&self, r: f32, g: f32, b: f32, a: f32) {
+        unsafe { (self.functions.glClearColor)(r, g, b, a) };
     }
 
-    pub fn stencil_op(&self, fail: u32, zfail: u32, zpass: u32) {
-        unsafe { glStencilOp(fail, zfail, zpass) };
+    fn clear(&self, mask: u32) {
+        unsafe { (self.functions.glClear)(mask) };
     }
 
-    pub fn stencil_func(&self, func: u32, ref_: i32, mask: u32) {
-        unsafe { glStencilFunc(func, ref_, mask) };
+    fn stencil_op(&self, fail: u32, zfail: u32, zpass: u32) {
+        unsafe { (self.functions.glStencilOp)(fail, zfail, zpass) };
     }
 
-    pub fn stencil_mask(&self, mask: u32) {
-        unsafe { glStencilMask(mask) };
+    fn stencil_func(&self, func: u32, ref_: i32, mask: u32) {
+        unsafe { (self.functions.glStencilFunc)(func, ref_, mask) };
     }
 
-    pub fn color_mask(&self, r: bool, g: bool, b: bool, a: bool) {
-        unsafe { glColorMask(r, g, b, a) };
+    fn stencil_mask(&self, mask: u32) {
+        unsafe { (self.functions.glStencilMask)(mask) };
     }
 
-    pub fn blend_func(&self, sfactor: u32, dfactor: u32) {
-        unsafe { glBlendFunc(sfactor, dfactor) };
+    fn color_mask(&self, r: bool, g: bool, b: bool, a: bool) {
+        unsafe { (self.functions.glColorMask)(r, g, b, a) };
     }
 
-    pub fn enable_vertex_attrib_array(&self, index: u32) {
-        unsafe { glEnableVertexAttribArray(index) };
+    fn blend_func(&self, sfactor: u32, dfactor: u32) {
+        unsafe { (self.functions.glBlendFunc)(sfactor, dfactor) };
     }
 
-    pub fn vertex_attrib_pointer_with_i32(
+    fn blend_func_separate(&self, src_rgb: u32, dst_rgb: u32, src_alpha: u32, dst_alpha: u32) {
+        unsafe { (self.functions.glBlendFuncSeparate)(src_rgb, dst_rgb, src_alpha, dst_alpha) };
+    }
+
+    fn blend_equation_separate(&self, mode_rgb: u32, mode_alpha: u32) {
+        unsafe { (self.functions.glBlendEquationSeparate)(mode_rgb, mode_alpha) };
+    }
+
+    fn active_texture(&self, texture: u32) {
+        unsafe { (self.functions.glActiveTexture)(texture) };
+    }
+
+    fn enable_vertex_attrib_array(&self, index: u32) {
+        unsafe { (self.functions.glEnableVertexAttribArray)(index) };
+    }
+
+    fn disable_vertex_attrib_array(&self, index: u32) {
+        unsafe { (self.functions.glDisableVertexAttribArray)(index) };
+    }
+
+    fn vertex_attrib_pointer_with_i32(
         &self,
         index: u32,
         size: i32,
         type_: u32,
         normalized: bool,
         stride: i32,
-        pointer: u32,
+        offset: i32,
     ) {
-        unsafe { glVertexAttribPointer(index, size, type_, normalized, stride, pointer) };
+        // With a buffer bound, the pointer is an offset into it.
+        let pointer = offset as usize as *const core::ffi::c_void;
+        unsafe {
+            (self.functions.glVertexAttribPointer)(index, size, type_, normalized, stride, pointer)
+        };
     }
 
-    pub fn create_buffer(&self) -> Option<NativeBuffer> {
+    fn create_buffer(&self) -> Option<NativeBuffer> {
         let mut buf = 0u32;
-        unsafe { glGenBuffers(1, &mut buf) };
+        unsafe { (self.functions.glGenBuffers)(1, &mut buf) };
         NonZeroU32::new(buf).map(NativeBuffer)
     }
 
-    pub fn bind_buffer(&self, target: u32, buffer: Option<&NativeBuffer>) {
+    fn bind_buffer(&self, target: u32, buffer: Option<&NativeBuffer>) {
         let buffer = match buffer {
             None => 0,
             Some(NativeBuffer(buffer)) => buffer.get(),
         };
-        unsafe { glBindBuffer(target, buffer) };
+        unsafe { (self.functions.glBindBuffer)(target, buffer) };
     }
 
-    pub fn buffer_data_with_u8_array(&self, target: u32, bytes: &[u8], usage: u32) {
+    fn buffer_data_with_u8_array(&self, target: u32, bytes: &[u8], usage: u32) {
         let size = bytes.len() as isize;
         let data = bytes.as_ptr();
-        unsafe { glBufferData(target, size, data, usage) };
+        unsafe { (self.functions.glBufferData)(target, size, data, usage) };
     }
 
-    pub fn buffer_sub_data_with_i32_and_u8_array(&self, target: u32, offset: i32, bytes: &[u8]) {
+    fn buffer_sub_data_with_i32_and_u8_array(&self, target: u32, offset: i32, bytes: &[u8]) {
         let size = bytes.len() as isize;
         let data = bytes.as_ptr();
-        unsafe { glBufferSubData(target, offset, size, data) };
+        unsafe { (self.functions.glBufferSubData)(target, offset, size, data) };
     }
 
-    pub fn draw_arrays(&self, mode: u32, first: i32, count: i32) {
-        unsafe { glDrawArrays(mode, first, count) };
+    fn draw_arrays(&self, mode: u32, first: i32, count: i32) {
+        unsafe { (self.functions.glDrawArrays)(mode, first, count) };
     }
 
-    pub fn draw_elements_with_i32(&self, mode: u32, count: i32, type_: u32, indices: i32) {
-        unsafe { glDrawElements(mode, count, type_, indices) };
+    fn draw_elements_with_i32(&self, mode: u32, count: i32, type_: u32, indices: i32) {
+        let indices = indices as usize as *const core::ffi::c_void;
+        unsafe { (self.functions.glDrawElements)(mode, count, type_, indices) };
     }
 
-    pub fn create_texture(&self) -> Option<NativeTexture> {
+    fn create_texture(&self) -> Option<NativeTexture> {
         let mut tex = 0u32;
-        unsafe { glGenTextures(1, &mut tex) };
+        unsafe { (self.functions.glGenTextures)(1, &mut tex) };
         NonZeroU32::new(tex).map(NativeTexture)
     }
 
-    pub fn bind_texture(&self, target: u32, texture: Option<&NativeTexture>) {
+    fn bind_texture(&self, target: u32, texture: Option<&NativeTexture>) {
         let texture = match texture {
             None => 0,
             Some(NativeTexture(texture)) => texture.get(),
         };
-        unsafe { glBindTexture(target, texture) };
+        unsafe { (self.functions.glBindTexture)(target, texture) };
     }
 
-    pub fn delete_texture(&self, texture: Option<&NativeTexture>) {
+    fn delete_texture(&self, texture: Option<&NativeTexture>) {
         let texture = match texture {
             None => 0,
             Some(NativeTexture(texture)) => texture.get(),
         };
-        unsafe { glDeleteTextures(1, &texture) };
+        unsafe { (self.functions.glDeleteTextures)(1, &texture) };
     }
 
-    pub fn tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+    fn tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
         &self,
         target: u32,
         level: i32,
@@ -297,13 +775,13 @@ impl Context {
         format: u32,
         type_: u32,
         pixels: Option<&[u8]>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), String> {
         let pixels = match pixels {
             None => null(),
             Some(pixels) => pixels.as_ptr(),
         };
         unsafe {
-            glTexImage2D(
+            (self.functions.glTexImage2D)(
                 target,
                 level,
                 internalformat,
@@ -318,33 +796,57 @@ impl Context {
         Ok(())
     }
 
-    pub fn tex_parameteri(&self, target: u32, pname: u32, param: i32) {
-        unsafe { glTexParameteri(target, pname, param) };
+    fn compressed_tex_image_2d_with_u8_array(
+        &self,
+        target: u32,
+        level: i32,
+        internalformat: u32,
+        width: i32,
+        height: i32,
+        border: i32,
+        data: &[u8],
+    ) {
+        unsafe {
+            (self.functions.glCompressedTexImage2D)(
+                target,
+                level,
+                internalformat,
+                width,
+                height,
+                border,
+                data.len() as i32,
+                data.as_ptr(),
+            )
+        };
+    }
+
+    fn tex_parameteri(&self, target: u32, pname: u32, param: i32) {
+        unsafe { (self.functions.glTexParameteri)(target, pname, param) };
     }
 
-    pub fn create_framebuffer(&self) -> Option<NativeFramebuffer> {
+    fn create_framebuffer(&self) -> Option<NativeFramebuffer> {
         let mut fbo = 0u32;
-        unsafe { glGenFramebuffers(1, &mut fbo) };
+        unsafe { (self.functions.glGenFramebuffers)(1, &mut fbo) };
         NonZeroU32::new(fbo).map(NativeFramebuffer)
     }
 
-    pub fn bind_framebuffer(&self, target: u32, fbo: Option<&NativeFramebuffer>) {
+    fn bind_framebuffer(&self, target: u32, fbo: Option<&NativeFramebuffer>) {
         let fbo = match fbo {
             None => 0,
             Some(NativeFramebuffer(fbo)) => fbo.get(),
         };
-        unsafe { glBindFramebuffer(target, fbo) };
+        unsafe { (self.functions.glBindFramebuffer)(target, fbo) };
     }
 
-    pub fn delete_framebuffer(&self, framebuffer: Option<&NativeFramebuffer>) {
+    fn delete_framebuffer(&self, framebuffer: Option<&NativeFramebuffer>) {
         let framebuffer = match framebuffer {
             None => 0,
             Some(NativeFramebuffer(framebuffer)) => framebuffer.get(),
         };
-        unsafe { glDeleteFramebuffers(1, &framebuffer) };
+        unsafe { (self.functions.glDeleteFramebuffers)(1, &framebuffer) };
     }
 
-    pub fn framebuffer_texture_2d(
+    fn framebuffer_texture_2d(
         &self,
         target: u32,
         attachment: u32,
@@ -356,137 +858,805 @@ impl Context {
             None => 0,
             Some(NativeTexture(texture)) => texture.get(),
         };
-        unsafe { glFramebufferTexture2D(target, attachment, textarget, texture, level) };
+        unsafe {
+            (self.functions.glFramebufferTexture2D)(target, attachment, textarget, texture, level)
+        };
+    }
+
+    fn create_renderbuffer(&self) -> Option<NativeRenderbuffer> {
+        let mut renderbuffer = 0u32;
+        unsafe { (self.functions.glGenRenderbuffers)(1, &mut renderbuffer) };
+        NonZeroU32::new(renderbuffer).map(NativeRenderbuffer)
+    }
+
+    fn bind_renderbuffer(&self, target: u32, renderbuffer: Option<&NativeRenderbuffer>) {
+        let renderbuffer = match renderbuffer {
+            None => 0,
+            Some(NativeRenderbuffer(renderbuffer)) => renderbuffer.get(),
+        };
+        unsafe { (self.functions.glBindRenderbuffer)(target, renderbuffer) };
+    }
+
+    fn delete_renderbuffer(&self, renderbuffer: Option<&NativeRenderbuffer>) {
+        let renderbuffer = match renderbuffer {
+            None => 0,
+            Some(NativeRenderbuffer(renderbuffer)) => renderbuffer.get(),
+        };
+        unsafe { (self.functions.glDeleteRenderbuffers)(1, &renderbuffer) };
+    }
+
+    fn framebuffer_renderbuffer(
+        &self,
+        target: u32,
+        attachment: u32,
+        renderbuffertarget: u32,
+        renderbuffer: Option<&NativeRenderbuffer>,
+    ) {
+        let renderbuffer = match renderbuffer {
+            None => 0,
+            Some(NativeRenderbuffer(renderbuffer)) => renderbuffer.get(),
+        };
+        unsafe {
+            (self.functions.glFramebufferRenderbuffer)(
+                target,
+                attachment,
+                renderbuffertarget,
+                renderbuffer,
+            )
+        };
     }
 
-    pub fn check_framebuffer_status(&self, target: u32) -> u32 {
-        unsafe { glCheckFramebufferStatus(target) }
+    fn check_framebuffer_status(&self, target: u32) -> u32 {
+        unsafe { (self.functions.glCheckFramebufferStatus)(target) }
     }
 
-    pub fn create_shader(&self, type_: u32) -> Option<NativeShader> {
-        let shader = unsafe { glCreateShader(type_) };
+    fn create_shader(&self, type_: u32) -> Option<NativeShader> {
+        let shader = unsafe { (self.functions.glCreateShader)(type_) };
         NonZeroU32::new(shader).map(NativeShader)
     }
 
-    pub fn shader_source(&self, shader: &NativeShader, source: &str) {
+    fn shader_source(&self, shader: &NativeShader, source: &str) {
         let count = 1;
         let length = source.len() as i32;
         let string = source.as_ptr();
-        unsafe { glShaderSource(shader.0.get(), count, &string, &length) };
+        unsafe { (self.functions.glShaderSource)(shader.0.get(), count, &string, &length) };
     }
 
-    pub fn compile_shader(&self, shader: &NativeShader) {
-        unsafe { glCompileShader(shader.0.get()) };
+    fn compile_shader(&self, shader: &NativeShader) {
+        unsafe { (self.functions.glCompileShader)(shader.0.get()) };
     }
 
-    pub fn get_shader_parameter(&self, shader: &NativeShader, pname: u32) -> bool {
+    fn get_shader_parameter(&self, shader: &NativeShader, pname: u32) -> bool {
         let mut status = 0i32;
-        unsafe { glGetShaderiv(shader.0.get(), pname, &mut status) };
+        unsafe { (self.functions.glGetShaderiv)(shader.0.get(), pname, &mut status) };
         status != 0
     }
 
-    pub fn get_shader_info_log(&self, shader: &NativeShader) -> Option<String> {
+    fn get_shader_info_log(&self, shader: &NativeShader) -> Option<String> {
         let mut length = 0i32;
-        unsafe { glGetShaderiv(shader.0.get(), INFO_LOG_LENGTH, &mut length) };
+        unsafe { (self.functions.glGetShaderiv)(shader.0.get(), INFO_LOG_LENGTH, &mut length) };
         if length > 0 {
             let mut log = String::with_capacity(length as usize);
-            unsafe { glGetShaderInfoLog(shader.0.get(), length, &mut length, log.as_mut_ptr()) };
+            unsafe {
+                (self.functions.glGetShaderInfoLog)(
+                    shader.0.get(),
+                    length,
+                    &mut length,
+                    log.as_mut_ptr(),
+                )
+            };
             Some(log)
         } else {
             None
         }
     }
 
-    pub fn delete_shader(&self, shader: Option<&NativeShader>) {
+    fn delete_shader(&self, shader: Option<&NativeShader>) {
         let shader = match shader {
             None => 0,
             Some(NativeShader(shader)) => shader.get(),
         };
-        unsafe { glDeleteShader(shader) };
+        unsafe { (self.functions.glDeleteShader)(shader) };
     }
 
-    pub fn create_program(&self) -> Option<NativeProgram> {
-        let program = unsafe { glCreateProgram() };
+    fn create_program(&self) -> Option<NativeProgram> {
+        let program = unsafe { (self.functions.glCreateProgram)() };
         NonZeroU32::new(program).map(NativeProgram)
     }
 
-    pub fn attach_shader(&self, program: &NativeProgram, shader: &NativeShader) {
-        unsafe { glAttachShader(program.0.get(), shader.0.get()) };
+    fn attach_shader(&self, program: &NativeProgram, shader: &NativeShader) {
+        unsafe { (self.functions.glAttachShader)(program.0.get(), shader.0.get()) };
     }
 
-    pub fn link_program(&self, program: &NativeProgram) {
-        unsafe { glLinkProgram(program.0.get()) };
+    fn link_program(&self, program: &NativeProgram) {
+        unsafe { (self.functions.glLinkProgram)(program.0.get()) };
     }
 
-    pub fn get_program_parameter(&self, program: &NativeProgram, pname: u32) -> bool {
+    fn get_program_parameter(&self, program: &NativeProgram, pname: u32) -> bool {
         let mut status = 0i32;
-        unsafe { glGetProgramiv(program.0.get(), pname, &mut status) };
+        unsafe { (self.functions.glGetProgramiv)(program.0.get(), pname, &mut status) };
         status != 0
     }
 
-    pub fn get_program_info_log(&self, program: &NativeProgram) -> Option<String> {
+    fn get_program_info_log(&self, program: &NativeProgram) -> Option<String> {
         let mut length = 0i32;
-        unsafe { glGetProgramiv(program.0.get(), INFO_LOG_LENGTH, &mut length) };
+        unsafe { (self.functions.glGetProgramiv)(program.0.get(), INFO_LOG_LENGTH, &mut length) };
         if length > 0 {
             let mut log = String::with_capacity(length as usize);
-            unsafe { glGetProgramInfoLog(program.0.get(), length, &mut length, log.as_mut_ptr()) };
+            unsafe {
+                (self.functions.glGetProgramInfoLog)(
+                    program.0.get(),
+                    length,
+                    &mut length,
+                    log.as_mut_ptr(),
+                )
+            };
             Some(log)
         } else {
             None
         }
     }
 
-    pub fn use_program(&self, program: Option<&NativeProgram>) {
+    fn use_program(&self, program: Option<&NativeProgram>) {
         let program = match program {
             None => 0,
             Some(NativeProgram(program)) => program.get(),
         };
-        unsafe { glUseProgram(program) };
+        unsafe { (self.functions.glUseProgram)(program) };
     }
 
-    pub fn delete_program(&self, program: Option<&NativeProgram>) {
+    fn delete_program(&self, program: Option<&NativeProgram>) {
         let program = match program {
             None => 0,
             Some(NativeProgram(program)) => program.get(),
         };
-        unsafe { glDeleteProgram(program) };
+        unsafe { (self.functions.glDeleteProgram)(program) };
     }
 
-    pub fn get_uniform_location(
+    fn get_uniform_location(
         &self,
         program: &NativeProgram,
         name: &str,
     ) -> Option<NativeUniformLocation> {
         let name = CString::new(name).unwrap();
-        let location = unsafe { glGetUniformLocation(program.0.get(), name.as_ptr() as *const _) };
+        let location = unsafe {
+            (self.functions.glGetUniformLocation)(program.0.get(), name.as_ptr() as *const _)
+        };
         NonZeroI32::new(location).map(NativeUniformLocation)
     }
 
-    pub fn uniform1f(&self, location: Option<&NativeUniformLocation>, v0: f32) {
+    fn uniform1f(&self, location: Option<&NativeUniformLocation>, v0: f32) {
         let location = match location {
             None => 0,
             Some(NativeUniformLocation(location)) => location.get(),
         };
-        unsafe { glUniform1f(location, v0) };
+        unsafe { (self.functions.glUniform1f)(location, v0) };
     }
 
-    pub fn uniform2f(&self, location: Option<&NativeUniformLocation>, v0: f32, v1: f32) {
+    fn uniform2f(&self, location: Option<&NativeUniformLocation>, v0: f32, v1: f32) {
         let location = match location {
             None => 0,
             Some(NativeUniformLocation(location)) => location.get(),
         };
-        unsafe { glUniform2f(location, v0, v1) };
+        unsafe { (self.functions.glUniform2f)(location, v0, v1) };
+    }
+
+    fn enable_extension(&self, name: &str) -> bool {
+        // Everything supported is always enabled on native GL.
+        self.get_supported_extensions()
+            .iter()
+            .any(|extension| extension.strip_prefix("GL_") == Some(name))
+    }
+
+    fn is_enabled(&self, cap: u32) -> bool {
+        unsafe { (self.functions.glIsEnabled)(cap) != 0 }
+    }
+
+    fn get_parameter_i32(&self, pname: u32) -> i32 {
+        let mut value = 0i32;
+        unsafe { (self.functions.glGetIntegerv)(pname, &mut value) };
+        value
+    }
+
+    fn get_parameter_viewport(&self) -> [i32; 4] {
+        let mut viewport = [0i32; 4];
+        unsafe { (self.functions.glGetIntegerv)(VIEWPORT, viewport.as_mut_ptr()) };
+        viewport
+    }
+
+    fn get_parameter_color_clear_value(&self) -> [f32; 4] {
+        let mut color = [0f32; 4];
+        unsafe { (self.functions.glGetFloatv)(COLOR_CLEAR_VALUE, color.as_mut_ptr()) };
+        color
+    }
+
+    fn get_parameter_color_writemask(&self) -> [bool; 4] {
+        let mut mask = [0u8; 4];
+        unsafe { (self.functions.glGetBooleanv)(COLOR_WRITEMASK, mask.as_mut_ptr()) };
+        mask.map(|component| component != 0)
+    }
+
+    fn get_parameter_program(&self) -> Option<NativeProgram> {
+        NonZeroU32::new(self.get_parameter_i32(CURRENT_PROGRAM) as u32).map(NativeProgram)
+    }
+
+    fn get_parameter_texture_binding_2d(&self) -> Option<NativeTexture> {
+        NonZeroU32::new(self.get_parameter_i32(TEXTURE_BINDING_2D) as u32).map(NativeTexture)
+    }
+
+    fn get_parameter_buffer(&self, pname: u32) -> Option<NativeBuffer> {
+        NonZeroU32::new(self.get_parameter_i32(pname) as u32).map(NativeBuffer)
+    }
+
+    fn get_parameter_framebuffer(&self) -> Option<NativeFramebuffer> {
+        NonZeroU32::new(self.get_parameter_i32(FRAMEBUFFER_BINDING) as u32).map(NativeFramebuffer)
+    }
+
+    fn get_parameter_renderbuffer(&self) -> Option<NativeRenderbuffer> {
+        NonZeroU32::new(self.get_parameter_i32(RENDERBUFFER_BINDING) as u32).map(NativeRenderbuffer)
+    }
+
+    fn get_parameter_read_framebuffer(&self) -> Option<NativeFramebuffer> {
+        NonZeroU32::new(self.get_parameter_i32(READ_FRAMEBUFFER_BINDING) as u32)
+            .map(NativeFramebuffer)
+    }
+
+    fn get_parameter_vertex_array(&self) -> Option<NativeVertexArray> {
+        NonZeroU32::new(self.get_parameter_i32(VERTEX_ARRAY_BINDING) as u32).map(NativeVertexArray)
+    }
+
+    fn get_vertex_attrib_state(&self, index: u32) -> VertexAttrib {
+        let get = |pname| {
+            let mut value = 0i32;
+            unsafe { (self.functions.glGetVertexAttribiv)(index, pname, &mut value) };
+            value
+        };
+        let mut pointer = core::ptr::null_mut();
+        unsafe {
+            (self.functions.glGetVertexAttribPointerv)(
+                index,
+                VERTEX_ATTRIB_ARRAY_POINTER,
+                &mut pointer,
+            )
+        };
+        VertexAttrib {
+            enabled: get(VERTEX_ATTRIB_ARRAY_ENABLED) != 0,
+            buffer: NonZeroU32::new(get(VERTEX_ATTRIB_ARRAY_BUFFER_BINDING) as u32)
+                .map(NativeBuffer),
+            size: get(VERTEX_ATTRIB_ARRAY_SIZE),
+            type_: get(VERTEX_ATTRIB_ARRAY_TYPE) as u32,
+            normalized: get(VERTEX_ATTRIB_ARRAY_NORMALIZED) != 0,
+            stride: get(VERTEX_ATTRIB_ARRAY_STRIDE),
+            offset: pointer as usize as i32,
+        }
+    }
+
+    fn is_gles3(&self) -> bool {
+        self.gles3.is_some()
+    }
+
+    fn create_vertex_array(&self) -> Option<NativeVertexArray> {
+        let gles3 = self.gles3.as_ref()?;
+        let mut vao = 0u32;
+        unsafe { (gles3.gen_vertex_arrays)(1, &mut vao) };
+        NonZeroU32::new(vao).map(NativeVertexArray)
+    }
+
+    fn bind_vertex_array(&self, vao: Option<&NativeVertexArray>) {
+        let gles3 = self.gles3.as_ref().expect("Vertex arrays require GLES 3");
+        let vao = match vao {
+            None => 0,
+            Some(NativeVertexArray(vao)) => vao.get(),
+        };
+        unsafe { (gles3.bind_vertex_array)(vao) };
+    }
+
+    fn delete_vertex_array(&self, vao: Option<&NativeVertexArray>) {
+        let gles3 = self.gles3.as_ref().expect("Vertex arrays require GLES 3");
+        let vao = match vao {
+            None => 0,
+            Some(NativeVertexArray(vao)) => vao.get(),
+        };
+        unsafe { (gles3.delete_vertex_arrays)(1, &vao) };
+    }
+
+    fn renderbuffer_storage_multisample(
+        &self,
+        target: u32,
+        samples: i32,
+        internalformat: u32,
+        width: i32,
+        height: i32,
+    ) {
+        let gles3 = self.gles3.as_ref().expect("Multisampling requires GLES 3");
+        unsafe {
+            (gles3.renderbuffer_storage_multisample)(target, samples, internalformat, width, height)
+        };
+    }
+
+    fn blit_framebuffer(
+        &self,
+        src_x0: i32,
+        src_y0: i32,
+        src_x1: i32,
+        src_y1: i32,
+        dst_x0: i32,
+        dst_y0: i32,
+        dst_x1: i32,
+        dst_y1: i32,
+        mask: u32,
+        filter: u32,
+    ) {
+        let gles3 = self.gles3.as_ref().expect("Blitting requires GLES 3");
+        unsafe {
+            (gles3.blit_framebuffer)(
+                src_x0, src_y0, src_x1, src_y1, dst_x0, dst_y0, dst_x1, dst_y1, mask, filter,
+            )
+        };
     }
 
     // GL_KHR_debug
 
     #[cfg(feature = "debug")]
-    pub fn push_debug_group(&self, source: u32, id: u32, message: &str) {
-        unsafe { glPushDebugGroup(source, id, message.len() as i32, message.as_ptr()) };
+    fn push_debug_group(&self, source: u32, id: u32, message: &str) {
+        if let Some(push_debug_group) = self.push_debug_group {
+            unsafe { push_debug_group(source, id, message.len() as i32, message.as_ptr()) };
+        }
     }
 
     #[cfg(feature = "debug")]
-    pub fn pop_debug_group(&self) {
-        unsafe { glPopDebugGroup() };
+    fn pop_debug_group(&self) {
+        if let Some(pop_debug_group) = self.pop_debug_group {
+            unsafe { pop_debug_group() };
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Gl for Context {
+    fn enable(&self, cap: u32) {
+        Context::enable(self, cap);
+    }
+
+    fn disable(&self, cap: u32) {
+        Context::disable(self, cap);
+    }
+
+    fn viewport(&self, x: i32, y: i32, width: i32, height: i32) {
+        Context::viewport(self, x, y, width, height);
+    }
+
+    fn clear_color(&self, r: f32, g: f32, b: f32, a: f32) {
+        Context::clear_color(self, r, g, b, a);
+    }
+
+    fn clear(&self, mask: u32) {
+        Context::clear(self, mask);
+    }
+
+    fn stencil_op(&self, fail: u32, zfail: u32, zpass: u32) {
+        Context::stencil_op(self, fail, zfail, zpass);
+    }
+
+    fn stencil_func(&self, func: u32, ref_: i32, mask: u32) {
+        Context::stencil_func(self, func, ref_, mask);
+    }
+
+    fn stencil_mask(&self, mask: u32) {
+        Context::stencil_mask(self, mask);
+    }
+
+    fn color_mask(&self, r: bool, g: bool, b: bool, a: bool) {
+        Context::color_mask(self, r, g, b, a);
+    }
+
+    fn blend_func(&self, sfactor: u32, dfactor: u32) {
+        Context::blend_func(self, sfactor, dfactor);
+    }
+
+    fn blend_func_separate(&self, src_rgb: u32, dst_rgb: u32, src_alpha: u32, dst_alpha: u32) {
+        Context::blend_func_separate(self, src_rgb, dst_rgb, src_alpha, dst_alpha);
+    }
+
+    fn blend_equation_separate(&self, mode_rgb: u32, mode_alpha: u32) {
+        Context::blend_equation_separate(self, mode_rgb, mode_alpha);
+    }
+
+    fn active_texture(&self, texture: u32) {
+        Context::active_texture(self, texture);
+    }
+
+    fn enable_vertex_attrib_array(&self, index: u32) {
+        Context::enable_vertex_attrib_array(self, index);
+    }
+
+    fn disable_vertex_attrib_array(&self, index: u32) {
+        Context::disable_vertex_attrib_array(self, index);
+    }
+
+    fn vertex_attrib_pointer_with_i32(
+        &self,
+        index: u32,
+        size: i32,
+        type_: u32,
+        normalized: bool,
+        stride: i32,
+        offset: i32,
+    ) {
+        Context::vertex_attrib_pointer_with_i32(
+            self, index, size, type_, normalized, stride, offset,
+        );
+    }
+
+    fn create_buffer(&self) -> Option<NativeBuffer> {
+        Context::create_buffer(self)
+    }
+
+    fn bind_buffer(&self, target: u32, buffer: Option<&NativeBuffer>) {
+        Context::bind_buffer(self, target, buffer);
+    }
+
+    fn buffer_data_with_u8_array(&self, target: u32, bytes: &[u8], usage: u32) {
+        Context::buffer_data_with_u8_array(self, target, bytes, usage);
+    }
+
+    fn buffer_sub_data_with_i32_and_u8_array(&self, target: u32, offset: i32, bytes: &[u8]) {
+        Context::buffer_sub_data_with_i32_and_u8_array(self, target, offset, bytes);
+    }
+
+    fn draw_arrays(&self, mode: u32, first: i32, count: i32) {
+        Context::draw_arrays(self, mode, first, count);
+    }
+
+    fn draw_elements_with_i32(&self, mode: u32, count: i32, type_: u32, indices: i32) {
+        Context::draw_elements_with_i32(self, mode, count, type_, indices);
+    }
+
+    fn create_texture(&self) -> Option<NativeTexture> {
+        Context::create_texture(self)
+    }
+
+    fn bind_texture(&self, target: u32, texture: Option<&NativeTexture>) {
+        Context::bind_texture(self, target, texture);
+    }
+
+    fn delete_texture(&self, texture: Option<&NativeTexture>) {
+        Context::delete_texture(self, texture);
+    }
+
+    fn tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+        &self,
+        target: u32,
+        level: i32,
+        internalformat: i32,
+        width: i32,
+        height: i32,
+        border: i32,
+        format: u32,
+        type_: u32,
+        pixels: Option<&[u8]>,
+    ) -> Result<(), String> {
+        Context::tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+            self,
+            target,
+            level,
+            internalformat,
+            width,
+            height,
+            border,
+            format,
+            type_,
+            pixels,
+        )
+        .map_err(|err| format!("{err:?}"))
+    }
+
+    fn compressed_tex_image_2d_with_u8_array(
+        &self,
+        target: u32,
+        level: i32,
+        internalformat: u32,
+        width: i32,
+        height: i32,
+        border: i32,
+        data: &[u8],
+    ) {
+        Context::compressed_tex_image_2d_with_u8_array(
+            self,
+            target,
+            level,
+            internalformat,
+            width,
+            height,
+            border,
+            data,
+        );
+    }
+
+    fn tex_parameteri(&self, target: u32, pname: u32, param: i32) {
+        Context::tex_parameteri(self, target, pname, param);
+    }
+
+    fn create_framebuffer(&self) -> Option<NativeFramebuffer> {
+        Context::create_framebuffer(self)
+    }
+
+    fn bind_framebuffer(&self, target: u32, fbo: Option<&NativeFramebuffer>) {
+        Context::bind_framebuffer(self, target, fbo);
+    }
+
+    fn delete_framebuffer(&self, framebuffer: Option<&NativeFramebuffer>) {
+        Context::delete_framebuffer(self, framebuffer);
+    }
+
+    fn framebuffer_texture_2d(
+        &self,
+        target: u32,
+        attachment: u32,
+        textarget: u32,
+        texture: Option<&NativeTexture>,
+        level: i32,
+    ) {
+        Context::framebuffer_texture_2d(self, target, attachment, textarget, texture, level);
+    }
+
+    fn check_framebuffer_status(&self, target: u32) -> u32 {
+        Context::check_framebuffer_status(self, target)
+    }
+
+    fn create_renderbuffer(&self) -> Option<NativeRenderbuffer> {
+        Context::create_renderbuffer(self)
+    }
+
+    fn bind_renderbuffer(&self, target: u32, renderbuffer: Option<&NativeRenderbuffer>) {
+        Context::bind_renderbuffer(self, target, renderbuffer);
+    }
+
+    fn delete_renderbuffer(&self, renderbuffer: Option<&NativeRenderbuffer>) {
+        Context::delete_renderbuffer(self, renderbuffer);
+    }
+
+    fn framebuffer_renderbuffer(
+        &self,
+        target: u32,
+        attachment: u32,
+        renderbuffertarget: u32,
+        renderbuffer: Option<&NativeRenderbuffer>,
+    ) {
+        Context::framebuffer_renderbuffer(
+            self,
+            target,
+            attachment,
+            renderbuffertarget,
+            renderbuffer,
+        );
+    }
+
+    fn create_shader(&self, type_: u32) -> Option<NativeShader> {
+        Context::create_shader(self, type_)
+    }
+
+    fn shader_source(&self, shader: &NativeShader, source: &str) {
+        Context::shader_source(self, shader, source);
+    }
+
+    fn compile_shader(&self, shader: &NativeShader) {
+        Context::compile_shader(self, shader);
+    }
+
+    fn get_shader_parameter(&self, shader: &NativeShader, pname: u32) -> bool {
+        Context::get_shader_parameter(self, shader, pname)
+            .as_bool()
+            .unwrap_or(false)
+    }
+
+    fn get_shader_info_log(&self, shader: &NativeShader) -> Option<String> {
+        Context::get_shader_info_log(self, shader)
+    }
+
+    fn delete_shader(&self, shader: Option<&NativeShader>) {
+        Context::delete_shader(self, shader);
     }
+
+    fn create_program(&self) -> Option<NativeProgram> {
+        Context::create_program(self)
+    }
+
+    fn attach_shader(&self, program: &NativeProgram, shader: &NativeShader) {
+        Context::attach_shader(self, program, shader);
+    }
+
+    fn link_program(&self, program: &NativeProgram) {
+        Context::link_program(self, program);
+    }
+
+    fn get_program_parameter(&self, program: &NativeProgram, pname: u32) -> bool {
+        Context::get_program_parameter(self, program, pname)
+            .as_bool()
+            .unwrap_or(false)
+    }
+
+    fn get_program_info_log(&self, program: &NativeProgram) -> Option<String> {
+        Context::get_program_info_log(self, program)
+    }
+
+    fn use_program(&self, program: Option<&NativeProgram>) {
+        Context::use_program(self, program);
+    }
+
+    fn delete_program(&self, program: Option<&NativeProgram>) {
+        Context::delete_program(self, program);
+    }
+
+    fn get_uniform_location(
+        &self,
+        program: &NativeProgram,
+        name: &str,
+    ) -> Option<NativeUniformLocation> {
+        Context::get_uniform_location(self, program, name)
+    }
+
+    fn uniform1f(&self, location: Option<&NativeUniformLocation>, v0: f32) {
+        Context::uniform1f(self, location, v0);
+    }
+
+    fn uniform2f(&self, location: Option<&NativeUniformLocation>, v0: f32, v1: f32) {
+        Context::uniform2f(self, location, v0, v1);
+    }
+
+    fn enable_extension(&self, name: &str) -> bool {
+        matches!(self.get_extension(name), Ok(Some(_)))
+    }
+
+    fn is_enabled(&self, cap: u32) -> bool {
+        Context::is_enabled(self, cap)
+    }
+
+    fn get_parameter_i32(&self, pname: u32) -> i32 {
+        // Enums and integers are both plain numbers in JavaScript.
+        self.get_parameter(pname)
+            .ok()
+            .and_then(|value| value.as_f64())
+            .unwrap_or(0.) as i32
+    }
+
+    fn get_parameter_viewport(&self) -> [i32; 4] {
+        let mut viewport = [0i32; 4];
+        if let Ok(value) = self.get_parameter(VIEWPORT) {
+            js_sys::Int32Array::from(value).copy_to(&mut viewport);
+        }
+        viewport
+    }
+
+    fn get_parameter_color_clear_value(&self) -> [f32; 4] {
+        let mut color = [0f32; 4];
+        if let Ok(value) = self.get_parameter(COLOR_CLEAR_VALUE) {
+            js_sys::Float32Array::from(value).copy_to(&mut color);
+        }
+        color
+    }
+
+    fn get_parameter_color_writemask(&self) -> [bool; 4] {
+        match self.get_parameter(COLOR_WRITEMASK) {
+            Ok(value) => {
+                let mask = js_sys::Array::from(&value);
+                [0, 1, 2, 3].map(|i| mask.get(i).as_bool().unwrap_or(true))
+            }
+            Err(_) => [true; 4],
+        }
+    }
+
+    fn get_parameter_program(&self) -> Option<NativeProgram> {
+        self.get_parameter(CURRENT_PROGRAM).ok()?.dyn_into().ok()
+    }
+
+    fn get_parameter_texture_binding_2d(&self) -> Option<NativeTexture> {
+        self.get_parameter(TEXTURE_BINDING_2D).ok()?.dyn_into().ok()
+    }
+
+    fn get_parameter_buffer(&self, pname: u32) -> Option<NativeBuffer> {
+        self.get_parameter(pname).ok()?.dyn_into().ok()
+    }
+
+    fn get_parameter_framebuffer(&self) -> Option<NativeFramebuffer> {
+        self.get_parameter(FRAMEBUFFER_BINDING)
+            .ok()?
+            .dyn_into()
+            .ok()
+    }
+
+    fn get_parameter_renderbuffer(&self) -> Option<NativeRenderbuffer> {
+        self.get_parameter(RENDERBUFFER_BINDING)
+            .ok()?
+            .dyn_into()
+            .ok()
+    }
+
+    fn get_parameter_read_framebuffer(&self) -> Option<NativeFramebuffer> {
+        self.get_parameter(READ_FRAMEBUFFER_BINDING)
+            .ok()?
+            .dyn_into()
+            .ok()
+    }
+
+    fn get_parameter_vertex_array(&self) -> Option<NativeVertexArray> {
+        self.get_parameter(VERTEX_ARRAY_BINDING)
+            .ok()?
+            .dyn_into()
+            .ok()
+    }
+
+    fn get_vertex_attrib_state(&self, index: u32) -> VertexAttrib {
+        let get = |pname| self.get_vertex_attrib(index, pname).unwrap_or_default();
+        let number = |pname| get(pname).as_f64().unwrap_or(0.);
+        VertexAttrib {
+            enabled: get(VERTEX_ATTRIB_ARRAY_ENABLED).as_bool().unwrap_or(false),
+            buffer: get(VERTEX_ATTRIB_ARRAY_BUFFER_BINDING).dyn_into().ok(),
+            size: number(VERTEX_ATTRIB_ARRAY_SIZE) as i32,
+            type_: number(VERTEX_ATTRIB_ARRAY_TYPE) as u32,
+            normalized: get(VERTEX_ATTRIB_ARRAY_NORMALIZED)
+                .as_bool()
+                .unwrap_or(false),
+            stride: number(VERTEX_ATTRIB_ARRAY_STRIDE) as i32,
+            offset: self.get_vertex_attrib_offset(index, VERTEX_ATTRIB_ARRAY_POINTER) as i32,
+        }
+    }
+
+    fn is_gles3(&self) -> bool {
+        self.is_instance_of::<web_sys::WebGl2RenderingContext>()
+    }
+
+    fn create_vertex_array(&self) -> Option<NativeVertexArray> {
+        self.dyn_ref::<web_sys::WebGl2RenderingContext>()?
+            .create_vertex_array()
+    }
+
+    fn bind_vertex_array(&self, vao: Option<&NativeVertexArray>) {
+        self.unchecked_ref::<web_sys::WebGl2RenderingContext>()
+            .bind_vertex_array(vao);
+    }
+
+    fn delete_vertex_array(&self, vao: Option<&NativeVertexArray>) {
+        self.unchecked_ref::<web_sys::WebGl2RenderingContext>()
+            .delete_vertex_array(vao);
+    }
+
+    fn renderbuffer_storage_multisample(
+        &self,
+        target: u32,
+        samples: i32,
+        internalformat: u32,
+        width: i32,
+        height: i32,
+    ) {
+        self.unchecked_ref::<web_sys::WebGl2RenderingContext>()
+            .renderbuffer_storage_multisample(target, samples, internalformat, width, height);
+    }
+
+    fn blit_framebuffer(
+        &self,
+        src_x0: i32,
+        src_y0: i32,
+        src_x1: i32,
+        src_y1: i32,
+        dst_x0: i32,
+        dst_y0: i32,
+        dst_x1: i32,
+        dst_y1: i32,
+        mask: u32,
+        filter: u32,
+    ) {
+        self.unchecked_ref::<web_sys::WebGl2RenderingContext>()
+            .blit_framebuffer(
+                src_x0, src_y0, src_x1, src_y1, dst_x0, dst_y0, dst_x1, dst_y1, mask, filter,
+            );
+    }
+
+    // WebGL has no equivalent of GL_KHR_debug.
+
+    #[cfg(feature = "debug")]
+    fn push_debug_group(&self, _source: u32, _id: u32, _message: &str) {}
+
+    #[cfg(feature = "debug")]
+    fn pop_debug_group(&self) {}
 }
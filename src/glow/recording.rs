@@ -0,0 +1,845 @@
+// Copyright (c) 2023 Emmanuel Gil Peyrot <linkmauve@linkmauve.fr>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A context which records GL calls instead of executing them, so that the command stream of
+//! the renderer can be checked without a GPU.
+
+use super::*;
+use core::num::{NonZeroI32, NonZeroU32};
+use std::cell::{Cell, RefCell};
+
+/// One call made on a [`RecordingContext`], with objects identified by their name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Call {
+    Enable(u32),
+    Disable(u32),
+    Viewport {
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    },
+    ClearColor([f32; 4]),
+    Clear(u32),
+    StencilOp {
+        fail: u32,
+        zfail: u32,
+        zpass: u32,
+    },
+    StencilFunc {
+        func: u32,
+        ref_: i32,
+        mask: u32,
+    },
+    StencilMask(u32),
+    ColorMask([bool; 4]),
+    BlendFunc {
+        sfactor: u32,
+        dfactor: u32,
+    },
+    BlendFuncSeparate {
+        src_rgb: u32,
+        dst_rgb: u32,
+        src_alpha: u32,
+        dst_alpha: u32,
+    },
+    BlendEquationSeparate {
+        mode_rgb: u32,
+        mode_alpha: u32,
+    },
+    ActiveTexture(u32),
+    EnableVertexAttribArray(u32),
+    DisableVertexAttribArray(u32),
+    VertexAttribPointer {
+        index: u32,
+        size: i32,
+        type_: u32,
+        normalized: bool,
+        stride: i32,
+        offset: i32,
+    },
+    CreateBuffer(u32),
+    BindBuffer {
+        target: u32,
+        buffer: Option<u32>,
+    },
+    BufferData {
+        target: u32,
+        data: Vec<u8>,
+        usage: u32,
+    },
+    BufferSubData {
+        target: u32,
+        offset: i32,
+        data: Vec<u8>,
+    },
+    DrawArrays {
+        mode: u32,
+        first: i32,
+        count: i32,
+    },
+    DrawElements {
+        mode: u32,
+        count: i32,
+        type_: u32,
+        offset: i32,
+    },
+    CreateTexture(u32),
+    BindTexture {
+        target: u32,
+        texture: Option<u32>,
+    },
+    DeleteTexture(Option<u32>),
+    TexImage2d {
+        target: u32,
+        level: i32,
+        internalformat: i32,
+        width: i32,
+        height: i32,
+        format: u32,
+        type_: u32,
+        pixels: Option<Vec<u8>>,
+    },
+    CompressedTexImage2d {
+        target: u32,
+        level: i32,
+        internalformat: u32,
+        width: i32,
+        height: i32,
+        data: Vec<u8>,
+    },
+    TexParameteri {
+        target: u32,
+        pname: u32,
+        param: i32,
+    },
+    CreateFramebuffer(u32),
+    BindFramebuffer {
+        target: u32,
+        framebuffer: Option<u32>,
+    },
+    DeleteFramebuffer(Option<u32>),
+    FramebufferTexture2d {
+        target: u32,
+        attachment: u32,
+        textarget: u32,
+        texture: Option<u32>,
+        level: i32,
+    },
+    CheckFramebufferStatus(u32),
+    CreateRenderbuffer(u32),
+    BindRenderbuffer {
+        target: u32,
+        renderbuffer: Option<u32>,
+    },
+    DeleteRenderbuffer(Option<u32>),
+    FramebufferRenderbuffer {
+        target: u32,
+        attachment: u32,
+        renderbuffertarget: u32,
+        renderbuffer: Option<u32>,
+    },
+    CreateShader {
+        type_: u32,
+        shader: u32,
+    },
+    ShaderSource {
+        shader: u32,
+        source: String,
+    },
+    CompileShader(u32),
+    GetShaderParameter {
+        shader: u32,
+        pname: u32,
+    },
+    GetShaderInfoLog(u32),
+    DeleteShader(Option<u32>),
+    CreateProgram(u32),
+    AttachShader {
+        program: u32,
+        shader: u32,
+    },
+    LinkProgram(u32),
+    GetProgramParameter {
+        program: u32,
+        pname: u32,
+    },
+    GetProgramInfoLog(u32),
+    UseProgram(Option<u32>),
+    DeleteProgram(Option<u32>),
+    GetUniformLocation {
+        program: u32,
+        name: String,
+        location: i32,
+    },
+    Uniform1f {
+        location: Option<i32>,
+        v0: f32,
+    },
+    Uniform2f {
+        location: Option<i32>,
+        v0: f32,
+        v1: f32,
+    },
+    EnableExtension {
+        name: String,
+        supported: bool,
+    },
+    IsEnabled(u32),
+    GetParameter(u32),
+    GetVertexAttrib(u32),
+    CreateVertexArray(u32),
+    BindVertexArray(Option<u32>),
+    DeleteVertexArray(Option<u32>),
+    RenderbufferStorageMultisample {
+        target: u32,
+        samples: i32,
+        internalformat: u32,
+        width: i32,
+        height: i32,
+    },
+    BlitFramebuffer {
+        src: [i32; 4],
+        dst: [i32; 4],
+        mask: u32,
+        filter: u32,
+    },
+    #[cfg(feature = "debug")]
+    PushDebugGroup {
+        source: u32,
+        id: u32,
+        message: String,
+    },
+    #[cfg(feature = "debug")]
+    PopDebugGroup,
+}
+
+/// Logs every call made on it, succeeding at everything: shaders compile, programs link and
+/// framebuffers are complete. Queries return the initial state of a fresh context, whatever
+/// got called before.
+///
+/// Objects get names from a single counter starting at 1, so the same sequence of calls
+/// always gives the same names.
+#[derive(Debug)]
+pub struct RecordingContext {
+    calls: RefCell<Vec<Call>>,
+    next_name: Cell<u32>,
+    extensions: Vec<String>,
+    gles3: bool,
+}
+
+impl Default for RecordingContext {
+    fn default() -> RecordingContext {
+        RecordingContext::new()
+    }
+}
+
+impl RecordingContext {
+    /// A context without any extension, like the most basic GLES 2 implementation.
+    pub fn new() -> RecordingContext {
+        RecordingContext::with_extensions(&[])
+    }
+
+    /// A context supporting these extensions, with their WebGL names, such as
+    /// `OES_element_index_uint`.
+    pub fn with_extensions(extensions: &[&str]) -> RecordingContext {
+        RecordingContext {
+            calls: RefCell::new(Vec::new()),
+            next_name: Cell::new(1),
+            extensions: extensions.iter().map(|&name| String::from(name)).collect(),
+            gles3: false,
+        }
+    }
+
+    /// A GLES 3 context, supporting these extensions on top.
+    pub fn gles3(extensions: &[&str]) -> RecordingContext {
+        RecordingContext {
+            gles3: true,
+            ..RecordingContext::with_extensions(extensions)
+        }
+    }
+
+    /// The calls made so far.
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.borrow().clone()
+    }
+
+    /// Returns the calls made so far and forgets them, to look at a single frame for instance.
+    pub fn take_calls(&self) -> Vec<Call> {
+        std::mem::take(&mut self.calls.borrow_mut())
+    }
+
+    fn record(&self, call: Call) {
+        self.calls.borrow_mut().push(call);
+    }
+
+    /// What a fresh context returns for this single-valued parameter.
+    fn initial_parameter(pname: u32) -> i32 {
+        match pname {
+            BLEND_SRC_RGB | BLEND_SRC_ALPHA => ONE as i32,
+            BLEND_EQUATION_RGB | BLEND_EQUATION_ALPHA => FUNC_ADD as i32,
+            STENCIL_FUNC => ALWAYS as i32,
+            STENCIL_FAIL | STENCIL_PASS_DEPTH_FAIL | STENCIL_PASS_DEPTH_PASS => KEEP as i32,
+            STENCIL_VALUE_MASK | STENCIL_WRITEMASK => -1,
+            ACTIVE_TEXTURE => TEXTURE0 as i32,
+            MAX_SAMPLES => 4,
+            _ => 0,
+        }
+    }
+
+    fn gen_name(&self) -> NonZeroU32 {
+        let name = self.next_name.get();
+        self.next_name.set(name + 1);
+        NonZeroU32::new(name).unwrap()
+    }
+}
+
+impl Gl for RecordingContext {
+    fn enable(&self, cap: u32) {
+        self.record(Call::Enable(cap));
+    }
+
+    fn disable(&self, cap: u32) {
+        self.record(Call::Disable(cap));
+    }
+
+    fn viewport(&self, x: i32, y: i32, width: i32, height: i32) {
+        self.record(Call::Viewport {
+            x,
+            y,
+            width,
+            height,
+        });
+    }
+
+    fn clear_color(&self, r: f32, g: f32, b: f32, a: f32) {
+        self.record(Call::ClearColor([r, g, b, a]));
+    }
+
+    fn clear(&self, mask: u32) {
+        self.record(Call::Clear(mask));
+    }
+
+    fn stencil_op(&self, fail: u32, zfail: u32, zpass: u32) {
+        self.record(Call::StencilOp { fail, zfail, zpass });
+    }
+
+    fn stencil_func(&self, func: u32, ref_: i32, mask: u32) {
+        self.record(Call::StencilFunc { func, ref_, mask });
+    }
+
+    fn stencil_mask(&self, mask: u32) {
+        self.record(Call::StencilMask(mask));
+    }
+
+    fn color_mask(&self, r: bool, g: bool, b: bool, a: bool) {
+        self.record(Call::ColorMask([r, g, b, a]));
+    }
+
+    fn blend_func(&self, sfactor: u32, dfactor: u32) {
+        self.record(Call::BlendFunc { sfactor, dfactor });
+    }
+
+    fn blend_func_separate(&self, src_rgb: u32, dst_rgb: u32, src_alpha: u32, dst_alpha: u32) {
+        self.record(Call::BlendFuncSeparate {
+            src_rgb,
+            dst_rgb,
+            src_alpha,
+            dst_alpha,
+        });
+    }
+
+    fn blend_equation_separate(&self, mode_rgb: u32, mode_alpha: u32) {
+        self.record(Call::BlendEquationSeparate {
+            mode_rgb,
+            mode_alpha,
+        });
+    }
+
+    fn active_texture(&self, texture: u32) {
+        self.record(Call::ActiveTexture(texture));
+    }
+
+    fn enable_vertex_attrib_array(&self, index: u32) {
+        self.record(Call::EnableVertexAttribArray(index));
+    }
+
+    fn disable_vertex_attrib_array(&self, index: u32) {
+        self.record(Call::DisableVertexAttribArray(index));
+    }
+
+    fn vertex_attrib_pointer_with_i32(
+        &self,
+        index: u32,
+        size: i32,
+        type_: u32,
+        normalized: bool,
+        stride: i32,
+        offset: i32,
+    ) {
+        self.record(Call::VertexAttribPointer {
+            index,
+            size,
+            type_,
+            normalized,
+            stride,
+            offset,
+        });
+    }
+
+    fn create_buffer(&self) -> Option<NativeBuffer> {
+        let name = self.gen_name();
+        self.record(Call::CreateBuffer(name.get()));
+        Some(NativeBuffer(name))
+    }
+
+    fn bind_buffer(&self, target: u32, buffer: Option<&NativeBuffer>) {
+        self.record(Call::BindBuffer {
+            target,
+            buffer: buffer.map(|buffer| buffer.0.get()),
+        });
+    }
+
+    fn buffer_data_with_u8_array(&self, target: u32, bytes: &[u8], usage: u32) {
+        self.record(Call::BufferData {
+            target,
+            data: bytes.to_vec(),
+            usage,
+        });
+    }
+
+    fn buffer_sub_data_with_i32_and_u8_array(&self, target: u32, offset: i32, bytes: &[u8]) {
+        self.record(Call::BufferSubData {
+            target,
+            offset,
+            data: bytes.to_vec(),
+        });
+    }
+
+    fn draw_arrays(&self, mode: u32, first: i32, count: i32) {
+        self.record(Call::DrawArrays { mode, first, count });
+    }
+
+    fn draw_elements_with_i32(&self, mode: u32, count: i32, type_: u32, indices: i32) {
+        self.record(Call::DrawElements {
+            mode,
+            count,
+            type_,
+            offset: indices,
+        });
+    }
+
+    fn create_texture(&self) -> Option<NativeTexture> {
+        let name = self.gen_name();
+        self.record(Call::CreateTexture(name.get()));
+        Some(NativeTexture(name))
+    }
+
+    fn bind_texture(&self, target: u32, texture: Option<&NativeTexture>) {
+        self.record(Call::BindTexture {
+            target,
+            texture: texture.map(|texture| texture.0.get()),
+        });
+    }
+
+    fn delete_texture(&self, texture: Option<&NativeTexture>) {
+        self.record(Call::DeleteTexture(texture.map(|texture| texture.0.get())));
+    }
+
+    fn tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+        &self,
+        target: u32,
+        level: i32,
+        internalformat: i32,
+        width: i32,
+        height: i32,
+        _border: i32,
+        format: u32,
+        type_: u32,
+        pixels: Option<&[u8]>,
+    ) -> Result<(), String> {
+        self.record(Call::TexImage2d {
+            target,
+            level,
+            internalformat,
+            width,
+            height,
+            format,
+            type_,
+            pixels: pixels.map(<[u8]>::to_vec),
+        });
+        Ok(())
+    }
+
+    fn compressed_tex_image_2d_with_u8_array(
+        &self,
+        target: u32,
+        level: i32,
+        internalformat: u32,
+        width: i32,
+        height: i32,
+        _border: i32,
+        data: &[u8],
+    ) {
+        self.record(Call::CompressedTexImage2d {
+            target,
+            level,
+            internalformat,
+            width,
+            height,
+            data: data.to_vec(),
+        });
+    }
+
+    fn tex_parameteri(&self, target: u32, pname: u32, param: i32) {
+        self.record(Call::TexParameteri {
+            target,
+            pname,
+            param,
+        });
+    }
+
+    fn create_framebuffer(&self) -> Option<NativeFramebuffer> {
+        let name = self.gen_name();
+        self.record(Call::CreateFramebuffer(name.get()));
+        Some(NativeFramebuffer(name))
+    }
+
+    fn bind_framebuffer(&self, target: u32, fbo: Option<&NativeFramebuffer>) {
+        self.record(Call::BindFramebuffer {
+            target,
+            framebuffer: fbo.map(|fbo| fbo.0.get()),
+        });
+    }
+
+    fn delete_framebuffer(&self, framebuffer: Option<&NativeFramebuffer>) {
+        self.record(Call::DeleteFramebuffer(
+            framebuffer.map(|framebuffer| framebuffer.0.get()),
+        ));
+    }
+
+    fn framebuffer_texture_2d(
+        &self,
+        target: u32,
+        attachment: u32,
+        textarget: u32,
+        texture: Option<&NativeTexture>,
+        level: i32,
+    ) {
+        self.record(Call::FramebufferTexture2d {
+            target,
+            attachment,
+            textarget,
+            texture: texture.map(|texture| texture.0.get()),
+            level,
+        });
+    }
+
+    fn check_framebuffer_status(&self, target: u32) -> u32 {
+        self.record(Call::CheckFramebufferStatus(target));
+        FRAMEBUFFER_COMPLETE
+    }
+
+    fn create_renderbuffer(&self) -> Option<NativeRenderbuffer> {
+        let name = self.gen_name();
+        self.record(Call::CreateRenderbuffer(name.get()));
+        Some(NativeRenderbuffer(name))
+    }
+
+    fn bind_renderbuffer(&self, target: u32, renderbuffer: Option<&NativeRenderbuffer>) {
+        self.record(Call::BindRenderbuffer {
+            target,
+            renderbuffer: renderbuffer.map(|renderbuffer| renderbuffer.0.get()),
+        });
+    }
+
+    fn delete_renderbuffer(&self, renderbuffer: Option<&NativeRenderbuffer>) {
+        self.record(Call::DeleteRenderbuffer(
+            renderbuffer.map(|renderbuffer| renderbuffer.0.get()),
+        ));
+    }
+
+    fn framebuffer_renderbuffer(
+        &self,
+        target: u32,
+        attachment: u32,
+        renderbuffertarget: u32,
+        renderbuffer: Option<&NativeRenderbuffer>,
+    ) {
+        self.record(Call::FramebufferRenderbuffer {
+            target,
+            attachment,
+            renderbuffertarget,
+            renderbuffer: renderbuffer.map(|renderbuffer| renderbuffer.0.get()),
+        });
+    }
+
+    fn create_shader(&self, type_: u32) -> Option<NativeShader> {
+        let name = self.gen_name();
+        self.record(Call::CreateShader {
+            type_,
+            shader: name.get(),
+        });
+        Some(NativeShader(name))
+    }
+
+    fn shader_source(&self, shader: &NativeShader, source: &str) {
+        self.record(Call::ShaderSource {
+            shader: shader.0.get(),
+            source: String::from(source),
+        });
+    }
+
+    fn compile_shader(&self, shader: &NativeShader) {
+        self.record(Call::CompileShader(shader.0.get()));
+    }
+
+    fn get_shader_parameter(&self, shader: &NativeShader, pname: u32) -> bool {
+        self.record(Call::GetShaderParameter {
+            shader: shader.0.get(),
+            pname,
+        });
+        true
+    }
+
+    fn get_shader_info_log(&self, shader: &NativeShader) -> Option<String> {
+        self.record(Call::GetShaderInfoLog(shader.0.get()));
+        None
+    }
+
+    fn delete_shader(&self, shader: Option<&NativeShader>) {
+        self.record(Call::DeleteShader(shader.map(|shader| shader.0.get())));
+    }
+
+    fn create_program(&self) -> Option<NativeProgram> {
+        let name = self.gen_name();
+        self.record(Call::CreateProgram(name.get()));
+        Some(NativeProgram(name))
+    }
+
+    fn attach_shader(&self, program: &NativeProgram, shader: &NativeShader) {
+        self.record(Call::AttachShader {
+            program: program.0.get(),
+            shader: shader.0.get(),
+        });
+    }
+
+    fn link_program(&self, program: &NativeProgram) {
+        self.record(Call::LinkProgram(program.0.get()));
+    }
+
+    fn get_program_parameter(&self, program: &NativeProgram, pname: u32) -> bool {
+        self.record(Call::GetProgramParameter {
+            program: program.0.get(),
+            pname,
+        });
+        true
+    }
+
+    fn get_program_info_log(&self, program: &NativeProgram) -> Option<String> {
+        self.record(Call::GetProgramInfoLog(program.0.get()));
+        None
+    }
+
+    fn use_program(&self, program: Option<&NativeProgram>) {
+        self.record(Call::UseProgram(program.map(|program| program.0.get())));
+    }
+
+    fn delete_program(&self, program: Option<&NativeProgram>) {
+        self.record(Call::DeleteProgram(program.map(|program| program.0.get())));
+    }
+
+    fn get_uniform_location(
+        &self,
+        program: &NativeProgram,
+        name: &str,
+    ) -> Option<NativeUniformLocation> {
+        // Every uniform exists, since the shaders don’t actually get compiled.
+        let location = NonZeroI32::new(self.gen_name().get() as i32).unwrap();
+        self.record(Call::GetUniformLocation {
+            program: program.0.get(),
+            name: String::from(name),
+            location: location.get(),
+        });
+        Some(NativeUniformLocation(location))
+    }
+
+    fn uniform1f(&self, location: Option<&NativeUniformLocation>, v0: f32) {
+        self.record(Call::Uniform1f {
+            location: location.map(|location| location.0.get()),
+            v0,
+        });
+    }
+
+    fn uniform2f(&self, location: Option<&NativeUniformLocation>, v0: f32, v1: f32) {
+        self.record(Call::Uniform2f {
+            location: location.map(|location| location.0.get()),
+            v0,
+            v1,
+        });
+    }
+
+    fn enable_extension(&self, name: &str) -> bool {
+        let supported = self.extensions.iter().any(|extension| extension == name);
+        self.record(Call::EnableExtension {
+            name: String::from(name),
+            supported,
+        });
+        supported
+    }
+
+    fn is_enabled(&self, cap: u32) -> bool {
+        self.record(Call::IsEnabled(cap));
+        false
+    }
+
+    fn get_parameter_i32(&self, pname: u32) -> i32 {
+        self.record(Call::GetParameter(pname));
+        RecordingContext::initial_parameter(pname)
+    }
+
+    fn get_parameter_viewport(&self) -> [i32; 4] {
+        self.record(Call::GetParameter(VIEWPORT));
+        [0; 4]
+    }
+
+    fn get_parameter_color_clear_value(&self) -> [f32; 4] {
+        self.record(Call::GetParameter(COLOR_CLEAR_VALUE));
+        [0.; 4]
+    }
+
+    fn get_parameter_color_writemask(&self) -> [bool; 4] {
+        self.record(Call::GetParameter(COLOR_WRITEMASK));
+        [true; 4]
+    }
+
+    fn get_parameter_program(&self) -> Option<NativeProgram> {
+        self.record(Call::GetParameter(CURRENT_PROGRAM));
+        None
+    }
+
+    fn get_parameter_texture_binding_2d(&self) -> Option<NativeTexture> {
+        self.record(Call::GetParameter(TEXTURE_BINDING_2D));
+        None
+    }
+
+    fn get_parameter_buffer(&self, pname: u32) -> Option<NativeBuffer> {
+        self.record(Call::GetParameter(pname));
+        None
+    }
+
+    fn get_parameter_framebuffer(&self) -> Option<NativeFramebuffer> {
+        self.record(Call::GetParameter(FRAMEBUFFER_BINDING));
+        None
+    }
+
+    fn get_parameter_renderbuffer(&self) -> Option<NativeRenderbuffer> {
+        self.record(Call::GetParameter(RENDERBUFFER_BINDING));
+        None
+    }
+
+    fn get_parameter_read_framebuffer(&self) -> Option<NativeFramebuffer> {
+        assert!(self.gles3, "Read framebuffers require GLES 3");
+        self.record(Call::GetParameter(READ_FRAMEBUFFER_BINDING));
+        None
+    }
+
+    fn get_parameter_vertex_array(&self) -> Option<NativeVertexArray> {
+        assert!(self.gles3, "Vertex arrays require GLES 3");
+        self.record(Call::GetParameter(VERTEX_ARRAY_BINDING));
+        None
+    }
+
+    fn get_vertex_attrib_state(&self, index: u32) -> VertexAttrib {
+        self.record(Call::GetVertexAttrib(index));
+        VertexAttrib {
+            enabled: false,
+            buffer: None,
+            size: 4,
+            type_: FLOAT,
+            normalized: false,
+            stride: 0,
+            offset: 0,
+        }
+    }
+
+    fn is_gles3(&self) -> bool {
+        self.gles3
+    }
+
+    fn create_vertex_array(&self) -> Option<NativeVertexArray> {
+        if !self.gles3 {
+            return None;
+        }
+        let name = self.gen_name();
+        self.record(Call::CreateVertexArray(name.get()));
+        Some(NativeVertexArray(name))
+    }
+
+    fn bind_vertex_array(&self, vao: Option<&NativeVertexArray>) {
+        assert!(self.gles3, "Vertex arrays require GLES 3");
+        self.record(Call::BindVertexArray(vao.map(|vao| vao.0.get())));
+    }
+
+    fn delete_vertex_array(&self, vao: Option<&NativeVertexArray>) {
+        assert!(self.gles3, "Vertex arrays require GLES 3");
+        self.record(Call::DeleteVertexArray(vao.map(|vao| vao.0.get())));
+    }
+
+    fn renderbuffer_storage_multisample(
+        &self,
+        target: u32,
+        samples: i32,
+        internalformat: u32,
+        width: i32,
+        height: i32,
+    ) {
+        assert!(self.gles3, "Multisampling requires GLES 3");
+        self.record(Call::RenderbufferStorageMultisample {
+            target,
+            samples,
+            internalformat,
+            width,
+            height,
+        });
+    }
+
+    fn blit_framebuffer(
+        &self,
+        src_x0: i32,
+        src_y0: i32,
+        src_x1: i32,
+        src_y1: i32,
+        dst_x0: i32,
+        dst_y0: i32,
+        dst_x1: i32,
+        dst_y1: i32,
+        mask: u32,
+        filter: u32,
+    ) {
+        assert!(self.gles3, "Blitting requires GLES 3");
+        self.record(Call::BlitFramebuffer {
+            src: [src_x0, src_y0, src_x1, src_y1],
+            dst: [dst_x0, dst_y0, dst_x1, dst_y1],
+            mask,
+            filter,
+        });
+    }
+
+    #[cfg(feature = "debug")]
+    fn push_debug_group(&self, source: u32, id: u32, message: &str) {
+        self.record(Call::PushDebugGroup {
+            source,
+            id,
+            message: String::from(message),
+        });
+    }
+
+    #[cfg(feature = "debug")]
+    fn pop_debug_group(&self) {
+        self.record(Call::PopDebugGroup);
+    }
+}
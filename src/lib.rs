@@ -9,27 +9,78 @@
 //! A pure Rust implementation of [Inochi2D](https://inochi2d.com), the realtime 2D puppet
 //! animation framework.
 
+mod astc;
+mod bc7;
+#[cfg(feature = "encoding")]
+mod blocks;
+mod decoder;
 pub mod gl;
 pub mod glow;
+mod loader;
 mod parser;
 mod tga;
+mod validate;
 #[cfg(target_arch = "wasm32")]
 mod wasm;
 
 use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
 use parser::Param;
 pub use parser::{
-    Anim, BlendMode, Mask, Meta, Model, Node, Puppet, Texture, TextureReceiver, Transform,
+    AlphaMode, Anim, BlendMode, CompressedTexture, Extensions, Mask, Meta, Model, Node,
+    PixelFormat, Puppet, SessionBinding, Texture, TextureReceiver, TextureSupport, Transform,
+    UnknownFields, SESSION_BINDINGS,
 };
+pub use decoder::{IncrementalDecoder, Progress};
+pub use loader::{LazyModel, TextureLocation};
+pub use validate::ValidationError;
+#[cfg(feature = "parallel")]
+pub use decoder::{JobHandle, TextureDecoder};
+#[cfg(feature = "encoding")]
+pub use parser::{Quality, TextureFormat};
+
+/// The params of a puppet, either borrowed from its model or moved out of it.
+enum Params<'a> {
+    Borrowed(&'a [Param]),
+    Owned(Vec<Param>),
+}
+
+impl Deref for Params<'_> {
+    type Target = [Param];
+
+    fn deref(&self) -> &[Param] {
+        match self {
+            Params::Borrowed(params) => params,
+            Params::Owned(params) => params,
+        }
+    }
+}
 
 pub struct ParamValues<'a> {
-    params: &'a [Param],
+    params: Params<'a>,
     values: Vec<[f32; 2]>,
     index_map: HashMap<String, usize>,
+    /// Unique to this instance, so that renderers notice when they get passed another one.
+    id: u64,
+    /// Bumped whenever a value changes.
+    generation: u64,
+    /// The generation at which each value last changed.
+    changed: Vec<u64>,
 }
 
 impl<'a> ParamValues<'a> {
-    pub fn new(params: &'a [Param]) -> ParamValues {
+    pub fn new(params: &'a [Param]) -> ParamValues<'a> {
+        ParamValues::with_params(Params::Borrowed(params))
+    }
+
+    /// Takes ownership of the params, for when the values have to outlive any borrow of the
+    /// model.
+    pub fn from_owned(params: Vec<Param>) -> ParamValues<'static> {
+        ParamValues::with_params(Params::Owned(params))
+    }
+
+    fn with_params(params: Params<'a>) -> ParamValues<'a> {
         let mut values = Vec::with_capacity(params.len());
         let mut index_map = HashMap::new();
         for (i, param) in params.iter().enumerate() {
@@ -38,18 +89,53 @@ impl<'a> ParamValues<'a> {
             values.push([x, y]);
             index_map.insert(param.name.clone(), i);
         }
-        ParamValues { params, values, index_map }
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ParamValues {
+            params,
+            changed: vec![1; values.len()],
+            values,
+            index_map,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            generation: 1,
+        }
     }
 
+    /// Sets the value of a param, marking it as changed if it differs from the previous one.
     pub fn set(&mut self, name: &str, value: [f32; 2]) {
-        if let Some(i) = self.index_map.get(name) {
-            self.values[*i] = value;
+        if let Some(&i) = self.index_map.get(name) {
+            if self.values[i] != value {
+                self.values[i] = value;
+                self.generation += 1;
+                self.changed[i] = self.generation;
+            }
         }
     }
 
+    /// Identifies the current values: it changes whenever any of them does.
+    pub fn generation(&self) -> (u64, u64) {
+        (self.id, self.generation)
+    }
+
+    /// The indices of the params which changed after this generation, or of every param
+    /// without one or if it comes from another instance.
+    pub fn changed_since(
+        &self,
+        generation: Option<(u64, u64)>,
+    ) -> impl Iterator<Item = usize> + '_ {
+        let generation = match generation {
+            Some((id, generation)) if id == self.id => generation,
+            _ => 0,
+        };
+        self.changed
+            .iter()
+            .enumerate()
+            .filter(move |&(_, &changed)| changed > generation)
+            .map(|(i, _)| i)
+    }
+
     pub fn iter(&'a self) -> IterParamValues<'a> {
         IterParamValues {
-            params: self.params,
+            params: &self.params,
             values: &self.values,
             cur: 0,
         }
@@ -0,0 +1,117 @@
+// Copyright (c) 2023 Emmanuel Gil Peyrot <linkmauve@linkmauve.fr>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Loading of models without reading their textures up front.
+
+use crate::parser::{missing_texture, read_extensions, read_puppet, read_texture_header, read_vec};
+use crate::{CompressedTexture, Extensions, Model, Puppet};
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Where the data of a texture is stored in a model file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextureLocation {
+    format: u8,
+    /// From the start of the file.
+    pub offset: u64,
+    pub length: u32,
+}
+
+/// A model whose puppet has been parsed, but whose textures stay in the file until they get
+/// read one by one.
+///
+/// This is much faster than [`Model::parse`] when only the metadata of a puppet is needed, for
+/// instance to list a library of models.
+#[derive(Debug)]
+pub struct LazyModel<R> {
+    pub puppet: Puppet,
+    pub textures: Vec<TextureLocation>,
+    pub extensions: Extensions,
+    reader: R,
+}
+
+impl<R: Read + Seek> LazyModel<R> {
+    /// Parses the puppet and the extensions, and records where each texture is, seeking over
+    /// their data.
+    ///
+    /// A file truncated in the middle of a texture only gets noticed once that texture is
+    /// read.
+    pub fn open(reader: R) -> io::Result<LazyModel<R>> {
+        LazyModel::open_impl(reader, false)
+    }
+
+    /// Like [`open`](Self::open), but keeps unknown fields as [`Model::parse_lenient`] does.
+    pub fn open_lenient(reader: R) -> io::Result<LazyModel<R>> {
+        LazyModel::open_impl(reader, true)
+    }
+
+    fn open_impl(mut reader: R, lenient: bool) -> io::Result<LazyModel<R>> {
+        let (puppet, num_textures) = read_puppet(&mut reader, lenient)?;
+        let mut textures = Vec::new();
+        for _ in 0..num_textures {
+            let (format, length) = read_texture_header(&mut reader)?;
+            let offset = reader.stream_position()?;
+            reader.seek(SeekFrom::Current(length as i64))?;
+            textures.push(TextureLocation {
+                format,
+                offset,
+                length,
+            });
+        }
+        let extensions = read_extensions(&mut reader)?;
+        Ok(LazyModel {
+            puppet,
+            textures,
+            extensions,
+            reader,
+        })
+    }
+
+    /// Reads the data of the texture at this index.
+    pub fn read_texture(&mut self, index: usize) -> io::Result<CompressedTexture> {
+        let location = *self
+            .textures
+            .get(index)
+            .ok_or_else(|| missing_texture(index))?;
+        self.reader.seek(SeekFrom::Start(location.offset))?;
+        let data = read_vec(&mut self.reader, location.length)?;
+        CompressedTexture::from_format(location.format, data)
+    }
+
+    /// Reads every texture, to get the same model [`Model::parse`] would have returned.
+    pub fn into_model(mut self) -> io::Result<Model> {
+        let textures = (0..self.textures.len())
+            .map(|index| self.read_texture(index))
+            .collect::<io::Result<_>>()?;
+        Ok(Model {
+            puppet: self.puppet,
+            textures,
+            extensions: self.extensions,
+        })
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<'a> LazyModel<io::Cursor<&'a [u8]>> {
+    /// Parses a model which is already in memory, typically a memory-mapped file.
+    pub fn from_slice(data: &'a [u8]) -> io::Result<LazyModel<io::Cursor<&'a [u8]>>> {
+        LazyModel::open(io::Cursor::new(data))
+    }
+
+    /// Borrows the data of the texture at this index, without copying it.
+    pub fn texture_bytes(&self, index: usize) -> io::Result<&'a [u8]> {
+        let location = self
+            .textures
+            .get(index)
+            .ok_or_else(|| missing_texture(index))?;
+        let data: &'a [u8] = self.reader.get_ref();
+        let start = location.offset as usize;
+        data.get(start..start + location.length as usize)
+            .ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+    }
+}
@@ -4,16 +4,57 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use crate::tga;
+use crate::{astc, bc7, tga};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::io;
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc};
 
 const MAGIC: &[u8] = b"TRNSRTS\0";
 const TEX: &[u8] = b"TEX_SECT";
+const EXT: &[u8] = b"EXT_SECT";
+
+/// Name of the extension in which Inochi Session stores the tracking bindings of a puppet.
+pub const SESSION_BINDINGS: &str = "com.inochi2d.inochi-session.bindings";
+
+/// JSON fields which got parsed leniently, to be written back as they were.
+pub type UnknownFields = serde_json::Map<String, serde_json::Value>;
+
+/// How Inochi Session drives a param from a tracking source, as stored in the
+/// [`SESSION_BINDINGS`] extension.
+///
+/// Only the fields common to every kind of binding are required, the other ones depend on
+/// the kind.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionBinding {
+    pub name: String,
+    /// Such as `RatioBinding` or `ExpressionBinding`.
+    pub binding_type: String,
+    /// The UUID of the param being driven.
+    pub param: u32,
+    /// The axis of the param being driven, 0 for x and 1 for y.
+    pub axis: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_range: Option<[f32; 2]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub out_range: Option<[f32; 2]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inverse: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expression: Option<String>,
+    /// Every other field, written back as is.
+    #[serde(flatten)]
+    pub unknown: UnknownFields,
+}
 
 #[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase", deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
 pub struct Meta {
     pub name: Option<String>,
     pub version: String,
@@ -27,30 +68,40 @@ pub struct Meta {
     pub reference: Option<String>,
     thumbnail_id: u32,
     preserve_pixels: bool,
+    /// Fields this crate doesn’t know about, only kept when parsing leniently.
+    #[serde(flatten)]
+    pub unknown: UnknownFields,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase", deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
 struct Physics {
     pixels_per_meter: f32,
     gravity: f32,
+    /// Fields this crate doesn’t know about, only kept when parsing leniently.
+    #[serde(flatten)]
+    unknown: UnknownFields,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
-#[serde(deny_unknown_fields)]
 pub struct Transform {
     pub trans: [f32; 3],
     rot: [f32; 3],
     scale: [f32; 2],
+    /// Fields this crate doesn’t know about, only kept when parsing leniently.
+    #[serde(flatten)]
+    pub unknown: UnknownFields,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-#[serde(deny_unknown_fields)]
 pub struct Mesh {
     pub verts: Vec<f32>,
     pub uvs: Vec<f32>,
     pub indices: Vec<u16>,
     origin: [f32; 2],
+    /// Fields this crate doesn’t know about, only kept when parsing leniently.
+    #[serde(flatten)]
+    pub unknown: UnknownFields,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
@@ -59,10 +110,12 @@ enum MaskMode {
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
-#[serde(deny_unknown_fields)]
 pub struct Mask {
     pub source: u32,
     mode: MaskMode,
+    /// Fields this crate doesn’t know about, only kept when parsing leniently.
+    #[serde(flatten)]
+    pub unknown: UnknownFields,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -86,7 +139,7 @@ pub enum BlendMode {
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-#[serde(deny_unknown_fields, tag = "type")]
+#[serde(tag = "type")]
 pub enum Node {
     Node {
         uuid: u32,
@@ -97,6 +150,8 @@ pub enum Node {
         #[serde(rename = "lockToRoot")]
         lock_to_root: bool,
         children: Vec<Node>,
+        #[serde(flatten)]
+        unknown: UnknownFields,
     },
     Part {
         uuid: u32,
@@ -120,6 +175,8 @@ pub enum Node {
         masks: Vec<Mask>,
         #[serde(rename = "psdLayerPath")]
         psd_layer_path: String,
+        #[serde(flatten)]
+        unknown: UnknownFields,
     },
     Composite {
         uuid: u32,
@@ -136,6 +193,8 @@ pub enum Node {
         #[serde(rename = "screenTint")]
         screen_tint: [f32; 3],
         mask_threshold: f32,
+        #[serde(flatten)]
+        unknown: UnknownFields,
     },
     SimplePhysics {
         uuid: u32,
@@ -154,6 +213,8 @@ pub enum Node {
         angle_damping: f32,
         length_damping: f32,
         output_scale: [f32; 2],
+        #[serde(flatten)]
+        unknown: UnknownFields,
     },
 }
 
@@ -184,6 +245,25 @@ impl Node {
             | Node::SimplePhysics { zsort, .. } => zsort,
         }
     }
+
+    pub fn children(&self) -> &[Node] {
+        match self {
+            Node::Node { children, .. }
+            | Node::Part { children, .. }
+            | Node::Composite { children, .. } => children,
+            Node::SimplePhysics { .. } => &[],
+        }
+    }
+
+    /// Fields this crate doesn’t know about, only kept when parsing leniently.
+    pub fn unknown_fields(&self) -> &UnknownFields {
+        match self {
+            Node::Node { unknown, .. }
+            | Node::Part { unknown, .. }
+            | Node::Composite { unknown, .. }
+            | Node::SimplePhysics { unknown, .. } => unknown,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -218,14 +298,16 @@ pub enum BindingValues {
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-#[serde(deny_unknown_fields)]
 pub struct Binding {
     pub node: u32,
     #[serde(flatten)]
-    values: BindingValues,
+    pub(crate) values: BindingValues,
     #[serde(rename = "isSet")]
-    is_set: Vec<Vec<bool>>,
+    pub(crate) is_set: Vec<Vec<bool>>,
     interpolate_mode: InterpolateMode,
+    /// Fields this crate doesn’t know about, only kept when parsing leniently.
+    #[serde(flatten)]
+    pub unknown: UnknownFields,
 }
 
 #[derive(Debug)]
@@ -243,168 +325,107 @@ pub enum Anim {
 }
 
 impl Binding {
+    /// Evaluates the binding at this position, allocating a new buffer for deform bindings.
+    ///
+    /// See [`interpolate_into`](Self::interpolate_into) to avoid that allocation.
     pub fn interpolate(&self, axis_points: &[Vec<f32>; 2], pos: [f32; 2]) -> Anim {
+        match &self.values {
+            BindingValues::Deform(values) => {
+                let len = values.first().and_then(|row| row.first()).map_or(0, Vec::len);
+                let mut deform = vec![0.; len * 2];
+                accumulate_deform(values, axis_points, pos, &mut deform);
+                Anim::Deform(deform)
+            }
+            _ => self.interpolate_into(axis_points, pos, &mut []).unwrap(),
+        }
+    }
+
+    /// Evaluates the binding at this position, without allocating.
+    ///
+    /// Deform bindings get added to `deform`, which contains the two coordinates of each
+    /// vertex of the node, and return None. Every other binding returns its value.
+    pub fn interpolate_into(
+        &self,
+        axis_points: &[Vec<f32>; 2],
+        pos: [f32; 2],
+        deform: &mut [f32],
+    ) -> Option<Anim> {
         assert!(pos[0] >= 0.);
         assert!(pos[1] >= 0.);
         assert!(pos[0] <= 1.);
         assert!(pos[1] <= 1.);
 
-        match &self.values {
+        let value = |values: &[Vec<f32>]| interpolate_f32(values, axis_points, pos);
+        Some(match &self.values {
             BindingValues::Deform(values) => {
-                let deform = interpolate_deform(values, axis_points, pos);
-                let len = deform.len();
-                let deform = unsafe {
-                    let mut deform: Vec<f32> = core::mem::transmute(deform);
-                    deform.set_len(len * 2);
-                    deform
-                };
-                Anim::Deform(deform)
-            }
-            BindingValues::TransformTX(values) => {
-                Anim::TransformTX(interpolate_f32(values, axis_points, pos))
-            }
-            BindingValues::TransformTY(values) => {
-                Anim::TransformTY(interpolate_f32(values, axis_points, pos))
-            }
-            BindingValues::TransformTZ(values) => {
-                Anim::TransformTZ(interpolate_f32(values, axis_points, pos))
-            }
-            BindingValues::TransformSX(values) => {
-                Anim::TransformSX(interpolate_f32(values, axis_points, pos))
-            }
-            BindingValues::TransformSY(values) => {
-                Anim::TransformSY(interpolate_f32(values, axis_points, pos))
+                accumulate_deform(values, axis_points, pos, deform);
+                return None;
             }
-            BindingValues::TransformRX(values) => {
-                Anim::TransformRX(interpolate_f32(values, axis_points, pos))
-            }
-            BindingValues::TransformRY(values) => {
-                Anim::TransformRY(interpolate_f32(values, axis_points, pos))
-            }
-            BindingValues::TransformRZ(values) => {
-                Anim::TransformRZ(interpolate_f32(values, axis_points, pos))
-            }
-            BindingValues::ZSort(values) => Anim::ZSort(interpolate_f32(values, axis_points, pos)),
-        }
+            BindingValues::TransformTX(values) => Anim::TransformTX(value(values)),
+            BindingValues::TransformTY(values) => Anim::TransformTY(value(values)),
+            BindingValues::TransformTZ(values) => Anim::TransformTZ(value(values)),
+            BindingValues::TransformSX(values) => Anim::TransformSX(value(values)),
+            BindingValues::TransformSY(values) => Anim::TransformSY(value(values)),
+            BindingValues::TransformRX(values) => Anim::TransformRX(value(values)),
+            BindingValues::TransformRY(values) => Anim::TransformRY(value(values)),
+            BindingValues::TransformRZ(values) => Anim::TransformRZ(value(values)),
+            BindingValues::ZSort(values) => Anim::ZSort(value(values)),
+        })
     }
 }
 
-fn get_step(pos: f32, index: usize, axis_points: &[f32]) -> f32 {
-    let a = axis_points[index - 1];
-    let b = axis_points[index];
-    (pos - a) / (b - a)
+/// Finds the two axis points surrounding `pos`, and how far along it is between them.
+///
+/// Both indices are the same when `pos` falls exactly on an axis point, or outside of them.
+fn locate(axis_points: &[f32], pos: f32) -> (usize, usize, f32) {
+    let i = axis_points.partition_point(|&point| point < pos);
+    if i == axis_points.len() {
+        let last = axis_points.len() - 1;
+        return (last, last, 0.);
+    }
+    if i == 0 || axis_points[i] == pos {
+        return (i, i, 0.);
+    }
+    let a = axis_points[i - 1];
+    let b = axis_points[i];
+    (i - 1, i, (pos - a) / (b - a))
 }
 
 fn mix(a: f32, b: f32, t: f32) -> f32 {
     a + t * (b - a)
 }
 
-fn mix_deform(a: &[[f32; 2]], b: &[[f32; 2]], t: f32) -> Vec<[f32; 2]> {
-    a.iter()
-        .zip(b.iter())
-        .map(|([ax, ay], [bx, by])| [mix(*ax, *bx, t), mix(*ay, *by, t)])
-        .collect()
+fn interpolate_f32(values: &[Vec<f32>], axis_points: &[Vec<f32>; 2], pos: [f32; 2]) -> f32 {
+    let (x0, x1, tx) = locate(&axis_points[0], pos[0]);
+    let (y0, y1, ty) = locate(&axis_points[1], pos[1]);
+    let top = mix(values[x0][y0], values[x1][y0], tx);
+    let bottom = mix(values[x0][y1], values[x1][y1], tx);
+    mix(top, bottom, ty)
 }
 
-// TODO: merge the next two functions into one, they are almost the same just operating on
-// different types.
-
-fn interpolate_deform(
+fn accumulate_deform(
     values: &[Vec<Vec<[f32; 2]>>],
     axis_points: &[Vec<f32>; 2],
     pos: [f32; 2],
-) -> Vec<[f32; 2]> {
-    for (i, x) in axis_points[0].iter().enumerate() {
-        if *x == pos[0] {
-            for (j, y) in axis_points[1].iter().enumerate() {
-                if *y == pos[1] {
-                    // No interpolation needed.
-                    return values[i][j].clone();
-                }
-                if *y > pos[1] {
-                    let a = &values[i][j - 1];
-                    let b = &values[i][j];
-                    let t = get_step(pos[1], j, &axis_points[1]);
-                    return mix_deform(a, b, t);
-                }
-            }
-            unreachable!();
-        }
-        if *x > pos[0] {
-            for (j, y) in axis_points[1].iter().enumerate() {
-                if *y == pos[1] {
-                    let a = &values[i - 1][j];
-                    let b = &values[i][j];
-                    let t = get_step(pos[0], i, &axis_points[0]);
-                    return mix_deform(a, b, t);
-                }
-                if *y > pos[1] {
-                    let a = &values[i - 1][j - 1];
-                    let b = &values[i][j - 1];
-                    let t = get_step(pos[0], i, &axis_points[0]);
-                    let y1 = mix_deform(a, b, t);
-
-                    let a = &values[i - 1][j];
-                    let b = &values[i][j];
-                    let y2 = mix_deform(a, b, t);
-
-                    let t = get_step(pos[1], j, &axis_points[1]);
-                    return mix_deform(&y1, &y2, t);
-                }
-            }
-            unreachable!();
+    out: &mut [f32],
+) {
+    let (x0, x1, tx) = locate(&axis_points[0], pos[0]);
+    let (y0, y1, ty) = locate(&axis_points[1], pos[1]);
+    let corners = values[x0][y0]
+        .iter()
+        .zip(&values[x1][y0])
+        .zip(&values[x0][y1])
+        .zip(&values[x1][y1]);
+    for (out, (((a, b), c), d)) in out.chunks_exact_mut(2).zip(corners) {
+        for i in 0..2 {
+            let top = mix(a[i], b[i], tx);
+            let bottom = mix(c[i], d[i], tx);
+            out[i] += mix(top, bottom, ty);
         }
     }
-    unreachable!();
-}
-
-fn interpolate_f32(values: &[Vec<f32>], axis_points: &[Vec<f32>; 2], pos: [f32; 2]) -> f32 {
-    for (i, x) in axis_points[0].iter().enumerate() {
-        if *x == pos[0] {
-            for (j, y) in axis_points[1].iter().enumerate() {
-                if *y == pos[1] {
-                    // No interpolation needed.
-                    return values[i][j];
-                }
-                if *y > pos[1] {
-                    let a = values[i][j - 1];
-                    let b = values[i][j];
-                    let t = get_step(pos[1], j, &axis_points[1]);
-                    return mix(a, b, t);
-                }
-            }
-            unreachable!();
-        }
-        if *x > pos[0] {
-            for (j, y) in axis_points[1].iter().enumerate() {
-                if *y == pos[1] {
-                    let a = values[i - 1][j];
-                    let b = values[i][j];
-                    let t = get_step(pos[0], i, &axis_points[0]);
-                    return mix(a, b, t);
-                }
-                if *y > pos[1] {
-                    let a = values[i - 1][j - 1];
-                    let b = values[i][j - 1];
-                    let t = get_step(pos[0], i, &axis_points[0]);
-                    let y1 = mix(a, b, t);
-
-                    let a = values[i - 1][j];
-                    let b = values[i][j];
-                    let y2 = mix(a, b, t);
-
-                    let t = get_step(pos[1], j, &axis_points[1]);
-                    return mix(y1, y2, t);
-                }
-            }
-            unreachable!();
-        }
-    }
-    unreachable!();
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-#[serde(deny_unknown_fields)]
 pub struct Param {
     uuid: u32,
     pub name: String,
@@ -414,10 +435,12 @@ pub struct Param {
     pub defaults: [f32; 2],
     pub axis_points: [Vec<f32>; 2],
     pub bindings: Vec<Binding>,
+    /// Fields this crate doesn’t know about, only kept when parsing leniently.
+    #[serde(flatten)]
+    pub unknown: UnknownFields,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-#[serde(deny_unknown_fields)]
 pub struct Puppet {
     pub meta: Meta,
     physics: Physics,
@@ -425,94 +448,378 @@ pub struct Puppet {
     automation: Option<serde_json::Value>,
     pub param: Vec<Param>,
     animations: Option<serde_json::Value>,
+    /// Fields this crate doesn’t know about, only kept when parsing leniently.
+    #[serde(flatten)]
+    pub unknown: UnknownFields,
 }
 
-#[derive(Debug)]
+/// Returns an error naming the first of these fields, if any.
+fn deny_unknown_fields(unknown: &UnknownFields, location: &str) -> io::Result<()> {
+    match unknown.keys().next() {
+        Some(field) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unknown field `{field}` in {location}"),
+        )),
+        None => Ok(()),
+    }
+}
+
+impl Node {
+    fn deny_unknown_fields(&self) -> io::Result<()> {
+        let location = format!("node {}", self.uuid());
+        deny_unknown_fields(self.unknown_fields(), &location)?;
+        let (Node::Node { transform, .. }
+        | Node::Part { transform, .. }
+        | Node::Composite { transform, .. }
+        | Node::SimplePhysics { transform, .. }) = self;
+        deny_unknown_fields(&transform.unknown, &format!("transform of {location}"))?;
+        if let Node::Part { mesh, masks, .. } = self {
+            deny_unknown_fields(&mesh.unknown, &format!("mesh of {location}"))?;
+            for mask in masks.iter() {
+                deny_unknown_fields(&mask.unknown, &format!("mask of {location}"))?;
+            }
+        }
+        for child in self.children() {
+            child.deny_unknown_fields()?;
+        }
+        Ok(())
+    }
+}
+
+impl Puppet {
+    /// Fails on the first field which would have been kept aside when parsing leniently.
+    fn deny_unknown_fields(&self) -> io::Result<()> {
+        deny_unknown_fields(&self.unknown, "puppet")?;
+        deny_unknown_fields(&self.meta.unknown, "meta")?;
+        deny_unknown_fields(&self.physics.unknown, "physics")?;
+        self.nodes.deny_unknown_fields()?;
+        for param in self.param.iter() {
+            deny_unknown_fields(&param.unknown, &format!("param {}", param.name))?;
+            for binding in param.bindings.iter() {
+                deny_unknown_fields(
+                    &binding.unknown,
+                    &format!("binding of param {} to node {}", param.name, binding.node),
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A texture file, as stored in the model.
+///
+/// The data is shared, so that cloning a texture to decode it on another thread doesn’t copy
+/// it.
+#[derive(Debug, Clone)]
 pub enum CompressedTexture {
-    Png(Vec<u8>),
-    Tga(Vec<u8>),
-    Bc7(Vec<u8>),
+    Png(Arc<[u8]>),
+    Tga(Arc<[u8]>),
+    /// A DDS file containing BC7 blocks.
+    Bc7(Arc<[u8]>),
+    /// An .astc file, as written by ARM’s tools.
+    Astc(Arc<[u8]>),
+}
+
+/// Order of the channels of an uncompressed texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgba8,
+    Bgra8,
+}
+
+/// Whether the color channels of a texture have already been multiplied by its alpha.
+///
+/// The renderer blends assuming premultiplied alpha, textures with straight alpha get
+/// premultiplied before or while being sampled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlphaMode {
+    Straight,
+    Premultiplied,
 }
 
 #[derive(Debug)]
 pub enum Texture {
+    /// Uncompressed pixels, with 8 bits per channel. Decoding always produces premultiplied
+    /// RGBA.
     Rgba {
         width: u32,
         height: u32,
+        format: PixelFormat,
+        alpha: AlphaMode,
         data: Vec<u8>,
     },
+    /// Raw BC7 blocks, to be uploaded as is.
+    Bc7 {
+        width: u32,
+        height: u32,
+        alpha: AlphaMode,
+        data: Vec<u8>,
+    },
+    /// Raw ASTC LDR blocks, to be uploaded as is.
+    Astc {
+        block_width: u8,
+        block_height: u8,
+        width: u32,
+        height: u32,
+        alpha: AlphaMode,
+        data: Vec<u8>,
+    },
+}
+
+/// Which compressed formats the GPU can sample from directly.
+///
+/// Textures in a format which isn’t supported get decoded to RGBA on the CPU instead.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TextureSupport {
+    pub bptc: bool,
+    pub astc: bool,
+}
+
+/// The format to encode a texture into.
+#[cfg(feature = "encoding")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureFormat {
+    Png,
+    Tga,
+    Bc7,
+    /// ASTC LDR, with one of the 2D block sizes from 4×4 to 12×12.
+    Astc {
+        block_width: u8,
+        block_height: u8,
+    },
+}
+
+/// How much time the encoder spends searching for a better result.
+#[cfg(feature = "encoding")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quality {
+    Fast,
+    #[default]
+    Normal,
+    Best,
 }
 
 impl CompressedTexture {
-    pub fn decode(&self) -> Texture {
-        match self {
-            #[cfg(not(feature = "png"))]
-            CompressedTexture::Png(_) => {
-                panic!("PNG textures are unsupported without the png feature")
+    /// Wraps texture data, given the format byte stored before it in the model.
+    pub(crate) fn from_format(format: u8, data: Vec<u8>) -> io::Result<CompressedTexture> {
+        Ok(match format {
+            0 => CompressedTexture::Png(data.into()),
+            1 => CompressedTexture::Tga(data.into()),
+            2 => CompressedTexture::Bc7(data.into()),
+            3 => CompressedTexture::Astc(data.into()),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown texture format {format}"),
+                ))
             }
-            #[cfg(feature = "png")]
-            CompressedTexture::Png(data) => {
-                use image::ImageDecoder;
-                let cursor = io::Cursor::new(data);
-                let decoder = image::codecs::png::PngDecoder::new(cursor).unwrap();
-                let (width, height) = decoder.dimensions();
-                let mut data = vec![0u8; decoder.total_bytes() as usize];
-                let color_type = decoder.color_type();
-                decoder.read_image(&mut data).unwrap();
-                let data = match color_type {
-                    image::ColorType::Rgba8 => data,
-                    image::ColorType::Rgb8 => {
-                        let rgb = image::ImageBuffer::from_raw(width, height, data).unwrap();
-                        let dynamic = image::DynamicImage::ImageRgb8(rgb);
-                        let rgba = dynamic.into_rgba8();
-                        rgba.into_vec()
-                    }
-                    _ => panic!("Unknown color type {color_type:?}"),
-                };
-                Texture::Rgba {
+        })
+    }
+
+    /// The format byte stored before the texture data in the model.
+    pub(crate) fn format(&self) -> u8 {
+        match self {
+            CompressedTexture::Png(_) => 0,
+            CompressedTexture::Tga(_) => 1,
+            CompressedTexture::Bc7(_) => 2,
+            CompressedTexture::Astc(_) => 3,
+        }
+    }
+
+    /// The file, as stored in the model.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            CompressedTexture::Png(data)
+            | CompressedTexture::Tga(data)
+            | CompressedTexture::Bc7(data)
+            | CompressedTexture::Astc(data) => data,
+        }
+    }
+
+    /// Keeps the texture compressed if the GPU supports its format, otherwise decodes it.
+    pub fn decode_for(&self, support: TextureSupport) -> io::Result<Texture> {
+        Ok(match self {
+            CompressedTexture::Bc7(data) if support.bptc => {
+                let (width, height, data) = bc7::parse(data)?;
+                // Files never store premultiplied alpha, as it would be lost when decoding them.
+                Texture::Bc7 {
                     width,
                     height,
-                    data,
+                    alpha: AlphaMode::Straight,
+                    data: data.to_vec(),
                 }
             }
-            CompressedTexture::Tga(data) => {
-                let (width, height, data) = tga::decode(data);
-                Texture::Rgba {
-                    width,
-                    height,
-                    data,
+            CompressedTexture::Astc(data) if support.astc => {
+                let (header, data) = astc::parse(data)?;
+                Texture::Astc {
+                    block_width: header.block_width,
+                    block_height: header.block_height,
+                    width: header.width,
+                    height: header.height,
+                    alpha: AlphaMode::Straight,
+                    data: data.to_vec(),
                 }
             }
-            CompressedTexture::Bc7(_) => todo!("BC7 is still unimplemented"),
-        }
+            _ => self.decode()?,
+        })
+    }
+
+    /// Decodes the texture to premultiplied RGBA.
+    pub fn decode(&self) -> io::Result<Texture> {
+        let (width, height, mut data) = match self {
+            #[cfg(not(feature = "png"))]
+            CompressedTexture::Png(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "PNG textures are unsupported without the png feature",
+                ))
+            }
+            #[cfg(feature = "png")]
+            CompressedTexture::Png(data) => decode_png(data)?,
+            CompressedTexture::Tga(data) => tga::decode(data)?,
+            CompressedTexture::Bc7(data) => bc7::decode(data)?,
+            CompressedTexture::Astc(data) => astc::decode(data)?,
+        };
+        premultiply(&mut data);
+        Ok(Texture::Rgba {
+            width,
+            height,
+            format: PixelFormat::Rgba8,
+            alpha: AlphaMode::Premultiplied,
+            data,
+        })
     }
 }
 
+/// Decodes a PNG of any color type and bit depth to 8-bit RGBA.
+#[cfg(feature = "png")]
+fn decode_png(data: &[u8]) -> io::Result<(u32, u32, Vec<u8>)> {
+    let to_io_error = |err| match err {
+        image::ImageError::IoError(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    };
+    let decoder = image::codecs::png::PngDecoder::new(data).map_err(to_io_error)?;
+    // The decoder already expands palettes and bit depths below 8, what remains is converting
+    // grayscale and 16-bit images.
+    let image = image::DynamicImage::from_decoder(decoder).map_err(to_io_error)?;
+    let rgba = image.into_rgba8();
+    Ok((rgba.width(), rgba.height(), rgba.into_raw()))
+}
+
 impl Texture {
+    /// Converts an uncompressed texture to premultiplied RGBA, which is what the renderer
+    /// expects.
+    pub fn normalize(&mut self) {
+        if let Texture::Rgba {
+            format,
+            alpha,
+            data,
+            ..
+        } = self
+        {
+            if *format == PixelFormat::Bgra8 {
+                swap_red_blue(data);
+                *format = PixelFormat::Rgba8;
+            }
+            if *alpha == AlphaMode::Straight {
+                premultiply(data);
+                *alpha = AlphaMode::Premultiplied;
+            }
+        }
+    }
+
+    /// Encodes an RGBA texture into the given format.
     #[cfg(feature = "encoding")]
-    pub fn encode(&self, format: image::ImageFormat) -> CompressedTexture {
-        match self {
-            Texture::Rgba {
-                width,
-                height,
-                data,
-            } => {
-                let buf = Vec::new();
-                let mut buf = std::io::Cursor::new(buf);
-                image::write_buffer_with_format(
-                    &mut buf,
-                    &data,
-                    *width,
-                    *height,
-                    image::ColorType::Rgba8,
-                    format,
-                )
-                .unwrap();
-                match format {
-                    image::ImageFormat::Png => CompressedTexture::Png(buf.into_inner()),
-                    image::ImageFormat::Tga => CompressedTexture::Tga(buf.into_inner()),
-                    _ => panic!("Unsupported format {format:?}"),
-                }
+    pub fn encode(&self, format: TextureFormat, quality: Quality) -> io::Result<CompressedTexture> {
+        let Texture::Rgba {
+            width,
+            height,
+            format: pixel_format,
+            alpha,
+            data,
+        } = self
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Only RGBA textures can be encoded",
+            ));
+        };
+        let (width, height) = (*width, *height);
+        // Every format we can encode to stores straight RGBA.
+        let mut straight;
+        let data = if (*pixel_format, *alpha) == (PixelFormat::Rgba8, AlphaMode::Straight) {
+            data
+        } else {
+            straight = data.clone();
+            if *pixel_format == PixelFormat::Bgra8 {
+                swap_red_blue(&mut straight);
+            }
+            if *alpha == AlphaMode::Premultiplied {
+                unpremultiply(&mut straight);
+            }
+            &straight
+        };
+        let to_io_error = io::Error::other;
+        Ok(match format {
+            TextureFormat::Png => {
+                use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+                use image::ImageEncoder;
+                let compression = match quality {
+                    Quality::Fast => CompressionType::Fast,
+                    Quality::Normal => CompressionType::Default,
+                    Quality::Best => CompressionType::Best,
+                };
+                let mut buf = Vec::new();
+                PngEncoder::new_with_quality(&mut buf, compression, FilterType::Adaptive)
+                    .write_image(data, width, height, image::ColorType::Rgba8)
+                    .map_err(to_io_error)?;
+                CompressedTexture::Png(buf.into())
+            }
+            TextureFormat::Tga => {
+                use image::ImageEncoder;
+                let mut buf = Vec::new();
+                image::codecs::tga::TgaEncoder::new(&mut buf)
+                    .write_image(data, width, height, image::ColorType::Rgba8)
+                    .map_err(to_io_error)?;
+                CompressedTexture::Tga(buf.into())
+            }
+            TextureFormat::Bc7 => {
+                CompressedTexture::Bc7(bc7::encode(width, height, data, quality).into())
+            }
+            TextureFormat::Astc {
+                block_width,
+                block_height,
+            } => CompressedTexture::Astc(
+                astc::encode(width, height, data, block_width, block_height, quality)?.into(),
+            ),
+        })
+    }
+}
+
+fn swap_red_blue(data: &mut [u8]) {
+    for pixel in data.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+}
+
+fn premultiply(data: &mut [u8]) {
+    for pixel in data.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u16;
+        if alpha != 255 {
+            for channel in &mut pixel[..3] {
+                *channel = ((*channel as u16 * alpha + 127) / 255) as u8;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "encoding")]
+fn unpremultiply(data: &mut [u8]) {
+    for pixel in data.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u16;
+        if alpha != 255 && alpha != 0 {
+            for channel in &mut pixel[..3] {
+                *channel = ((*channel as u16 * 255 + alpha / 2) / alpha).min(255) as u8;
             }
         }
     }
@@ -536,56 +843,148 @@ fn read_array<R: io::Read, const LENGTH: usize>(reader: &mut R) -> io::Result<[u
     Ok(data)
 }
 
-fn read_vec<R: io::Read>(reader: &mut R, length: u32) -> io::Result<Vec<u8>> {
-    let length = length as usize;
-    let mut data = Vec::with_capacity(length);
-    unsafe { data.set_len(length) };
-    reader.read_exact(&mut data)?;
+pub(crate) fn read_vec<R: io::Read>(reader: &mut R, length: u32) -> io::Result<Vec<u8>> {
+    // Don’t trust the length to allocate up front, a truncated or corrupted file would then
+    // make us reserve up to 4 GiB for nothing.
+    use io::Read;
+    let mut data = Vec::new();
+    reader.take(length as u64).read_to_end(&mut data)?;
+    if data.len() != length as usize {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
     Ok(data)
 }
 
-pub type TextureReceiver = (usize, mpsc::Receiver<(usize, Texture)>);
+/// Reads everything up to the texture section, and returns the puppet along with the number
+/// of textures following it.
+///
+/// Unless `lenient` is set, any field of the puppet this crate doesn’t know about is an error.
+pub(crate) fn read_puppet<R: io::Read>(
+    reader: &mut R,
+    lenient: bool,
+) -> io::Result<(Puppet, u32)> {
+    let magic = read_array::<R, 8>(reader)?;
+    if magic != MAGIC {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+
+    let puppet = {
+        let length = read_be_u32(reader)?;
+        let json = read_vec(reader, length)?;
+        let puppet: Puppet = serde_json::from_slice(&json)?;
+        if !lenient {
+            puppet.deny_unknown_fields()?;
+        }
+        puppet
+    };
+
+    let magic = read_array::<R, 8>(reader)?;
+    if magic != TEX {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+
+    let num_textures = read_be_u32(reader)?;
+    Ok((puppet, num_textures))
+}
+
+/// Reads the header of a texture, returning its format and the length of its data.
+pub(crate) fn read_texture_header<R: io::Read>(reader: &mut R) -> io::Result<(u8, u32)> {
+    let length = read_be_u32(reader)?;
+    let format = read_u8(reader)?;
+    Ok((format, length))
+}
+
+fn read_string<R: io::Read>(reader: &mut R) -> io::Result<String> {
+    let length = read_be_u32(reader)?;
+    let data = read_vec(reader, length)?;
+    String::from_utf8(data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Reads the optional extension section, which follows the textures.
+pub(crate) fn read_extensions<R: io::Read>(reader: &mut R) -> io::Result<Extensions> {
+    let mut extensions = Extensions::new();
+    let mut magic = [0u8; 8];
+    // Files written before extensions existed just end after the textures.
+    let read = loop {
+        match reader.read(&mut magic[..1]) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            result => break result?,
+        }
+    };
+    if read == 0 {
+        return Ok(extensions);
+    }
+    reader.read_exact(&mut magic[1..])?;
+    if magic != EXT {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+
+    let num_extensions = read_be_u32(reader)?;
+    for _ in 0..num_extensions {
+        let name = read_string(reader)?;
+        let length = read_be_u32(reader)?;
+        let payload = read_vec(reader, length)?;
+        extensions.insert(name, payload);
+    }
+    Ok(extensions)
+}
+
+/// Vendor payloads stored along with a model, by name.
+pub type Extensions = BTreeMap<String, Vec<u8>>;
+
+/// The number of textures being decoded, and the channel on which each of them gets sent
+/// along with its index in the model once decoded.
+pub type TextureReceiver = (usize, mpsc::Receiver<(usize, io::Result<Texture>)>);
+
+pub(crate) fn missing_texture(index: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("No texture at index {index}"),
+    )
+}
 
 #[derive(Debug)]
 pub struct Model {
     pub puppet: Puppet,
     pub textures: Vec<CompressedTexture>,
+    /// Written back as is, even the ones this crate doesn’t know about.
+    pub extensions: Extensions,
 }
 
 impl Model {
-    pub fn parse<R: io::Read>(mut reader: R) -> io::Result<Model> {
-        let magic = read_array::<R, 8>(&mut reader)?;
-        if magic != MAGIC {
-            return Err(io::ErrorKind::InvalidData.into());
-        }
-
-        let puppet = {
-            let length = read_be_u32(&mut reader)?;
-            let json = read_vec(&mut reader, length)?;
-            serde_json::from_slice(&json).unwrap()
-        };
+    /// Reads a whole model, including the data of every texture.
+    ///
+    /// Fields this crate doesn’t know about are an error, see
+    /// [`parse_lenient`](Self::parse_lenient) to accept them. See [`LazyModel`] to read the
+    /// textures only when needed.
+    ///
+    /// [`LazyModel`]: crate::LazyModel
+    pub fn parse<R: io::Read>(reader: R) -> io::Result<Model> {
+        Model::parse_impl(reader, false)
+    }
 
-        let magic = read_array::<R, 8>(&mut reader)?;
-        if magic != TEX {
-            return Err(io::ErrorKind::InvalidData.into());
-        }
+    /// Like [`parse`](Self::parse), but keeps the fields this crate doesn’t know about in the
+    /// `unknown` maps of the node, transform, mesh, mask, param, binding, meta, physics or
+    /// puppet they belong to, so that [`serialize`](Self::serialize) writes them back.
+    pub fn parse_lenient<R: io::Read>(reader: R) -> io::Result<Model> {
+        Model::parse_impl(reader, true)
+    }
 
-        let num_textures = read_be_u32(&mut reader)?;
-        let mut textures = Vec::with_capacity(num_textures as usize);
+    fn parse_impl<R: io::Read>(mut reader: R, lenient: bool) -> io::Result<Model> {
+        let (puppet, num_textures) = read_puppet(&mut reader, lenient)?;
+        let mut textures = Vec::new();
         for _ in 0..num_textures {
-            let length = read_be_u32(&mut reader)?;
-            let format = read_u8(&mut reader)?;
+            let (format, length) = read_texture_header(&mut reader)?;
             let data = read_vec(&mut reader, length)?;
-            let texture = match format {
-                0 => CompressedTexture::Png(data),
-                1 => CompressedTexture::Tga(data),
-                2 => CompressedTexture::Bc7(data),
-                _ => panic!("Unknown format {format}"),
-            };
-            textures.push(texture);
+            textures.push(CompressedTexture::from_format(format, data)?);
         }
+        let extensions = read_extensions(&mut reader)?;
 
-        Ok(Model { puppet, textures })
+        Ok(Model {
+            puppet,
+            textures,
+            extensions,
+        })
     }
 
     pub fn serialize<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
@@ -596,66 +995,507 @@ impl Model {
         writer.write_all(TEX)?;
         writer.write_all(&(self.textures.len() as u32).to_be_bytes())?;
         for texture in self.textures.iter() {
-            let (format, data) = match texture {
-                CompressedTexture::Png(data) => (0u8, data),
-                CompressedTexture::Tga(data) => (1u8, data),
-                CompressedTexture::Bc7(data) => (2u8, data),
-            };
+            let data = texture.as_bytes();
             writer.write_all(&(data.len() as u32).to_be_bytes())?;
-            writer.write_all(&[format])?;
+            writer.write_all(&[texture.format()])?;
             writer.write_all(data)?;
         }
+        if !self.extensions.is_empty() {
+            writer.write_all(EXT)?;
+            writer.write_all(&(self.extensions.len() as u32).to_be_bytes())?;
+            for (name, payload) in self.extensions.iter() {
+                writer.write_all(&(name.len() as u32).to_be_bytes())?;
+                writer.write_all(name.as_bytes())?;
+                writer.write_all(&(payload.len() as u32).to_be_bytes())?;
+                writer.write_all(payload)?;
+            }
+        }
         Ok(())
     }
 
+    /// Parses the extension with this name as JSON, if the model has one.
+    pub fn extension_json<T: DeserializeOwned>(&self, name: &str) -> Option<io::Result<T>> {
+        let payload = self.extensions.get(name)?;
+        Some(serde_json::from_slice(payload).map_err(io::Error::from))
+    }
+
+    /// Stores this value as JSON in the extension with this name, replacing any previous one.
+    pub fn set_extension_json<T: Serialize>(&mut self, name: &str, value: &T) -> io::Result<()> {
+        let payload = serde_json::to_vec(value)?;
+        self.extensions.insert(name.to_string(), payload);
+        Ok(())
+    }
+
+    /// The tracking bindings Inochi Session saved in this model, if any.
+    pub fn session_bindings(&self) -> Option<io::Result<Vec<SessionBinding>>> {
+        self.extension_json(SESSION_BINDINGS)
+    }
+
+    /// Decodes every texture to premultiplied RGBA.
+    pub fn decode_textures(&self) -> TextureReceiver {
+        self.decode_textures_for(TextureSupport::default())
+    }
+
+    /// Decodes every texture, keeping them compressed whenever `support` allows it.
+    pub fn decode_textures_for(&self, support: TextureSupport) -> TextureReceiver {
+        let indices: Vec<_> = (0..self.textures.len()).collect();
+        self.decode_some_textures_for(&indices, support)
+    }
+
+    /// Decodes only the textures at these indices, leaving the model untouched.
+    ///
+    /// An index without a texture gets reported as an error on the channel.
+    ///
+    /// This uses a pool shared by the whole process, create a [`TextureDecoder`] to choose how
+    /// many threads to use, or to follow the progress of decoding.
+    ///
+    /// [`TextureDecoder`]: crate::TextureDecoder
     #[cfg(feature = "parallel")]
-    pub fn decode_textures(&mut self) -> TextureReceiver {
-        let num = self.textures.len();
-        let mut num_threads = std::thread::available_parallelism().unwrap().get();
-        if num_threads > 1 {
-            num_threads -= 1;
+    pub fn decode_some_textures_for(
+        &self,
+        indices: &[usize],
+        support: TextureSupport,
+    ) -> TextureReceiver {
+        static POOL: std::sync::OnceLock<crate::TextureDecoder> = std::sync::OnceLock::new();
+        let pool = POOL.get_or_init(crate::TextureDecoder::default);
+        let (_, receiver) = pool.decode_some(self, indices, support);
+        receiver
+    }
+
+    /// Decodes only the textures at these indices, leaving the model untouched.
+    ///
+    /// An index without a texture gets reported as an error on the channel.
+    #[cfg(not(feature = "parallel"))]
+    pub fn decode_some_textures_for(
+        &self,
+        indices: &[usize],
+        support: TextureSupport,
+    ) -> TextureReceiver {
+        let (tx, rx) = mpsc::channel();
+        for &i in indices {
+            let tex = match self.textures.get(i) {
+                Some(tex) => tex.decode_for(support),
+                None => Err(missing_texture(i)),
+            };
+            tx.send((i, tex)).unwrap();
+        }
+        (indices.len(), rx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    /// A root node with a part masked by itself, and a param bound to that part.
+    fn puppet() -> Value {
+        let transform = json!({ "trans": [0., 0., 0.], "rot": [0., 0., 0.], "scale": [1., 1.] });
+        let verts = [0., 0., 0., 1., 1., 0.];
+        json!({
+            "meta": { "version": "1.0-alpha", "thumbnailId": u32::MAX, "preservePixels": false },
+            "physics": { "pixelsPerMeter": 1000., "gravity": 9.8 },
+            "nodes": {
+                "type": "Node",
+                "uuid": 1,
+                "name": "Root",
+                "enabled": true,
+                "zsort": 0.,
+                "transform": transform,
+                "lockToRoot": false,
+                "children": [{
+                    "type": "Part",
+                    "uuid": 2,
+                    "name": "Part",
+                    "enabled": true,
+                    "zsort": 0.,
+                    "transform": transform,
+                    "lockToRoot": false,
+                    "textures": [0, u32::MAX, u32::MAX],
+                    "mesh": {
+                        "verts": verts,
+                        "uvs": verts,
+                        "indices": [0, 1, 2],
+                        "origin": [0., 0.],
+                    },
+                    "blend_mode": "Normal",
+                    "opacity": 1.,
+                    "tint": [1., 1., 1.],
+                    "screenTint": [0., 0., 0.],
+                    "mask_threshold": 0.5,
+                    "masks": [{ "source": 2, "mode": "Mask" }],
+                    "psdLayerPath": "",
+                }],
+            },
+            "param": [{
+                "uuid": 100,
+                "name": "param",
+                "is_vec2": false,
+                "min": [0., 0.],
+                "max": [1., 1.],
+                "defaults": [0., 0.],
+                "axis_points": [[0., 1.], [0.]],
+                "bindings": [{
+                    "node": 2,
+                    "param_name": "transform.t.x",
+                    "values": [[0.], [10.]],
+                    "isSet": [[true], [true]],
+                    "interpolate_mode": "Linear",
+                }],
+            }],
+        })
+    }
+
+    /// A model file containing this puppet, without any texture, followed by `rest`.
+    fn inp(puppet: &Value, rest: &[u8]) -> Vec<u8> {
+        let json = serde_json::to_vec(puppet).unwrap();
+        let mut inp = MAGIC.to_vec();
+        inp.extend((json.len() as u32).to_be_bytes());
+        inp.extend(json);
+        inp.extend(TEX);
+        inp.extend(0u32.to_be_bytes());
+        inp.extend(rest);
+        inp
+    }
+
+    /// The puppet, as written in this model file.
+    fn puppet_json(inp: &[u8]) -> Value {
+        let length = u32::from_be_bytes(inp[8..12].try_into().unwrap()) as usize;
+        serde_json::from_slice(&inp[12..12 + length]).unwrap()
+    }
+
+    /// Where unknown fields can appear, as JSON pointers, along with how strict parsing
+    /// reports them.
+    const UNKNOWN_FIELDS: &[(&str, &str)] = &[
+        ("", "puppet"),
+        ("/meta", "meta"),
+        ("/physics", "physics"),
+        ("/nodes", "node 1"),
+        ("/nodes/transform", "transform of node 1"),
+        ("/nodes/children/0/mesh", "mesh of node 2"),
+        ("/nodes/children/0/masks/0", "mask of node 2"),
+        ("/param/0", "param param"),
+        ("/param/0/bindings/0", "binding of param param to node 2"),
+    ];
+
+    fn add_unknown_field(puppet: &mut Value, pointer: &str) {
+        let object = puppet
+            .pointer_mut(pointer)
+            .unwrap()
+            .as_object_mut()
+            .unwrap();
+        object.insert(String::from("futureField"), json!({ "pointer": pointer }));
+    }
+
+    #[test]
+    fn lenient_round_trip() {
+        let mut puppet = puppet();
+        for &(pointer, _) in UNKNOWN_FIELDS {
+            add_unknown_field(&mut puppet, pointer);
         }
-        if num_threads > self.textures.len() {
-            num_threads = self.textures.len();
+        let model = Model::parse_lenient(&inp(&puppet, &[])[..]).unwrap();
+        let mut serialized = Vec::new();
+        model.serialize(&mut serialized).unwrap();
+        // Known fields may get written differently, such as missing options as null.
+        let serialized = puppet_json(&serialized);
+        for &(pointer, _) in UNKNOWN_FIELDS {
+            assert_eq!(
+                serialized.pointer(&format!("{pointer}/futureField")),
+                Some(&json!({ "pointer": pointer }))
+            );
         }
+    }
 
-        let (tx2, rx2) = mpsc::channel();
-        let mut pipes = Vec::with_capacity(num_threads);
-        for _ in 0..num_threads {
-            let (tx, rx) = mpsc::channel::<(usize, CompressedTexture)>();
-            let tx2 = tx2.clone();
-            std::thread::Builder::new()
-                .name(String::from("Texture Decoder"))
-                .spawn(move || {
-                    while let Ok((i, tex)) = rx.recv() {
-                        let tex = tex.decode();
-                        tx2.send((i, tex)).unwrap();
-                    }
-                })
-                .unwrap();
-            pipes.push(tx);
+    #[test]
+    fn strict_rejects_unknown_fields() {
+        assert!(Model::parse(&inp(&puppet(), &[])[..]).is_ok());
+        for &(pointer, location) in UNKNOWN_FIELDS {
+            let mut puppet = puppet();
+            add_unknown_field(&mut puppet, pointer);
+            let err = Model::parse(&inp(&puppet, &[])[..]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(
+                err.to_string(),
+                format!("Unknown field `futureField` in {location}")
+            );
         }
+    }
 
-        for ((i, tex), tx) in self
-            .textures
-            .drain(..)
-            .enumerate()
-            .zip(pipes.iter().cycle())
-        {
-            tx.send((i, tex)).unwrap();
+    /// An extension section containing these extensions.
+    fn ext_sect(extensions: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut section = EXT.to_vec();
+        section.extend((extensions.len() as u32).to_be_bytes());
+        for (name, payload) in extensions {
+            section.extend((name.len() as u32).to_be_bytes());
+            section.extend(name.as_bytes());
+            section.extend((payload.len() as u32).to_be_bytes());
+            section.extend(*payload);
         }
+        section
+    }
 
-        (num, rx2)
+    #[test]
+    fn extensions_round_trip() {
+        let section = ext_sect(&[("org.example.empty", b""), ("org.example.data", b"\0\x01")]);
+        let model = Model::parse(&inp(&puppet(), &section)[..]).unwrap();
+        assert_eq!(
+            model.extensions,
+            Extensions::from([
+                (String::from("org.example.empty"), Vec::new()),
+                (String::from("org.example.data"), vec![0, 1]),
+            ])
+        );
+        let mut serialized = Vec::new();
+        model.serialize(&mut serialized).unwrap();
+        // Written back in name order.
+        let section = ext_sect(&[("org.example.data", b"\0\x01"), ("org.example.empty", b"")]);
+        assert!(serialized.ends_with(&section));
+        let model = Model::parse(&serialized[..]).unwrap();
+        assert_eq!(model.extensions.len(), 2);
     }
 
-    #[cfg(not(feature = "parallel"))]
-    pub fn decode_textures(&mut self) -> TextureReceiver {
-        let num = self.textures.len();
-        let (tx, rx) = mpsc::channel();
-        for (i, tex) in self.textures.drain(..).enumerate() {
-            let tex = tex.decode();
-            tx.send((i, tex)).unwrap();
+    #[test]
+    fn no_extensions() {
+        let model = Model::parse(&inp(&puppet(), &[])[..]).unwrap();
+        assert!(model.extensions.is_empty());
+        let mut serialized = Vec::new();
+        model.serialize(&mut serialized).unwrap();
+        // The file ends right after the empty texture section.
+        let tail = [TEX, &0u32.to_be_bytes()].concat();
+        assert!(serialized.ends_with(&tail));
+
+        let err = Model::parse(&inp(&puppet(), b"EXT")[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = Model::parse(&inp(&puppet(), b"NOT_SECT")[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    /// Fails every other read as interrupted, like a reader getting signals, so that each read
+    /// following a successful one gets interrupted.
+    struct Interrupting<R> {
+        reader: R,
+        interrupt: bool,
+    }
+
+    impl<R: io::Read> io::Read for Interrupting<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            self.reader.read(buf)
+        }
+    }
+
+    #[test]
+    fn interrupted_reads() {
+        for rest in [Vec::new(), ext_sect(&[("org.example", b"data")])] {
+            let inp = inp(&puppet(), &rest);
+            let reader = Interrupting {
+                reader: &inp[..],
+                interrupt: false,
+            };
+            let model = Model::parse(reader).unwrap();
+            assert_eq!(model.extensions.len(), rest.len().min(1));
         }
-        (num, rx)
+    }
+
+    #[test]
+    fn session_bindings() {
+        let mut model = Model::parse(&inp(&puppet(), &[])[..]).unwrap();
+        assert!(model.session_bindings().is_none());
+
+        let bindings = json!([
+            {
+                "name": "Head Yaw",
+                "bindingType": "RatioBinding",
+                "param": 100,
+                "axis": 0,
+                "sourceName": "HeadYaw",
+                "sourceDisplayName": "Head Yaw",
+                "inRange": [-1., 1.],
+                "outRange": [0., 1.],
+                "inverse": false,
+                "dampenLevel": 2,
+            },
+            {
+                "name": "Blink",
+                "bindingType": "ExpressionBinding",
+                "param": 101,
+                "axis": 1,
+                "expression": "blink()",
+            },
+        ]);
+        model
+            .set_extension_json(SESSION_BINDINGS, &bindings)
+            .unwrap();
+        let parsed = model.session_bindings().unwrap().unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].param, 100);
+        assert_eq!(parsed[0].in_range, Some([-1., 1.]));
+        assert_eq!(parsed[0].unknown["dampenLevel"], json!(2));
+        assert_eq!(parsed[1].expression.as_deref(), Some("blink()"));
+        assert_eq!(parsed[1].source_name, None);
+
+        // Through the file and back, without losing or adding anything.
+        model.set_extension_json(SESSION_BINDINGS, &parsed).unwrap();
+        let mut serialized = Vec::new();
+        model.serialize(&mut serialized).unwrap();
+        let model = Model::parse(&serialized[..]).unwrap();
+        let json: Value = model.extension_json(SESSION_BINDINGS).unwrap().unwrap();
+        assert_eq!(json, bindings);
+    }
+
+    #[test]
+    fn invalid_session_bindings() {
+        let section = ext_sect(&[(SESSION_BINDINGS, br#"[{ "name": "No param" }]"#)]);
+        let model = Model::parse(&inp(&puppet(), &section)[..]).unwrap();
+        let err = model.session_bindings().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "png")]
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in data {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+            }
+        }
+        !crc
+    }
+
+    /// A PNG with these rows, each one starting with its filter byte, stored without
+    /// compression, and with these extra chunks before the image data.
+    #[cfg(feature = "png")]
+    fn png(
+        width: u32,
+        color_type: u8,
+        depth: u8,
+        rows: &[&[u8]],
+        chunks: &[(&[u8; 4], &[u8])],
+    ) -> Vec<u8> {
+        let raw: Vec<u8> = rows.iter().flat_map(|row| [&[0], *row].concat()).collect();
+        let (mut a, mut b) = (1u32, 0u32);
+        for &byte in raw.iter() {
+            a = (a + byte as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+        let length = raw.len() as u16;
+        let mut zlib = vec![0x78, 0x01, 0x01];
+        zlib.extend(length.to_le_bytes());
+        zlib.extend((!length).to_le_bytes());
+        zlib.extend(raw);
+        zlib.extend(((b << 16) | a).to_be_bytes());
+
+        let mut ihdr = width.to_be_bytes().to_vec();
+        ihdr.extend((rows.len() as u32).to_be_bytes());
+        ihdr.extend([depth, color_type, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        let all_chunks = [(b"IHDR", &ihdr[..])]
+            .into_iter()
+            .chain(chunks.iter().copied())
+            .chain([(b"IDAT", &zlib[..]), (b"IEND", &[][..])]);
+        for (kind, data) in all_chunks {
+            png.extend((data.len() as u32).to_be_bytes());
+            let start = png.len();
+            png.extend(kind);
+            png.extend(data);
+            let crc = crc32(&png[start..]);
+            png.extend(crc.to_be_bytes());
+        }
+        png
+    }
+
+    #[cfg(feature = "png")]
+    fn decode_png(png: Vec<u8>) -> (u32, u32, Vec<u8>) {
+        match CompressedTexture::Png(png.into()).decode().unwrap() {
+            Texture::Rgba {
+                width,
+                height,
+                format: PixelFormat::Rgba8,
+                alpha: AlphaMode::Premultiplied,
+                data,
+            } => (width, height, data),
+            texture => panic!("Unexpected {texture:?}"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn png_grayscale() {
+        let png = png(2, 0, 8, &[&[0x40, 0xff]], &[]);
+        assert_eq!(
+            decode_png(png),
+            (2, 1, vec![0x40, 0x40, 0x40, 255, 255, 255, 255, 255])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn png_grayscale_alpha() {
+        let png = png(1, 4, 8, &[&[0xff, 0x80], &[0x40, 0]], &[]);
+        assert_eq!(
+            decode_png(png),
+            (1, 2, vec![0x80, 0x80, 0x80, 0x80, 0, 0, 0, 0])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn png_16_bits() {
+        // Opaque orange, then half transparent white.
+        let row: Vec<u8> = [0xffff, 0x8080, 0, 0xffff, 0xffff, 0xffff, 0xffff, 0x8080]
+            .into_iter()
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        let png = png(2, 6, 16, &[&row], &[]);
+        assert_eq!(
+            decode_png(png),
+            (2, 1, vec![255, 128, 0, 255, 128, 128, 128, 128])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn png_palette() {
+        // Indices 0, 1 and 2 on two bits, the second entry being half transparent and the
+        // third one opaque since the transparency chunk is shorter than the palette.
+        let palette = [255, 0, 0, 0, 255, 0, 0, 0, 255];
+        let png = png(
+            3,
+            3,
+            2,
+            &[&[0b0001_1000]],
+            &[(b"PLTE", &palette), (b"tRNS", &[255, 0x80])],
+        );
+        assert_eq!(
+            decode_png(png),
+            (3, 1, vec![255, 0, 0, 255, 0, 128, 0, 128, 0, 0, 255, 255])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn png_invalid() {
+        let decode = |png: Vec<u8>| CompressedTexture::Png(png.into()).decode().unwrap_err();
+        let mut truncated = png(1, 0, 8, &[&[0]], &[]);
+        truncated.truncate(truncated.len() - 20);
+        assert_eq!(decode(truncated).kind(), io::ErrorKind::UnexpectedEof);
+        // An invalid bit depth for grayscale, which also breaks the checksum of the header.
+        let mut corrupted = png(1, 0, 8, &[&[0]], &[]);
+        corrupted[24] = 3;
+        assert_eq!(decode(corrupted).kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    #[cfg(not(feature = "png"))]
+    fn png_unsupported() {
+        let err = CompressedTexture::Png(Vec::new().into())
+            .decode()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
     }
 }
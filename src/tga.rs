@@ -4,71 +4,384 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-unsafe fn copy_intersperce(dst: *mut u8, src: *const u8) -> *mut u8 {
-    dst.copy_from(src, 3);
-    dst.offset(3).write(255);
-    dst.offset(4)
+use std::io;
+
+const HEADER_SIZE: usize = 18;
+/// As large as any GPU accepts, a header claiming more is more likely crafted than real.
+const MAX_DIMENSION: usize = 16384;
+
+const COLOR_MAPPED: u8 = 1;
+const TRUE_COLOR: u8 = 2;
+const GRAYSCALE: u8 = 3;
+const RLE: u8 = 8;
+
+/// Bits 4 and 5 of the image descriptor.
+const RIGHT_TO_LEFT: u8 = 0x10;
+const TOP_TO_BOTTOM: u8 = 0x20;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn truncated() -> io::Error {
+    invalid("Truncated TGA image data")
+}
+
+fn read_le_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+/// Expands a 5-5-5 pixel, with the top bit being alpha if the image has any.
+fn rgba_from_16(pixel: u16, has_alpha: bool) -> [u8; 4] {
+    let expand = |value: u16| {
+        let value = (value & 0x1f) as u8;
+        (value << 3) | (value >> 2)
+    };
+    let alpha = if has_alpha && pixel & 0x8000 == 0 {
+        0
+    } else {
+        255
+    };
+    [
+        expand(pixel >> 10),
+        expand(pixel >> 5),
+        expand(pixel),
+        alpha,
+    ]
 }
 
-// This function decodes to RGBA instead of RGB, by interspercing a 255 byte every three bytes.
-fn decode_rle_24(mut rle: &[u8], mut ptr: *mut u8) {
-    while rle.len() > 18 {
-        let c = rle[0];
-        rle = &rle[1..];
-        if c & 0x80 != 0 {
-            let c = (c & !0x80) + 1;
-            let pixel = &rle[..3];
-            rle = &rle[3..];
-            for _ in 0..c {
-                ptr = unsafe { copy_intersperce(ptr, pixel.as_ptr()) };
+/// Decodes every pixel of the image, in file order, by passing each of them through
+/// `convert` which returns None if a pixel is invalid.
+fn decode_pixels<const N: usize, F>(
+    mut data: &[u8],
+    rle: bool,
+    mut out: &mut [u8],
+    convert: F,
+) -> io::Result<()>
+where
+    F: Fn([u8; N]) -> Option<[u8; 4]>,
+{
+    let convert = |pixel: &[u8]| {
+        convert(pixel.try_into().unwrap()).ok_or_else(|| invalid("Color map index out of range"))
+    };
+
+    if !rle {
+        let data = data.get(..out.len() / 4 * N).ok_or_else(truncated)?;
+        for (src, dst) in data.chunks_exact(N).zip(out.chunks_exact_mut(4)) {
+            dst.copy_from_slice(&convert(src)?);
+        }
+        return Ok(());
+    }
+
+    while !out.is_empty() {
+        let (&packet, rest) = data.split_first().ok_or_else(truncated)?;
+        // Packets may span multiple rows, but never past the end of the image.
+        let count = ((packet & 0x7f) as usize + 1).min(out.len() / 4);
+        let (dst, remaining) = out.split_at_mut(count * 4);
+        out = remaining;
+        if packet & 0x80 != 0 {
+            let pixel = convert(rest.get(..N).ok_or_else(truncated)?)?;
+            for dst in dst.chunks_exact_mut(4) {
+                dst.copy_from_slice(&pixel);
             }
+            data = &rest[N..];
         } else {
-            let c = c + 1;
-            for _ in 0..c {
-                let pixel = &rle[..3];
-                rle = &rle[3..];
-                ptr = unsafe { copy_intersperce(ptr, pixel.as_ptr()) };
+            let src = rest.get(..count * N).ok_or_else(truncated)?;
+            for (src, dst) in src.chunks_exact(N).zip(dst.chunks_exact_mut(4)) {
+                dst.copy_from_slice(&convert(src)?);
             }
+            data = &rest[count * N..];
         }
     }
+    Ok(())
 }
 
-fn decode_rle_32(mut rle: &[u8], mut ptr: *mut u8) {
-    while rle.len() > 16 {
-        let c = rle[0];
-        rle = &rle[1..];
-        if c & 0x80 != 0 {
-            let c = (c & !0x80) + 1;
-            let pixel = &rle[..4];
-            rle = &rle[4..];
-            for _ in 0..c {
-                unsafe {
-                    core::ptr::copy(pixel.as_ptr(), ptr, 4);
-                    ptr = ptr.offset(4);
-                }
-            }
-        } else {
-            let c = c + 1;
-            let pixels = &rle[..4 * c as usize];
-            rle = &rle[4 * c as usize..];
-            unsafe {
-                core::ptr::copy(pixels.as_ptr(), ptr, 4 * c as usize);
-                ptr = ptr.offset(4 * c as isize);
+fn read_color_map(data: &[u8], depth: u8, has_alpha: bool) -> io::Result<Vec<[u8; 4]>> {
+    Ok(match depth {
+        15 | 16 => data
+            .chunks_exact(2)
+            .map(|entry| rgba_from_16(read_le_u16(entry, 0), has_alpha && depth == 16))
+            .collect(),
+        24 => data
+            .chunks_exact(3)
+            .map(|entry| [entry[2], entry[1], entry[0], 255])
+            .collect(),
+        32 => data
+            .chunks_exact(4)
+            .map(|entry| [entry[2], entry[1], entry[0], entry[3]])
+            .collect(),
+        depth => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported TGA color map depth {depth}"),
+            ))
+        }
+    })
+}
+
+/// Reverses the order of the rows, or of the pixels in each row, to go from the image origin
+/// to the top-left corner.
+fn reorient(out: &mut [u8], width: usize, height: usize, descriptor: u8) {
+    let stride = width * 4;
+    if descriptor & RIGHT_TO_LEFT != 0 {
+        for row in out.chunks_exact_mut(stride) {
+            for x in 0..width / 2 {
+                let (left, right) = row.split_at_mut((width - 1 - x) * 4);
+                left[x * 4..x * 4 + 4].swap_with_slice(&mut right[..4]);
             }
         }
     }
+    if descriptor & TOP_TO_BOTTOM == 0 {
+        for y in 0..height / 2 {
+            let (top, bottom) = out.split_at_mut((height - 1 - y) * stride);
+            top[y * stride..(y + 1) * stride].swap_with_slice(&mut bottom[..stride]);
+        }
+    }
+}
+
+/// Decodes a TGA image into straight RGBA pixels, with the first row being the top one.
+///
+/// Uncompressed and RLE images are supported, in true color (15, 16, 24 or 32 bits per pixel),
+/// grayscale (8 bits, or 16 with alpha) or color mapped (8 or 16 bits indices).
+pub fn decode(tga: &[u8]) -> io::Result<(u32, u32, Vec<u8>)> {
+    let header = tga
+        .get(..HEADER_SIZE)
+        .ok_or_else(|| invalid("Truncated TGA header"))?;
+    let id_length = header[0] as usize;
+    let color_map_type = header[1];
+    let image_type = header[2];
+    let color_map_first = read_le_u16(header, 3) as usize;
+    let color_map_length = read_le_u16(header, 5) as usize;
+    let color_map_depth = header[7];
+    let width = read_le_u16(header, 12) as usize;
+    let height = read_le_u16(header, 14) as usize;
+    let depth = header[16];
+    let descriptor = header[17];
+    let has_alpha = descriptor & 0xf != 0;
+
+    let mut offset = HEADER_SIZE + id_length;
+    let color_map = match color_map_type {
+        0 => Vec::new(),
+        1 => {
+            let size = color_map_length * (color_map_depth as usize).div_ceil(8);
+            let data = tga
+                .get(offset..offset + size)
+                .ok_or_else(|| invalid("Truncated TGA color map"))?;
+            offset += size;
+            read_color_map(data, color_map_depth, has_alpha)?
+        }
+        _ => return Err(invalid("Unknown TGA color map type")),
+    };
+    let data = tga.get(offset..).ok_or_else(truncated)?;
+
+    if width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(invalid("TGA image too large"));
+    }
+    let size = width
+        .checked_mul(height)
+        .and_then(|count| count.checked_mul(4))
+        .ok_or_else(|| invalid("TGA image too large"))?;
+    let rle = image_type & RLE != 0;
+    // Without compression, the image has to contain every pixel before they get allocated.
+    if !rle
+        && matches!(depth, 8 | 15 | 16 | 24 | 32)
+        && data.len() < (size / 4).saturating_mul((depth as usize).div_ceil(8))
+    {
+        return Err(truncated());
+    }
+
+    let mut out = vec![0u8; size];
+    let lookup = |index: usize| color_map.get(index.checked_sub(color_map_first)?).copied();
+    match (image_type & !RLE, depth) {
+        (COLOR_MAPPED, 8) if color_map_type == 1 => {
+            decode_pixels(data, rle, &mut out, |[index]| lookup(index as usize))
+        }
+        (COLOR_MAPPED, 16) if color_map_type == 1 => decode_pixels(data, rle, &mut out, |index| {
+            lookup(u16::from_le_bytes(index) as usize)
+        }),
+        (TRUE_COLOR, 15 | 16) => decode_pixels(data, rle, &mut out, |pixel| {
+            Some(rgba_from_16(
+                u16::from_le_bytes(pixel),
+                has_alpha && depth == 16,
+            ))
+        }),
+        (TRUE_COLOR, 24) => decode_pixels(data, rle, &mut out, |[b, g, r]| Some([r, g, b, 255])),
+        (TRUE_COLOR, 32) => decode_pixels(data, rle, &mut out, |[b, g, r, a]| Some([r, g, b, a])),
+        (GRAYSCALE, 8) => decode_pixels(data, rle, &mut out, |[l]| Some([l, l, l, 255])),
+        (GRAYSCALE, 16) => decode_pixels(data, rle, &mut out, |[l, a]| Some([l, l, l, a])),
+        (image_type, depth) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported TGA image type {image_type} with {depth} bits per pixel"),
+            ))
+        }
+    }?;
+
+    reorient(&mut out, width, height, descriptor);
+    Ok((width as u32, height as u32, out))
 }
 
-pub fn decode(tga: &[u8]) -> (u32, u32, Vec<u8>) {
-    let width = u16::from_le_bytes([tga[12], tga[13]]) as u32;
-    let height = u16::from_le_bytes([tga[14], tga[15]]) as u32;
-    let mut data = Vec::with_capacity((width * height * 4) as usize);
-    let pixel_depth = tga[16];
-    match pixel_depth {
-        24 => decode_rle_24(&tga[18..], data.as_mut_ptr()),
-        32 => decode_rle_32(&tga[18..], data.as_mut_ptr()),
-        depth => todo!("Unimplemented pixel depth {depth}"),
-    }
-    unsafe { data.set_len((width * height * 4) as usize) };
-    (width, height, data)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A header without image ID, with a color map of `(first, length, depth)` if any.
+    fn header(
+        image_type: u8,
+        color_map: Option<(u16, u16, u8)>,
+        (width, height): (u16, u16),
+        depth: u8,
+        descriptor: u8,
+    ) -> Vec<u8> {
+        let (first, length, map_depth) = color_map.unwrap_or_default();
+        let mut header = vec![0, color_map.is_some() as u8, image_type];
+        header.extend(first.to_le_bytes());
+        header.extend(length.to_le_bytes());
+        header.push(map_depth);
+        header.extend([0; 4]);
+        header.extend(width.to_le_bytes());
+        header.extend(height.to_le_bytes());
+        header.extend([depth, descriptor]);
+        header
+    }
+
+    fn image(header: Vec<u8>, data: &[u8]) -> Vec<u8> {
+        let mut tga = header;
+        tga.extend_from_slice(data);
+        tga
+    }
+
+    fn error(tga: &[u8]) -> String {
+        let err = decode(tga).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        err.to_string()
+    }
+
+    #[test]
+    fn truncated_header() {
+        let tga = header(TRUE_COLOR, None, (1, 1), 32, 8);
+        assert_eq!(error(&tga[..HEADER_SIZE - 1]), "Truncated TGA header");
+        assert_eq!(error(&[]), "Truncated TGA header");
+        // The image ID goes past the end.
+        let mut tga = tga;
+        tga[0] = 4;
+        assert_eq!(error(&tga), "Truncated TGA image data");
+    }
+
+    #[test]
+    fn truncated_pixels() {
+        let tga = image(header(TRUE_COLOR, None, (2, 2), 24, 0), &[0; 11]);
+        assert_eq!(error(&tga), "Truncated TGA image data");
+        // A run packet missing its pixel, then a raw packet missing its second one.
+        let tga = image(header(TRUE_COLOR | RLE, None, (2, 2), 24, 0), &[0x80]);
+        assert_eq!(error(&tga), "Truncated TGA image data");
+        let tga = image(
+            header(TRUE_COLOR | RLE, None, (2, 2), 24, 0),
+            &[0x01, 0, 0, 0],
+        );
+        assert_eq!(error(&tga), "Truncated TGA image data");
+        let tga = image(
+            header(COLOR_MAPPED, Some((0, 4, 24)), (1, 1), 8, 0),
+            &[0; 11],
+        );
+        assert_eq!(error(&tga), "Truncated TGA color map");
+    }
+
+    #[test]
+    fn too_large() {
+        // Only a header, which mustn’t make the decoder allocate anything.
+        let tga = header(TRUE_COLOR, None, (u16::MAX, u16::MAX), 32, 8);
+        assert_eq!(error(&tga), "TGA image too large");
+        let tga = header(TRUE_COLOR, None, (16384, 16384), 32, 8);
+        assert_eq!(error(&tga), "Truncated TGA image data");
+    }
+
+    #[test]
+    fn rle_across_rows() {
+        // A run of three red pixels wrapping to the second row, then a raw green one, and a
+        // run going past the end of the image which gets cut.
+        let data = [0x82, 0, 0, 255, 0x00, 0, 255, 0];
+        let tga = image(
+            header(TRUE_COLOR | RLE, None, (2, 2), 24, TOP_TO_BOTTOM),
+            &data,
+        );
+        let red = [255, 0, 0, 255];
+        let green = [0, 255, 0, 255];
+        assert_eq!(
+            decode(&tga).unwrap(),
+            (2, 2, [red, red, red, green].concat())
+        );
+        let data = [0x87, 0, 0, 255];
+        let tga = image(
+            header(TRUE_COLOR | RLE, None, (2, 2), 24, TOP_TO_BOTTOM),
+            &data,
+        );
+        assert_eq!(decode(&tga).unwrap().2, [red; 4].concat());
+    }
+
+    #[test]
+    fn origins() {
+        // Pixels 1 to 4 in file order, as grayscale.
+        let data = [1, 2, 3, 4];
+        let decode_gray = |descriptor| {
+            let tga = image(header(GRAYSCALE, None, (2, 2), 8, descriptor), &data);
+            let (_, _, pixels) = decode(&tga).unwrap();
+            pixels
+                .chunks_exact(4)
+                .map(|pixel| pixel[0])
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(decode_gray(TOP_TO_BOTTOM), [1, 2, 3, 4]);
+        assert_eq!(decode_gray(0), [3, 4, 1, 2]);
+        assert_eq!(decode_gray(TOP_TO_BOTTOM | RIGHT_TO_LEFT), [2, 1, 4, 3]);
+        assert_eq!(decode_gray(RIGHT_TO_LEFT), [4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn color_mapped() {
+        // Entries 2 and 3 of the map, in BGR.
+        let mut data = vec![255, 0, 0, 0, 0, 255];
+        data.extend([3, 2]);
+        let tga = image(header(COLOR_MAPPED, Some((2, 2, 24)), (2, 1), 8, 0), &data);
+        assert_eq!(
+            decode(&tga).unwrap(),
+            (2, 1, vec![255, 0, 0, 255, 0, 0, 255, 255])
+        );
+        data[6] = 4;
+        let tga = image(header(COLOR_MAPPED, Some((2, 2, 24)), (2, 1), 8, 0), &data);
+        assert_eq!(error(&tga), "Color map index out of range");
+    }
+
+    #[test]
+    fn grayscale() {
+        let tga = image(header(GRAYSCALE, None, (2, 1), 8, 0), &[0x40, 0xff]);
+        assert_eq!(
+            decode(&tga).unwrap().2,
+            [0x40, 0x40, 0x40, 255, 0xff, 0xff, 0xff, 255]
+        );
+        let tga = image(
+            header(GRAYSCALE | RLE, None, (2, 1), 16, 8),
+            &[0x81, 0x40, 0x80],
+        );
+        assert_eq!(
+            decode(&tga).unwrap().2,
+            [0x40, 0x40, 0x40, 0x80, 0x40, 0x40, 0x40, 0x80]
+        );
+    }
+
+    #[test]
+    fn sixteen_bits() {
+        // Opaque red and transparent blue in 1-5-5-5.
+        let data = [0x00, 0xfc, 0x1f, 0x00];
+        let tga = image(header(TRUE_COLOR, None, (2, 1), 16, 1), &data);
+        assert_eq!(decode(&tga).unwrap().2, [255, 0, 0, 255, 0, 0, 255, 0]);
+        // Without alpha bits in the descriptor, the top bit gets ignored.
+        let tga = image(header(TRUE_COLOR, None, (2, 1), 16, 0), &data);
+        assert_eq!(decode(&tga).unwrap().2, [255, 0, 0, 255, 0, 0, 255, 255]);
+        let tga = image(header(TRUE_COLOR, None, (1, 1), 12, 0), &[0, 0]);
+        assert_eq!(
+            error(&tga),
+            "Unsupported TGA image type 2 with 12 bits per pixel"
+        );
+    }
 }
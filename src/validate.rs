@@ -0,0 +1,651 @@
+// Copyright (c) 2023 Emmanuel Gil Peyrot <linkmauve@linkmauve.fr>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Consistency checks on a parsed model, for the invariants the renderer relies on.
+
+use crate::parser::{Binding, BindingValues, Param};
+use crate::{Model, Node};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Texture slots which aren’t used by a part contain this index.
+const NO_TEXTURE: usize = u32::MAX as usize;
+
+/// A problem found in a model by [`Model::validate`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    DuplicateUuid(u32),
+    /// Parts and composites have to be children of another node.
+    RootNotANode,
+    MissingMaskSource {
+        node: u32,
+        source: u32,
+    },
+    /// Only parts can be drawn into the stencil buffer.
+    MaskSourceNotAPart {
+        node: u32,
+        source: u32,
+    },
+    /// The source of the mask of an enabled part is disabled itself, or in a disabled subtree,
+    /// so it never gets flattened for the renderer.
+    DisabledMaskSource {
+        node: u32,
+        source: u32,
+    },
+    MissingBindingNode {
+        param: String,
+        node: u32,
+    },
+    TextureOutOfRange {
+        node: u32,
+        texture: usize,
+        num_textures: usize,
+    },
+    /// The vertices and texture coordinates of a mesh don’t pair up.
+    MeshLengthMismatch {
+        node: u32,
+        verts: usize,
+        uvs: usize,
+    },
+    MeshIndexOutOfRange {
+        node: u32,
+        index: u16,
+        num_vertices: usize,
+    },
+    /// Every param needs at least one point on each axis.
+    EmptyAxisPoints {
+        param: String,
+        axis: usize,
+    },
+    /// Axis points have to be strictly increasing.
+    UnsortedAxisPoints {
+        param: String,
+        axis: usize,
+    },
+    /// The values or the set flags of a binding don’t form a grid of one entry per pair of
+    /// axis points.
+    BindingGridMismatch {
+        param: String,
+        node: u32,
+        expected: (usize, usize),
+    },
+    /// A deform binding doesn’t have one offset per vertex of the mesh it applies to.
+    DeformLengthMismatch {
+        param: String,
+        node: u32,
+        expected: usize,
+        found: usize,
+    },
+    /// A deform binding applies to a node without a mesh.
+    DeformWithoutMesh {
+        param: String,
+        node: u32,
+    },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationError::DuplicateUuid(uuid) => {
+                write!(f, "Multiple nodes have the UUID {uuid}")
+            }
+            ValidationError::RootNotANode => {
+                write!(f, "The root node can’t be a part or a composite")
+            }
+            ValidationError::MissingMaskSource { node, source } => {
+                write!(
+                    f,
+                    "Node {node} is masked by node {source} which doesn’t exist"
+                )
+            }
+            ValidationError::MaskSourceNotAPart { node, source } => {
+                write!(
+                    f,
+                    "Node {node} is masked by node {source} which isn’t a part"
+                )
+            }
+            ValidationError::DisabledMaskSource { node, source } => {
+                write!(
+                    f,
+                    "Node {node} is masked by node {source} which is disabled"
+                )
+            }
+            ValidationError::MissingBindingNode { param, node } => {
+                write!(
+                    f,
+                    "Param {param} is bound to node {node} which doesn’t exist"
+                )
+            }
+            ValidationError::TextureOutOfRange {
+                node,
+                texture,
+                num_textures,
+            } => write!(
+                f,
+                "Node {node} uses texture {texture} but there are only {num_textures}"
+            ),
+            ValidationError::MeshLengthMismatch { node, verts, uvs } => write!(
+                f,
+                "Mesh of node {node} has {verts} vertex coordinates but {uvs} texture coordinates"
+            ),
+            ValidationError::MeshIndexOutOfRange {
+                node,
+                index,
+                num_vertices,
+            } => write!(
+                f,
+                "Mesh of node {node} uses vertex {index} but only has {num_vertices}"
+            ),
+            ValidationError::EmptyAxisPoints { param, axis } => {
+                write!(f, "Axis {axis} of param {param} has no points")
+            }
+            ValidationError::UnsortedAxisPoints { param, axis } => {
+                write!(f, "Axis points {axis} of param {param} aren’t sorted")
+            }
+            ValidationError::BindingGridMismatch {
+                param,
+                node,
+                expected: (x, y),
+            } => write!(
+                f,
+                "Binding of param {param} to node {node} doesn’t have {x}×{y} values"
+            ),
+            ValidationError::DeformLengthMismatch {
+                param,
+                node,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Binding of param {param} deforms {found} vertices but node {node} has {expected}"
+            ),
+            ValidationError::DeformWithoutMesh { param, node } => write!(
+                f,
+                "Binding of param {param} deforms node {node} which doesn’t have a mesh"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Whether this grid has exactly one row per x axis point and one column per y axis point.
+fn is_grid<T>(rows: &[Vec<T>], (x, y): (usize, usize)) -> bool {
+    rows.len() == x && rows.iter().all(|row| row.len() == y)
+}
+
+/// What the other checks need to know about each node.
+struct NodeInfo {
+    /// The number of vertices of its mesh, if it has one.
+    num_vertices: Option<usize>,
+    is_part: bool,
+    /// Whether it and all of its ancestors are enabled, otherwise the renderer skips it.
+    enabled: bool,
+}
+
+struct Validator {
+    num_textures: usize,
+    nodes: HashMap<u32, NodeInfo>,
+    errors: Vec<ValidationError>,
+    /// Each enabled masked node, along with the source of the mask.
+    masks: Vec<(u32, u32)>,
+}
+
+impl Validator {
+    fn visit_node(&mut self, node: &Node, parent_enabled: bool) {
+        let uuid = node.uuid();
+        let enabled = parent_enabled && node.enabled();
+        let mut num_vertices = None;
+        if let Node::Part {
+            textures,
+            mesh,
+            masks,
+            ..
+        } = node
+        {
+            for (i, &texture) in textures.iter().enumerate() {
+                if (i == 0 || texture != NO_TEXTURE) && texture >= self.num_textures {
+                    self.errors.push(ValidationError::TextureOutOfRange {
+                        node: uuid,
+                        texture,
+                        num_textures: self.num_textures,
+                    });
+                }
+            }
+
+            if mesh.verts.len() != mesh.uvs.len() || mesh.verts.len() % 2 != 0 {
+                self.errors.push(ValidationError::MeshLengthMismatch {
+                    node: uuid,
+                    verts: mesh.verts.len(),
+                    uvs: mesh.uvs.len(),
+                });
+            }
+            let vertices = mesh.verts.len().min(mesh.uvs.len()) / 2;
+            // One error per mesh is enough to point at the problem.
+            if let Some(&index) = mesh.indices.iter().find(|&&i| i as usize >= vertices) {
+                self.errors.push(ValidationError::MeshIndexOutOfRange {
+                    node: uuid,
+                    index,
+                    num_vertices: vertices,
+                });
+            }
+            num_vertices = Some(mesh.verts.len() / 2);
+
+            if enabled {
+                self.masks
+                    .extend(masks.iter().map(|mask| (uuid, mask.source)));
+            }
+        }
+
+        let info = NodeInfo {
+            num_vertices,
+            is_part: matches!(node, Node::Part { .. }),
+            enabled,
+        };
+        if self.nodes.insert(uuid, info).is_some() {
+            self.errors.push(ValidationError::DuplicateUuid(uuid));
+        }
+        for child in node.children() {
+            self.visit_node(child, enabled);
+        }
+    }
+
+    fn visit_param(&mut self, param: &Param) {
+        for (axis, points) in param.axis_points.iter().enumerate() {
+            if points.is_empty() {
+                self.errors.push(ValidationError::EmptyAxisPoints {
+                    param: param.name.clone(),
+                    axis,
+                });
+            } else if points.windows(2).any(|pair| pair[0] >= pair[1]) {
+                self.errors.push(ValidationError::UnsortedAxisPoints {
+                    param: param.name.clone(),
+                    axis,
+                });
+            }
+        }
+        let shape = (param.axis_points[0].len(), param.axis_points[1].len());
+        for binding in param.bindings.iter() {
+            self.visit_binding(param, binding, shape);
+        }
+    }
+
+    fn visit_binding(&mut self, param: &Param, binding: &Binding, shape: (usize, usize)) {
+        let node = binding.node;
+        let Some(num_vertices) = self.nodes.get(&node).map(|info| info.num_vertices) else {
+            self.errors.push(ValidationError::MissingBindingNode {
+                param: param.name.clone(),
+                node,
+            });
+            return;
+        };
+
+        let is_values_grid = match &binding.values {
+            BindingValues::ZSort(values)
+            | BindingValues::TransformTX(values)
+            | BindingValues::TransformTY(values)
+            | BindingValues::TransformTZ(values)
+            | BindingValues::TransformSX(values)
+            | BindingValues::TransformSY(values)
+            | BindingValues::TransformRX(values)
+            | BindingValues::TransformRY(values)
+            | BindingValues::TransformRZ(values) => is_grid(values, shape),
+            BindingValues::Deform(values) => {
+                match num_vertices {
+                    None => self.errors.push(ValidationError::DeformWithoutMesh {
+                        param: param.name.clone(),
+                        node,
+                    }),
+                    Some(expected) => {
+                        if let Some(deform) = values
+                            .iter()
+                            .flatten()
+                            .find(|deform| deform.len() != expected)
+                        {
+                            self.errors.push(ValidationError::DeformLengthMismatch {
+                                param: param.name.clone(),
+                                node,
+                                expected,
+                                found: deform.len(),
+                            });
+                        }
+                    }
+                }
+                is_grid(values, shape)
+            }
+        };
+        if !is_values_grid || !is_grid(&binding.is_set, shape) {
+            self.errors.push(ValidationError::BindingGridMismatch {
+                param: param.name.clone(),
+                node,
+                expected: shape,
+            });
+        }
+    }
+}
+
+impl Model {
+    /// Checks that the puppet is consistent with itself and with the textures, reporting every
+    /// problem found instead of stopping at the first one.
+    ///
+    /// The renderer assumes a valid model, and may panic otherwise.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let puppet = &self.puppet;
+        let mut validator = Validator {
+            num_textures: self.textures.len(),
+            nodes: HashMap::new(),
+            errors: Vec::new(),
+            masks: Vec::new(),
+        };
+
+        if matches!(puppet.nodes, Node::Part { .. } | Node::Composite { .. }) {
+            validator.errors.push(ValidationError::RootNotANode);
+        }
+        validator.visit_node(&puppet.nodes, true);
+
+        // Masks can refer to nodes further down the tree, so only check them once every node
+        // has been seen.
+        for (node, source) in std::mem::take(&mut validator.masks) {
+            let error = match validator.nodes.get(&source) {
+                None => ValidationError::MissingMaskSource { node, source },
+                Some(info) if !info.is_part => ValidationError::MaskSourceNotAPart { node, source },
+                Some(info) if !info.enabled => ValidationError::DisabledMaskSource { node, source },
+                Some(_) => continue,
+            };
+            validator.errors.push(error);
+        }
+
+        for param in puppet.param.iter() {
+            validator.visit_param(param);
+        }
+
+        if validator.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(validator.errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{CompressedTexture, Extensions};
+    use serde_json::{json, Value};
+
+    fn node(type_: &str, uuid: u32, children: Vec<Value>) -> Value {
+        json!({
+            "type": type_,
+            "uuid": uuid,
+            "name": format!("{type_} {uuid}"),
+            "enabled": true,
+            "zsort": 0.,
+            "transform": { "trans": [0., 0., 0.], "rot": [0., 0., 0.], "scale": [1., 1.] },
+            "lockToRoot": false,
+            "children": children,
+        })
+    }
+
+    /// A quad using the first texture, masked by these parts.
+    fn part(uuid: u32, masks: &[u32]) -> Value {
+        let mut part = node("Part", uuid, Vec::new());
+        let masks: Vec<_> = masks
+            .iter()
+            .map(|&source| json!({ "source": source, "mode": "Mask" }))
+            .collect();
+        let verts = [0., 0., 0., 1., 1., 0., 1., 1.];
+        part.as_object_mut().unwrap().extend(
+            json!({
+                "textures": [0, u32::MAX, u32::MAX],
+                "mesh": {
+                    "verts": verts,
+                    "uvs": verts,
+                    "indices": [0, 1, 2, 2, 1, 3],
+                    "origin": [0., 0.],
+                },
+                "blend_mode": "Normal",
+                "opacity": 1.,
+                "tint": [1., 1., 1.],
+                "screenTint": [0., 0., 0.],
+                "mask_threshold": 0.5,
+                "masks": masks,
+                "psdLayerPath": "",
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        );
+        part
+    }
+
+    /// A param with two points on its first axis and one on the second, translating a node.
+    fn param(node: u32) -> Value {
+        json!({
+            "uuid": 100,
+            "name": "param",
+            "is_vec2": false,
+            "min": [0., 0.],
+            "max": [1., 1.],
+            "defaults": [0., 0.],
+            "axis_points": [[0., 1.], [0.]],
+            "bindings": [{
+                "node": node,
+                "param_name": "transform.t.x",
+                "values": [[0.], [10.]],
+                "isSet": [[true], [true]],
+                "interpolate_mode": "Linear",
+            }],
+        })
+    }
+
+    /// Parts 2 and 3 under node 1, the second one masked by the first one, with a param bound
+    /// to part 2.
+    fn puppet() -> Value {
+        json!({
+            "meta": { "version": "1.0-alpha", "thumbnailId": u32::MAX, "preservePixels": false },
+            "physics": { "pixelsPerMeter": 1000., "gravity": 9.8 },
+            "nodes": node("Node", 1, vec![part(2, &[]), part(3, &[2])]),
+            "param": [param(2)],
+        })
+    }
+
+    fn validate(puppet: Value) -> Result<(), Vec<ValidationError>> {
+        Model {
+            puppet: serde_json::from_value(puppet).unwrap(),
+            textures: vec![CompressedTexture::Png(Vec::new().into())],
+            extensions: Extensions::new(),
+        }
+        .validate()
+    }
+
+    #[test]
+    fn valid() {
+        assert_eq!(validate(puppet()), Ok(()));
+    }
+
+    #[test]
+    fn duplicate_uuids() {
+        let mut puppet = puppet();
+        puppet["nodes"]["children"][1]["uuid"] = json!(2);
+        // The second part now gets masked by itself, which is fine.
+        assert_eq!(
+            validate(puppet),
+            Err(vec![ValidationError::DuplicateUuid(2)])
+        );
+    }
+
+    #[test]
+    fn root_not_a_node() {
+        let mut puppet = puppet();
+        puppet["nodes"] = part(1, &[]);
+        puppet["param"] = json!([param(1)]);
+        assert_eq!(validate(puppet), Err(vec![ValidationError::RootNotANode]));
+    }
+
+    #[test]
+    fn dangling_references() {
+        let mut puppet = puppet();
+        puppet["nodes"]["children"][1]["masks"][0]["source"] = json!(4);
+        puppet["param"][0]["bindings"][0]["node"] = json!(5);
+        assert_eq!(
+            validate(puppet),
+            Err(vec![
+                ValidationError::MissingMaskSource { node: 3, source: 4 },
+                ValidationError::MissingBindingNode {
+                    param: String::from("param"),
+                    node: 5,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn mask_sources() {
+        // Masked by the root, which isn’t a part.
+        let mut value = puppet();
+        value["nodes"]["children"][1]["masks"][0]["source"] = json!(1);
+        assert_eq!(
+            validate(value),
+            Err(vec![ValidationError::MaskSourceNotAPart {
+                node: 3,
+                source: 1
+            }])
+        );
+
+        // Disabled through its parent.
+        let mut value = puppet();
+        let source = value["nodes"]["children"][0].take();
+        value["nodes"]["children"][0] = node("Node", 4, vec![source]);
+        value["nodes"]["children"][0]["enabled"] = json!(false);
+        assert_eq!(
+            validate(value.clone()),
+            Err(vec![ValidationError::DisabledMaskSource {
+                node: 3,
+                source: 2
+            }])
+        );
+
+        // Unless the masked part is disabled too.
+        value["nodes"]["children"][1]["enabled"] = json!(false);
+        assert_eq!(validate(value), Ok(()));
+    }
+
+    #[test]
+    fn texture_out_of_range() {
+        let mut puppet = puppet();
+        puppet["nodes"]["children"][0]["textures"] = json!([1, u32::MAX, 0]);
+        // The first slot can’t be empty.
+        puppet["nodes"]["children"][1]["textures"] = json!([u32::MAX, u32::MAX, u32::MAX]);
+        assert_eq!(
+            validate(puppet),
+            Err(vec![
+                ValidationError::TextureOutOfRange {
+                    node: 2,
+                    texture: 1,
+                    num_textures: 1,
+                },
+                ValidationError::TextureOutOfRange {
+                    node: 3,
+                    texture: u32::MAX as usize,
+                    num_textures: 1,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn meshes() {
+        let mut puppet = puppet();
+        puppet["nodes"]["children"][0]["mesh"]["uvs"] = json!([0., 0.]);
+        puppet["nodes"]["children"][1]["mesh"]["indices"] = json!([0, 1, 4, 5]);
+        assert_eq!(
+            validate(puppet),
+            Err(vec![
+                ValidationError::MeshLengthMismatch {
+                    node: 2,
+                    verts: 8,
+                    uvs: 2,
+                },
+                ValidationError::MeshIndexOutOfRange {
+                    node: 2,
+                    index: 1,
+                    num_vertices: 1,
+                },
+                ValidationError::MeshIndexOutOfRange {
+                    node: 3,
+                    index: 4,
+                    num_vertices: 4,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn axis_points() {
+        let mut puppet = puppet();
+        puppet["param"][0]["axis_points"] = json!([[1., 0.], []]);
+        let param = String::from("param");
+        assert_eq!(
+            validate(puppet),
+            Err(vec![
+                ValidationError::UnsortedAxisPoints {
+                    param: param.clone(),
+                    axis: 0,
+                },
+                ValidationError::EmptyAxisPoints {
+                    param: param.clone(),
+                    axis: 1,
+                },
+                // The values no longer match the shape of the axis points either.
+                ValidationError::BindingGridMismatch {
+                    param,
+                    node: 2,
+                    expected: (2, 0),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn binding_grids() {
+        let mismatch = ValidationError::BindingGridMismatch {
+            param: String::from("param"),
+            node: 2,
+            expected: (2, 1),
+        };
+        let mut value = puppet();
+        value["param"][0]["bindings"][0]["values"] = json!([[0.]]);
+        assert_eq!(validate(value), Err(vec![mismatch.clone()]));
+
+        let mut value = puppet();
+        value["param"][0]["bindings"][0]["isSet"] = json!([[true], [true, false]]);
+        assert_eq!(validate(value), Err(vec![mismatch]));
+    }
+
+    #[test]
+    fn deforms() {
+        let mut puppet = puppet();
+        let mut binding = puppet["param"][0]["bindings"][0].take();
+        binding["param_name"] = json!("deform");
+        binding["values"] = json!([vec![vec![[0.; 2]; 4]], vec![vec![[0.; 2]; 3]]]);
+        let mut on_node = binding.clone();
+        on_node["node"] = json!(1);
+        puppet["param"][0]["bindings"] = json!([binding, on_node]);
+        let param = String::from("param");
+        assert_eq!(
+            validate(puppet),
+            Err(vec![
+                ValidationError::DeformLengthMismatch {
+                    param: param.clone(),
+                    node: 2,
+                    expected: 4,
+                    found: 3,
+                },
+                ValidationError::DeformWithoutMesh { param, node: 1 },
+            ])
+        );
+    }
+}
@@ -5,12 +5,11 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use crate::glow;
-use crate::{gl, Model, TextureReceiver};
+use crate::{gl, IncrementalDecoder, Model, TextureReceiver, TextureSupport};
 use js_sys::{Array, Boolean, JsString, Object};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use crate::ParamValues;
-use std::collections::HashMap;
 
 #[wasm_bindgen]
 pub struct JsModel {
@@ -28,25 +27,142 @@ pub struct JsTextureReceiver {
     receiver: TextureReceiver,
 }
 
+/// Decodes every texture of this model to RGBA, see decode_textures_for() to keep them
+/// compressed when the context supports their format.
 #[wasm_bindgen]
-pub fn decode_textures(model: &mut JsModel) -> JsTextureReceiver 
+pub fn decode_textures(model: &JsModel) -> JsTextureReceiver 
 {
     JsTextureReceiver { receiver: model.model.decode_textures() }
 }
 
+/// Decodes the textures of this model, keeping them compressed when the context supports
+/// their format.
+#[wasm_bindgen]
+pub fn decode_textures_for(model: &JsModel, context: &JsContext) -> JsTextureReceiver 
+{
+    let support = texture_support(context);
+    JsTextureReceiver { receiver: model.model.decode_textures_for(support) }
+}
+
+/// Decodes textures a few at a time, to keep the page responsive.
+#[wasm_bindgen]
+pub struct JsTextureDecoder {
+    decoder: IncrementalDecoder,
+    receiver: Option<TextureReceiver>,
+}
+
+#[wasm_bindgen]
+impl JsTextureDecoder {
+    /// Decodes textures for about `budget_ms` milliseconds, and returns whether they are all
+    /// done.
+    pub fn step(&mut self, budget_ms: f64) -> bool {
+        let start = js_sys::Date::now();
+        self.decoder
+            .step_until(|| js_sys::Date::now() - start >= budget_ms)
+    }
+
+    pub fn decoded_bytes(&self) -> f64 {
+        self.decoder.progress().decoded_bytes as f64
+    }
+
+    pub fn total_bytes(&self) -> f64 {
+        self.decoder.progress().total_bytes as f64
+    }
+
+    /// Returns the decoded textures, to be passed to setup(), once step() returned true.
+    pub fn finish(&mut self) -> Option<JsTextureReceiver> {
+        if !self.decoder.is_done() {
+            return None;
+        }
+        self.receiver
+            .take()
+            .map(|receiver| JsTextureReceiver { receiver })
+    }
+
+    /// Returns the textures right away, to be passed to setup_progressive() while step() keeps
+    /// being called each frame.
+    pub fn take_receiver(&mut self) -> Option<JsTextureReceiver> {
+        self.receiver
+            .take()
+            .map(|receiver| JsTextureReceiver { receiver })
+    }
+}
+
+fn texture_support(context: &JsContext) -> TextureSupport {
+    TextureSupport {
+        bptc: context.has_bptc,
+        astc: context.has_astc,
+    }
+}
+
+/// Creates a decoder which has to be stepped by the caller, for instance once per frame.
+#[wasm_bindgen]
+pub fn texture_decoder(model: &JsModel, context: &JsContext) -> JsTextureDecoder {
+    let (decoder, receiver) = IncrementalDecoder::new(&model.model, texture_support(context));
+    JsTextureDecoder {
+        decoder,
+        receiver: Some(receiver),
+    }
+}
+
+fn step_on_next_frame(mut decoder: JsTextureDecoder, budget_ms: f64, resolve: js_sys::Function) {
+    let callback = Closure::once_into_js(move || {
+        if decoder.step(budget_ms) {
+            let textures = decoder.finish().unwrap();
+            resolve.call1(&JsValue::NULL, &textures.into()).unwrap();
+        } else {
+            step_on_next_frame(decoder, budget_ms, resolve);
+        }
+    });
+    web_sys::window()
+        .unwrap()
+        .request_animation_frame(callback.unchecked_ref())
+        .unwrap();
+}
+
+/// Decodes the textures of this model over as many frames as needed, spending at most about
+/// `budget_ms` milliseconds per frame, and returns a promise of what decode_textures_for()
+/// would have returned.
+#[wasm_bindgen]
+pub fn decode_textures_async(
+    model: &JsModel,
+    context: &JsContext,
+    budget_ms: f64,
+) -> js_sys::Promise {
+    let mut decoder = Some(texture_decoder(model, context));
+    js_sys::Promise::new(&mut |resolve, _reject| {
+        step_on_next_frame(decoder.take().unwrap(), budget_ms, resolve);
+    })
+}
+
 #[wasm_bindgen]
 pub struct JsGlRenderer 
 {
-    model: JsModel,
+    model: Model,
+    params: ParamValues<'static>,
     renderer: gl::GlRenderer<'static>,
 }
 
+impl JsGlRenderer {
+    fn new(model: JsModel, renderer: gl::GlRenderer<'static>) -> JsGlRenderer {
+        // Rendering only needs the nodes, so the params can move into the values.
+        let mut model = model.model;
+        let params = ParamValues::from_owned(std::mem::take(&mut model.puppet.param));
+        JsGlRenderer {
+            model,
+            params,
+            renderer,
+        }
+    }
+}
+
 #[wasm_bindgen]
 impl JsGlRenderer 
 {
+    /// Sets the params present in this object, the other ones keep their previous value, and
+    /// only the parts driven by the params which changed get animated again.
     pub fn animate(&mut self, params: js_sys::Object) 
     {
-        let mut param_map: HashMap<String, [f32; 2]> = HashMap::new();
         let keys = js_sys::Reflect::own_keys(&params).expect("Failed to get object keys");
         for key in keys.iter() {
             if let Some(key_str) = key.as_string() {
@@ -54,20 +170,20 @@ impl JsGlRenderer
                     if let Ok(js_arr) = js_value.dyn_into::<js_sys::Array>() {
                         let x = js_arr.get(0).as_f64().unwrap_or(0.0) as f32;
                         let y = js_arr.get(1).as_f64().unwrap_or(0.0) as f32;
-                        param_map.insert(key_str, [x, y]);
+                        self.params.set(&key_str, [x, y]);
                     }
                 }
             }
         }
+        self.renderer.animate(&self.params);
+    }
 
-        let mut param_values = ParamValues::new(&self.model.model.puppet.param);
-
-        // Iterate over all parameters and update them if found
-        for (key, value) in param_map.iter() 
-        {
-            param_values.set(key, *value);
-        }
-        self.renderer.animate(&param_values);
+    /// Uploads the textures decoded since the last call, and returns whether they have all
+    /// arrived.
+    pub fn poll_textures(&mut self) -> Result<bool, JsValue> {
+        self.renderer
+            .poll_textures()
+            .map_err(|err| JsValue::from_str(&err))
     }
 
     pub fn clear(&self) 
@@ -77,10 +193,76 @@ impl JsGlRenderer
 
     pub fn render(&self) 
     {
-        let num_nodes = gl::count_nodes(&self.model.model.puppet.nodes);
-        let order = gl::sort_nodes_by_zsort(num_nodes, &self.model.model.puppet.nodes);
+        let num_nodes = gl::count_nodes(&self.model.puppet.nodes);
+        let order = gl::sort_nodes_by_zsort(num_nodes, &self.model.puppet.nodes);
         self.renderer.render_nodes(&order);
     }
+
+    /// Renders into this framebuffer, or the canvas when null, within this rectangle of it,
+    /// starting from the next begin_frame(), clear() or render().
+    pub fn set_target(
+        &mut self,
+        framebuffer: Option<web_sys::WebGlFramebuffer>,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    ) {
+        self.renderer.set_target(framebuffer, [x, y, width, height]);
+    }
+
+    /// Saves the WebGL state of the page before rendering into a context it also uses, every
+    /// other call then has to happen before end_frame().
+    pub fn begin_frame(&mut self) {
+        self.renderer.begin_frame();
+    }
+
+    /// Restores the WebGL state saved by begin_frame().
+    pub fn end_frame(&mut self) {
+        self.renderer.end_frame();
+    }
+
+    /// Measures how long animate() and render() take, reported by take_stats().
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.renderer.set_profiling(profiling);
+    }
+
+    /// Returns the work done since the previous call, typically once per frame.
+    pub fn take_stats(&self) -> JsFrameStats {
+        let stats = self.renderer.take_stats();
+        JsFrameStats {
+            parts: stats.parts,
+            draw_calls: stats.draw_calls,
+            program_changes: stats.program_changes,
+            texture_changes: stats.texture_changes,
+            blend_changes: stats.blend_changes,
+            stencil_changes: stats.stencil_changes,
+            mask_recomputations: stats.mask_recomputations,
+            composite_passes: stats.composite_passes,
+            vertices_deformed: stats.vertices_deformed,
+            bytes_uploaded: stats.bytes_uploaded as f64,
+            animate_ms: stats.animate_time.as_secs_f64() * 1000.,
+            render_ms: stats.render_time.as_secs_f64() * 1000.,
+        }
+    }
+}
+
+/// The statistics of gl::FrameStats, with the times in milliseconds.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct JsFrameStats {
+    pub parts: u32,
+    pub draw_calls: u32,
+    pub program_changes: u32,
+    pub texture_changes: u32,
+    pub blend_changes: u32,
+    pub stencil_changes: u32,
+    pub mask_recomputations: u32,
+    pub composite_passes: u32,
+    pub vertices_deformed: u32,
+    pub bytes_uploaded: f64,
+    pub animate_ms: f64,
+    pub render_ms: f64,
 }
 
 #[wasm_bindgen]
@@ -102,6 +284,12 @@ pub fn has_bptc(JsContext { has_bptc, .. }: &JsContext) -> bool {
     *has_bptc
 }
 
+/// Whether the context is WebGL 2, otherwise WebGL 1.
+#[wasm_bindgen]
+pub fn is_webgl2(JsContext { gl, .. }: &JsContext) -> bool {
+    gl.is_instance_of::<web_sys::WebGl2RenderingContext>()
+}
+
 #[wasm_bindgen]
 pub fn setup_context(id: &str, scaling: Option<bool>) -> Result<JsContext, JsValue> {
     std::panic::set_hook(Box::new(console_error_panic_hook::hook)); // Setup actually helpful error messages on panic
@@ -133,10 +321,15 @@ pub fn setup_context(id: &str, scaling: Option<bool>) -> Result<JsContext, JsVal
     ]);
     let params = Array::from_iter([params]);
     let params = Object::from_entries(&params)?;
-    let gl = canvas
-        .get_context_with_context_options("webgl", &params)?
-        .ok_or(JsValue::from_str("WebGL context creation failure"))?
-        .dyn_into::<web_sys::WebGlRenderingContext>()?;
+    // WebGL 2 has every function of WebGL 1 under the same name, so it gets used through the
+    // same type, and the renderer picks its GLES 3 path for it.
+    let gl = match canvas.get_context_with_context_options("webgl2", &params)? {
+        Some(gl) => gl.unchecked_into::<web_sys::WebGlRenderingContext>(),
+        None => canvas
+            .get_context_with_context_options("webgl", &params)?
+            .ok_or(JsValue::from_str("WebGL context creation failure"))?
+            .dyn_into::<web_sys::WebGlRenderingContext>()?,
+    };
 
     let mut has_astc = false;
     let mut has_bptc = false;
@@ -179,5 +372,28 @@ pub fn setup(context: JsContext, model: JsModel, textures: JsTextureReceiver) ->
     let num_nodes = gl::count_nodes(&model.model.puppet.nodes);
     let order = gl::sort_nodes_by_zsort(num_nodes, &model.model.puppet.nodes);
     renderer.render_nodes(&order);
-    Ok(JsGlRenderer { renderer, model })
-}
\ No newline at end of file
+    Ok(JsGlRenderer::new(model, renderer))
+}
+
+/// Like setup(), but doesn’t wait for the textures, which then get drawn as they arrive as long
+/// as poll_textures() is called before rendering each frame.
+#[wasm_bindgen]
+pub fn setup_progressive(
+    context: JsContext,
+    model: JsModel,
+    textures: JsTextureReceiver,
+) -> Result<JsGlRenderer, JsValue> {
+    let JsContext {
+        gl, width, height, ..
+    } = context;
+    // This creates a memory leak!
+    let gl_static = Box::leak(Box::new(gl));
+    let renderer = gl::setup_progressive(
+        gl_static,
+        &model.model.puppet.nodes,
+        textures.receiver,
+        width,
+        height,
+    );
+    Ok(JsGlRenderer::new(model, renderer))
+}